@@ -25,7 +25,9 @@ fn main() -> Result<()> {
         },
     )?;
 
-    // Set QOS to only send us 1 message at a time.
+    // Set QOS to only send us 1 message at a time. With global = false, RabbitMQ applies
+    // the limit to each consumer, so a worker busy with a long task is not sent more work
+    // while other workers are idle (fair dispatch).
     channel.qos(0, 1, false)?;
 
     // Start a consumer.
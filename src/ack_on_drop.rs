@@ -0,0 +1,193 @@
+use crate::errors::*;
+use crate::logging::warn;
+use crate::Delivery;
+use std::ops::Deref;
+
+/// A [`Delivery`](struct.Delivery.html) that is acknowledged automatically when it is dropped.
+///
+/// Wrapping a delivery in an `AckOnDrop` (via [`new`](#method.new) or
+/// [`Delivery::ack_on_drop`](struct.Delivery.html#method.ack_on_drop)) is useful when a message
+/// should be acked once processing is finished no matter which path the processing code takes
+/// out of its scope, including an early `?` return. If processing panics, the delivery is still
+/// acked as the stack unwinds; use [`nack`](#method.nack), [`reject`](#method.reject) or
+/// [`into_inner`](#method.into_inner) to handle a delivery any other way.
+///
+/// The automatic ack is sent via the delivery's [`Acker`](struct.Acker.html), so the wrapper
+/// does not need access to the channel. Any error sending it (e.g., because the channel has
+/// since been closed) is logged and otherwise ignored; call [`ack`](#method.ack) explicitly to
+/// observe it. Deliveries received by a consumer in `no_ack` mode are never acked, since the
+/// server already considers them acknowledged.
+pub struct AckOnDrop {
+    delivery: Option<Delivery>,
+}
+
+impl AckOnDrop {
+    /// Wrap `delivery` so that it will be acked when dropped.
+    ///
+    /// Only deliveries received via a [`Consumer`](struct.Consumer.html) can be acked without
+    /// their channel; deliveries returned by
+    /// [`Channel::basic_get`](struct.Channel.html#method.basic_get) will return
+    /// [`Error::DeliveryNotFromConsumer`](enum.Error.html#variant.DeliveryNotFromConsumer).
+    pub fn new(delivery: Delivery) -> Result<AckOnDrop> {
+        if delivery.acker().is_none() {
+            return DeliveryNotFromConsumer.fail();
+        }
+        Ok(AckOnDrop {
+            delivery: Some(delivery),
+        })
+    }
+
+    /// Ack the delivery now, returning any error instead of logging it.
+    pub fn ack(mut self) -> Result<()> {
+        let delivery = self.take();
+        if delivery.no_ack() {
+            return Ok(());
+        }
+        delivery.ack_direct()
+    }
+
+    /// Nack the delivery instead of acking it. If `requeue` is true, instructs the server to
+    /// attempt to requeue the message. Does nothing if the delivery was received in `no_ack`
+    /// mode.
+    pub fn nack(mut self, requeue: bool) -> Result<()> {
+        let delivery = self.take();
+        if delivery.no_ack() {
+            return Ok(());
+        }
+        delivery.nack_direct(requeue)
+    }
+
+    /// Reject the delivery instead of acking it. If `requeue` is true, instructs the server to
+    /// attempt to requeue the message. Does nothing if the delivery was received in `no_ack`
+    /// mode.
+    pub fn reject(mut self, requeue: bool) -> Result<()> {
+        let delivery = self.take();
+        if delivery.no_ack() {
+            return Ok(());
+        }
+        delivery.reject_direct(requeue)
+    }
+
+    /// Unwrap the delivery without acking it. The caller becomes responsible for acknowledging
+    /// it.
+    pub fn into_inner(mut self) -> Delivery {
+        self.take()
+    }
+
+    fn take(&mut self) -> Delivery {
+        // Only `Drop` and the consuming methods above take the delivery, so it's always present.
+        self.delivery.take().unwrap()
+    }
+}
+
+impl Deref for AckOnDrop {
+    type Target = Delivery;
+
+    fn deref(&self) -> &Delivery {
+        self.delivery.as_ref().unwrap()
+    }
+}
+
+impl std::fmt::Debug for AckOnDrop {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("AckOnDrop").field(&self.delivery).finish()
+    }
+}
+
+impl Drop for AckOnDrop {
+    fn drop(&mut self) {
+        let delivery = match self.delivery.take() {
+            Some(delivery) => delivery,
+            None => return,
+        };
+        if delivery.no_ack() {
+            return;
+        }
+        let delivery_tag = delivery.delivery_tag();
+        if let Err(err) = delivery.ack_direct() {
+            warn!("failed to ack delivery {} on drop: {}", delivery_tag, err);
+        }
+    }
+}
+
+impl Delivery {
+    /// Wrap this delivery in an [`AckOnDrop`](struct.AckOnDrop.html) so that it is acked
+    /// automatically once it goes out of scope.
+    #[inline]
+    pub fn ack_on_drop(self) -> Result<AckOnDrop> {
+        AckOnDrop::new(self)
+    }
+}
+
+#[cfg(all(test, feature = "mock-broker"))]
+mod mock_broker_tests {
+    use super::*;
+    use crate::mock_broker::with_mock_conn;
+    use crate::{ConsumerMessage, ConsumerOptions, Publish, QueueDeclareOptions};
+    use std::time::Duration;
+
+    #[test]
+    fn ack_on_drop() {
+        with_mock_conn(|connection| {
+            let channel = connection.open_channel(None).unwrap();
+
+            let queue = channel
+                .queue_declare("ack_on_drop", QueueDeclareOptions::default())
+                .unwrap();
+            for body in &[b"a", b"b", b"c"] {
+                channel
+                    .basic_publish("", Publish::new(&body[..], queue.name()))
+                    .unwrap();
+            }
+            let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+            let next = || match consumer.receiver().recv_timeout(Duration::from_secs(5)) {
+                Ok(ConsumerMessage::Delivery(delivery)) => delivery.ack_on_drop().unwrap(),
+                other => panic!("unexpected consumer message {:?}", other),
+            };
+
+            // dropped: acked
+            assert_eq!(next().body(), b"a");
+            // explicitly rejected without requeueing
+            next().reject(false).unwrap();
+            // unwrapped and left unacked
+            let unacked = next().into_inner();
+            assert_eq!(unacked.body(), b"c");
+
+            // closing the channel requeues only the unacked delivery
+            channel.close().unwrap();
+            let channel = connection.open_channel(None).unwrap();
+            let get = channel.basic_get("ack_on_drop", true).unwrap().unwrap();
+            assert_eq!(get.delivery.body(), b"c");
+            assert!(channel.basic_get("ack_on_drop", true).unwrap().is_none());
+
+            // deliveries from basic_get have no acker
+            channel
+                .basic_publish("", Publish::new(b"d", "ack_on_drop"))
+                .unwrap();
+            let get = channel.basic_get("ack_on_drop", false).unwrap().unwrap();
+            match get.delivery.ack_on_drop() {
+                Err(Error::DeliveryNotFromConsumer) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            // no_ack deliveries are never acked; an ack for one would close the channel
+            let consumer = channel
+                .basic_consume(
+                    "ack_on_drop",
+                    ConsumerOptions {
+                        no_ack: true,
+                        ..ConsumerOptions::default()
+                    },
+                )
+                .unwrap();
+            channel
+                .basic_publish("", Publish::new(b"e", "ack_on_drop"))
+                .unwrap();
+            match consumer.receiver().recv_timeout(Duration::from_secs(5)) {
+                Ok(ConsumerMessage::Delivery(delivery)) => drop(delivery.ack_on_drop().unwrap()),
+                other => panic!("unexpected consumer message {:?}", other),
+            }
+            channel.queue_declare_passive("ack_on_drop").unwrap();
+        })
+    }
+}
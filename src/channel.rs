@@ -1,14 +1,25 @@
-use crate::io_loop::ChannelHandle;
+use crate::consumer::is_exclusive_conflict;
+use crate::errors::*;
+use crate::exchange::{
+    check_bind_exchange, check_declare_exchange, check_delete_exchange, current_exchange_type,
+    merge_properties,
+};
+use crate::io_loop::{Acker, ChannelHandle, ChannelOpener, ConsumerCanceller};
+use crate::logging::debug;
 use crate::serialize::{IntoAmqpClass, TryFromAmqpClass};
+use crate::spans;
+#[cfg(feature = "raw-frames")]
+use crate::AmqpClass;
 use crate::{
-    Confirm, Consumer, ConsumerOptions, Delivery, Exchange, ExchangeDeclareOptions, ExchangeType,
-    Get, Publish, Queue, QueueDeclareOptions, QueueDeleteOptions, Result, Return,
+    AmqpProperties, Confirm, Confirmation, Consumer, ConsumerOptions, Delivery, Exchange,
+    ExchangeDeclareOptions, ExchangeType, Get, Publish, PublishBatch, Queue, QueueDeclareOptions,
+    QueueDeleteOptions, Return, StreamingGet,
 };
 use amq_protocol::protocol::basic::AMQPMethod as AmqpBasic;
 use amq_protocol::protocol::basic::Get as AmqpGet;
 use amq_protocol::protocol::basic::Publish as AmqpPublish;
 use amq_protocol::protocol::basic::{
-    Ack, Cancel, CancelOk, Consume, Nack, Qos, QosOk, Recover, RecoverOk, Reject,
+    Ack, Cancel, CancelOk, Nack, Qos, QosOk, Recover, RecoverOk, Reject,
 };
 use amq_protocol::protocol::confirm::AMQPMethod as AmqpConfirm;
 use amq_protocol::protocol::confirm::Select as ConfirmSelect;
@@ -30,10 +41,56 @@ use amq_protocol::protocol::queue::Purge as QueuePurge;
 use amq_protocol::protocol::queue::PurgeOk as QueuePurgeOk;
 use amq_protocol::protocol::queue::Unbind as QueueUnbind;
 use amq_protocol::protocol::queue::UnbindOk as QueueUnbindOk;
+use amq_protocol::protocol::AMQPSoftError;
 use amq_protocol::types::FieldTable;
-use crossbeam_channel::Receiver;
-use std::cell::RefCell;
+use crossbeam_channel::{Receiver, Sender};
+use snafu::OptionExt;
+use std::cell::{RefCell, RefMut};
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Details of a server-initiated channel close, passed to callbacks registered with
+/// [`Channel::on_server_close`](struct.Channel.html#method.on_server_close).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerClose {
+    /// The channel that was closed.
+    pub channel_id: u16,
+
+    /// The AMQP reply code (e.g., 404 for `NOT_FOUND`).
+    pub reply_code: u16,
+
+    /// The server's description of why it closed the channel.
+    pub reply_text: String,
+
+    /// The class of the method that caused the close, or 0 if not caused by a method.
+    pub class_id: u16,
+
+    /// The method that caused the close, or 0 if not caused by a method.
+    pub method_id: u16,
+}
+
+pub(crate) type ServerCloseCallback = Arc<dyn Fn(ServerClose) + Send + Sync>;
+
+/// Settings applied to a channel in one step by [`Channel::configure`](struct.Channel.html#method.configure).
+///
+/// The [`default`](#impl-Default) implementation sets all numeric fields to 0 and all boolean
+/// fields to false, which leaves the channel's settings unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelConfig {
+    /// Prefetch limit in bytes; see [`Channel::qos`](struct.Channel.html#method.qos).
+    pub prefetch_size: u32,
+
+    /// Prefetch limit in messages; see [`Channel::qos`](struct.Channel.html#method.qos).
+    pub prefetch_count: u16,
+
+    /// Scope of the prefetch limits; see [`Channel::qos`](struct.Channel.html#method.qos).
+    pub global: bool,
+
+    /// If true, enable publisher confirms; see
+    /// [`Channel::enable_publisher_confirms`](struct.Channel.html#method.enable_publisher_confirms).
+    pub publisher_confirms: bool,
+}
 
 /// Handle for an AMQP channel.
 ///
@@ -75,10 +132,73 @@ use std::fmt::Debug;
 pub struct Channel {
     inner: RefCell<ChannelHandle>,
     closed: bool,
+    // Present if this channel reopens itself after a recoverable server close.
+    reopen: Option<RefCell<ReopenState>>,
+    default_properties: RefCell<Option<AmqpProperties>>,
+}
+
+// Everything needed to reopen a channel and restore the settings applied to it.
+struct ReopenState {
+    opener: ChannelOpener,
+    qos: Option<Qos>,
+    publisher_confirms: bool,
+    return_handler: Option<Sender<Return>>,
+    pub_confirm_handler: Option<Sender<Confirm>>,
+    server_close_handler: Option<ServerCloseCallback>,
+    // Number of times the channel has been reopened.
+    reopens: u64,
+}
+
+impl ReopenState {
+    fn reopen(&self) -> Result<ChannelHandle> {
+        let mut handle = self.opener.open()?;
+        match self.restore(&mut handle) {
+            Ok(()) => Ok(handle),
+            Err(err) => {
+                let _ = handle.close();
+                Err(err)
+            }
+        }
+    }
+
+    fn restore(&self, handle: &mut ChannelHandle) -> Result<()> {
+        if let Some(tx) = &self.return_handler {
+            handle.set_return_handler(Some(tx.clone()))?;
+        }
+        if let Some(tx) = &self.pub_confirm_handler {
+            handle.set_pub_confirm_handler(Some(tx.clone()))?;
+        }
+        if let Some(handler) = &self.server_close_handler {
+            handle.set_server_close_handler(Some(Arc::clone(handler)))?;
+        }
+        if self.publisher_confirms {
+            handle.expect_pub_confirms()?;
+            handle
+                .call::<_, ConfirmSelectOk>(AmqpConfirm::Select(ConfirmSelect { nowait: false }))?;
+        }
+        if let Some(qos) = &self.qos {
+            handle.call::<_, QosOk>(AmqpBasic::Qos(qos.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+// Server close codes that indicate a failed request rather than a problem with the channel
+// or connection itself.
+fn is_recoverable_close(code: u16) -> bool {
+    code == AMQPSoftError::NOTFOUND.get_id()
+        || code == AMQPSoftError::ACCESSREFUSED.get_id()
+        || code == AMQPSoftError::RESOURCELOCKED.get_id()
+        || code == AMQPSoftError::PRECONDITIONFAILED.get_id()
 }
 
 impl Drop for Channel {
     fn drop(&mut self) {
+        // If the channel or connection is already gone there is nothing to close, and no
+        // close-ok will ever arrive.
+        if !self.closed && !self.inner.get_mut().is_open() {
+            self.closed = true;
+        }
         let _ = self.close_impl();
     }
 }
@@ -88,6 +208,25 @@ impl Channel {
         Channel {
             inner: RefCell::new(handle),
             closed: false,
+            reopen: None,
+            default_properties: RefCell::new(None),
+        }
+    }
+
+    pub(crate) fn with_reopen(handle: ChannelHandle, opener: ChannelOpener) -> Channel {
+        Channel {
+            inner: RefCell::new(handle),
+            closed: false,
+            reopen: Some(RefCell::new(ReopenState {
+                opener,
+                qos: None,
+                publisher_confirms: false,
+                return_handler: None,
+                pub_confirm_handler: None,
+                server_close_handler: None,
+                reopens: 0,
+            })),
+            default_properties: RefCell::new(None),
         }
     }
 
@@ -97,6 +236,18 @@ impl Channel {
         self.close_impl()
     }
 
+    /// Close this channel without waiting for the server to confirm it.
+    ///
+    /// Unlike [`close`](#method.close) (and dropping a channel, which calls `close`), this
+    /// returns as soon as the close request has been handed to the I/O thread, so it never blocks
+    /// on the server. This is useful when shutting down with many channels open, or when the
+    /// server may be unresponsive. The I/O thread still processes the server's confirmation when
+    /// it arrives; until then, the channel's ID is not available for reuse.
+    pub fn forget(mut self) -> Result<()> {
+        self.closed = true;
+        self.inner.get_mut().close_nowait()
+    }
+
     fn close_impl(&mut self) -> Result<()> {
         // this can only happen if we're called from drop (since close() takes self),
         // in which case the return value doesn't matter.
@@ -117,12 +268,86 @@ impl Channel {
         self.inner.borrow().channel_id()
     }
 
+    /// Returns true if this channel is still usable; i.e., it has not been closed by the server and
+    /// the connection's I/O thread is still running.
+    ///
+    /// This does not return an error even if the channel has been closed; the reason for the
+    /// closure will still be returned by the next operation on this channel. Use
+    /// [`server_close_reason`](#method.server_close_reason) to inspect why the server closed the
+    /// channel.
+    pub fn is_open(&self) -> bool {
+        !self.closed && self.inner.borrow_mut().is_open()
+    }
+
+    /// If the server has closed this channel (or the connection it belongs to), returns the reply
+    /// code and text the server sent. Returns `None` if the channel is still open or was closed
+    /// for some other reason (e.g., the I/O thread exited due to a socket error).
+    pub fn server_close_reason(&self) -> Option<(u16, String)> {
+        self.inner.borrow_mut().server_close_reason()
+    }
+
+    /// Returns false while the server has paused content on this channel via `channel.flow`.
+    ///
+    /// amiquip acknowledges flow control automatically. While the channel is paused, the I/O
+    /// thread holds onto messages published on it (in memory) and sends them once the server
+    /// resumes the channel; everything else (acks, consumer cancels, closing the channel, etc.)
+    /// is sent as usual. [`flush`](#method.flush) does not wait for held publishes, and they are
+    /// discarded if the channel is closed before it is resumed.
+    /// [`try_basic_publish`](#method.try_basic_publish) fails with
+    /// [`Error::OutboundFull`](enum.Error.html#variant.OutboundFull) while the channel is paused,
+    /// so publishers that would rather shed load than buffer it can use it or check this before
+    /// publishing.
+    pub fn is_flow_active(&self) -> bool {
+        self.inner.borrow().is_flow_active()
+    }
+
+    // Count a wait for a reply from the server that timed out toward the connection's
+    // max_consecutive_timeouts.
+    pub(crate) fn record_timeout(&self) {
+        self.inner.borrow().record_timeout()
+    }
+
+    // True if this channel reopens itself after a recoverable server close.
+    #[inline]
+    pub(crate) fn is_auto_reopen(&self) -> bool {
+        self.reopen.is_some()
+    }
+
+    // Borrow the handle for this channel, first reopening the channel if it reopens itself and
+    // the server's close has already been reported to a caller.
+    fn handle(&self) -> Result<RefMut<ChannelHandle>> {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(reopen) = &self.reopen {
+            if inner
+                .reported_close_code()
+                .map_or(false, is_recoverable_close)
+            {
+                let old_id = inner.channel_id();
+                let mut reopen = reopen.borrow_mut();
+                *inner = reopen.reopen()?;
+                reopen.reopens += 1;
+                debug!(
+                    "reopened channel {} as channel {}",
+                    old_id,
+                    inner.channel_id()
+                );
+            }
+        }
+        Ok(inner)
+    }
+
+    fn reopens(&self) -> u64 {
+        self.reopen
+            .as_ref()
+            .map_or(0, |reopen| reopen.borrow().reopens)
+    }
+
     fn call<M: IntoAmqpClass + Debug, T: TryFromAmqpClass>(&self, method: M) -> Result<T> {
-        self.inner.borrow_mut().call(method)
+        self.handle()?.call(method)
     }
 
     fn call_nowait<M: IntoAmqpClass + Debug>(&self, method: M) -> Result<()> {
-        self.inner.borrow_mut().call_nowait(method)
+        self.handle()?.call_nowait(method)
     }
 
     /// Specify the prefetching window.
@@ -138,17 +363,49 @@ impl Channel {
     ///
     /// According to the AMQP spec, setting `global` to true means to apply these prefetch settings
     /// to all channels in the entire connection, and `global` false means the settings apply only
-    /// to this channel. RabbitMQ does not interpret `global` the same way; for it, `global: true`
-    /// means the settings apply to all consumers on this channel, and `global: false` means the
-    /// settings apply only to consumers created on this channel after this call to `qos`, not
-    /// affecting previously-created consumers.
+    /// to this channel. RabbitMQ does not interpret `global` the same way (servers that follow
+    /// RabbitMQ here report the `per_consumer_qos`
+    /// [capability](struct.ServerCapabilities.html#method.per_consumer_qos)):
+    ///
+    /// * `global: false` sets a limit for _each_ consumer created on this channel after this call
+    /// to `qos`; previously-created consumers are not affected. Every consumer gets its own window
+    /// of `prefetch_count` unacknowledged messages, so with a count of 1 a busy consumer is not
+    /// sent another message while an idle one is available. This is the usual way to get fair
+    /// dispatch among workers.
+    /// * `global: true` sets a single limit shared by all consumers on this channel, so
+    /// unacknowledged messages held by one consumer count against the others.
+    ///
+    /// The two can be combined by calling `qos` once with each value of `global`, in which case
+    /// RabbitMQ enforces both limits.
     pub fn qos(&self, prefetch_size: u32, prefetch_count: u16, global: bool) -> Result<()> {
-        self.call::<_, QosOk>(AmqpBasic::Qos(Qos {
+        let qos = Qos {
             prefetch_size,
             prefetch_count,
             global,
-        }))
-        .map(|_qos_ok| ())
+        };
+        self.call::<_, QosOk>(AmqpBasic::Qos(qos.clone()))?;
+        if let Some(reopen) = &self.reopen {
+            reopen.borrow_mut().qos = Some(qos);
+        }
+        Ok(())
+    }
+
+    /// Synchronously apply all the settings in `config` to this channel, returning once the
+    /// server has confirmed each of them. Call this before starting any consumers or publishing
+    /// so they never run with the channel's default settings.
+    ///
+    /// Prefetch limits are set with [`qos`](#method.qos) (skipped if both `prefetch_size` and
+    /// `prefetch_count` are 0), then publisher confirms are enabled with
+    /// [`enable_publisher_confirms`](#method.enable_publisher_confirms) if requested. If the
+    /// server rejects a setting, the error is returned and later settings are not applied.
+    pub fn configure(&self, config: ChannelConfig) -> Result<()> {
+        if config.prefetch_size != 0 || config.prefetch_count != 0 {
+            self.qos(config.prefetch_size, config.prefetch_count, config.global)?;
+        }
+        if config.publisher_confirms {
+            self.enable_publisher_confirms()?;
+        }
+        Ok(())
     }
 
     /// Ask the server to redeliver all unacknowledged messages on this channel. If `requeue` is
@@ -159,25 +416,413 @@ impl Channel {
             .map(|_recover_ok| ())
     }
 
+    /// Block until all frames the I/O thread has queued to send have been written to the socket.
+    ///
+    /// Most methods that do not wait for a server response (e.g.,
+    /// [`basic_publish`](#method.basic_publish)) return as soon as their frames have been handed
+    /// to the I/O thread. `flush` can be used to wait until those frames have actually been
+    /// written. Note that because all channels on a connection share a single socket, this also
+    /// waits for frames queued by other channels. Being written to the socket does not imply the
+    /// server has received or processed the frames; use [publisher
+    /// confirms](#method.enable_publisher_confirms) if you need that guarantee.
+    pub fn flush(&self) -> Result<()> {
+        self.handle()?.flush()
+    }
+
+    /// Run `f`, holding back the frames of any messages it publishes (and any other asynchronous
+    /// methods it calls, such as acks) on this channel until it returns, then handing them all to
+    /// the I/O thread at once. This lets the I/O thread write a batch of small messages with far
+    /// fewer socket writes; see the `publish_batch` example.
+    ///
+    /// Synchronous methods called within `f` (e.g.,
+    /// [`queue_declare`](#method.queue_declare)) first send everything held back so far. While
+    /// `f` runs, [`try_basic_publish`](#method.try_basic_publish) only returns
+    /// [`Error::OutboundFull`](enum.Error.html#variant.OutboundFull) if the server has paused the
+    /// channel via `channel.flow`, and held-back messages are buffered in memory without bound,
+    /// so keep batches to a reasonable size. Calls to
+    /// `batch` may be nested; frames are released when the outermost call returns, even if `f`
+    /// returns an error.
+    ///
+    /// If this channel was opened with
+    /// [`Connection::open_auto_reopen_channel`](struct.Connection.html#method.open_auto_reopen_channel)
+    /// and reopens itself while `f` runs, any frames held back before the server closed it are
+    /// discarded (the server would have ignored them anyway), and anything `f` does after the
+    /// reopen is sent without being batched. In that case `batch` returns
+    /// [`Error::BatchInterrupted`](enum.Error.html#variant.BatchInterrupted) (unless `f` itself
+    /// returns an error) so callers know some of the batch may not have been sent.
+    pub fn batch<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Channel) -> Result<T>,
+    {
+        let corked = self.handle()?.cork();
+        let reopens = self.reopens();
+        let result = f(self);
+        if corked {
+            let uncorked = self.inner.borrow_mut().uncork();
+            let value = result?;
+            uncorked?;
+            if self.reopens() != reopens {
+                return BatchInterrupted {
+                    channel_id: self.channel_id(),
+                }
+                .fail();
+            }
+            return Ok(value);
+        }
+        result
+    }
+
     /// Publish a message to `exchange`. If the exchange does not exist, the server will close this
     /// channel. Consider using one of the [`exchange_declare`](#method.exchange_declare) methods
     /// and then [`Exchange::publish`](struct.Exchange.html#method.publish) to avoid this.
+    ///
+    /// The body may be any size. Bodies larger than the connection's negotiated `frame_max` are
+    /// split across as many content body frames as needed, so the server never receives an
+    /// oversized frame.
     pub fn basic_publish<S: Into<String>>(&self, exchange: S, publish: Publish) -> Result<()> {
-        let mut inner = self.inner.borrow_mut();
-        inner.call_nowait(AmqpBasic::Publish(AmqpPublish {
+        self.publish_impl(exchange.into(), publish, false)
+    }
+
+    // Shared by basic_publish and try_basic_publish. If `try_send` is true, fail with
+    // OutboundFull instead of blocking when the I/O thread is not keeping up.
+    fn publish_impl(&self, exchange: String, publish: Publish, try_send: bool) -> Result<()> {
+        let mut inner = self.handle()?;
+        let _span = spans::publish(
+            inner.channel_id(),
+            &exchange,
+            &publish.routing_key,
+            inner.next_publish_tag(),
+        );
+        let properties = self.publish_properties(publish.properties);
+        let method = AmqpBasic::Publish(AmqpPublish {
             ticket: 0,
-            exchange: exchange.into(),
+            exchange,
             routing_key: publish.routing_key,
             mandatory: publish.mandatory,
             immediate: publish.immediate,
-        }))?;
-        inner.send_content(
-            publish.body,
-            AmqpPublish::get_class_id(),
-            &publish.properties,
+        });
+        if try_send {
+            inner.try_send_method_with_content(
+                method,
+                publish.body,
+                AmqpPublish::get_class_id(),
+                &properties,
+            )
+        } else {
+            inner.call_nowait(method)?;
+            inner.send_content(publish.body, AmqpPublish::get_class_id(), &properties)
+        }
+    }
+
+    /// Set properties to apply to every message published on this channel, such as `app_id` or
+    /// `content_type`. Each property set in `properties` is used for any published message that
+    /// does not set it itself; properties set on the message always win. Headers are merged
+    /// entry by entry, so a message's headers are added to the default headers, replacing any
+    /// default header with the same name.
+    ///
+    /// This applies to every publishing method on this channel (including
+    /// [`Exchange::publish`](struct.Exchange.html#method.publish)). Calling it again replaces the
+    /// previous defaults; pass `AmqpProperties::default()` to stop applying defaults.
+    pub fn set_default_properties(&self, properties: AmqpProperties) {
+        *self.default_properties.borrow_mut() = Some(properties);
+    }
+
+    fn publish_properties(&self, properties: AmqpProperties) -> AmqpProperties {
+        match &*self.default_properties.borrow() {
+            Some(defaults) => merge_properties(defaults, properties),
+            None => properties,
+        }
+    }
+
+    /// Publish a message to `exchange` and return its delivery tag, which can be passed to
+    /// [`wait_for_confirm`](#method.wait_for_confirm) to block until the server acks or nacks this
+    /// particular message. [Publisher confirms](#method.enable_publisher_confirms) must already be
+    /// enabled on this channel; if they are not, this returns
+    /// [`Error::PublisherConfirmsNotEnabled`](enum.Error.html#variant.PublisherConfirmsNotEnabled)
+    /// without publishing.
+    ///
+    /// The outcome of each message published this way is kept until it is waited on, so every
+    /// returned tag should eventually be passed to `wait_for_confirm`. Confirmations are still
+    /// also sent to any listener registered with
+    /// [`listen_for_publisher_confirms`](#method.listen_for_publisher_confirms).
+    pub fn basic_publish_tracked<S: Into<String>>(
+        &self,
+        exchange: S,
+        publish: Publish,
+    ) -> Result<u64> {
+        let delivery_tag = self
+            .handle()?
+            .track_next_publish()
+            .context(PublisherConfirmsNotEnabled)?;
+        match self.basic_publish(exchange, publish) {
+            Ok(()) => Ok(delivery_tag),
+            Err(err) => {
+                self.inner.borrow().untrack_confirm(delivery_tag);
+                Err(err)
+            }
+        }
+    }
+
+    /// Publish a message to `exchange` and arrange for `on_confirm` to be called once the server
+    /// acks or nacks it, returning the message's delivery tag. [Publisher
+    /// confirms](#method.enable_publisher_confirms) must already be enabled on this channel; if
+    /// they are not, this returns
+    /// [`Error::PublisherConfirmsNotEnabled`](enum.Error.html#variant.PublisherConfirmsNotEnabled)
+    /// without publishing. If publishing fails, `on_confirm` is dropped without being called.
+    ///
+    /// Callbacks registered on a channel are called in delivery tag order, even if the server
+    /// confirms messages out of order. If the channel or connection closes before a message is
+    /// confirmed, its callback is called with
+    /// [`Error::EventLoopDropped`](enum.Error.html#variant.EventLoopDropped).
+    ///
+    /// `on_confirm` runs on the connection's I/O thread, which cannot service the connection
+    /// until it returns. It should do as little as possible (e.g., send the outcome on a
+    /// channel), and must not make synchronous calls on any channel of this connection.
+    pub fn basic_publish_with_callback<S, F>(
+        &self,
+        exchange: S,
+        publish: Publish,
+        on_confirm: F,
+    ) -> Result<u64>
+    where
+        S: Into<String>,
+        F: FnOnce(Result<Confirmation>) + Send + 'static,
+    {
+        let delivery_tag = {
+            let inner = self.handle()?;
+            let delivery_tag = inner
+                .next_publish_tag()
+                .context(PublisherConfirmsNotEnabled)?;
+            inner.add_confirm_callback(delivery_tag, on_confirm)?;
+            delivery_tag
+        };
+        match self.basic_publish(exchange, publish) {
+            Ok(()) => Ok(delivery_tag),
+            Err(err) => {
+                self.inner.borrow().remove_confirm_callback(delivery_tag);
+                Err(err)
+            }
+        }
+    }
+
+    /// Block until the server acks or nacks the message published by
+    /// [`basic_publish_tracked`](#method.basic_publish_tracked) that was assigned `delivery_tag`,
+    /// or until `timeout` elapses. A confirmation with `multiple` set that covers `delivery_tag`
+    /// resolves it just like one for `delivery_tag` alone.
+    ///
+    /// On timeout this returns
+    /// [`Error::ConfirmTimeout`](enum.Error.html#variant.ConfirmTimeout) and the tag remains
+    /// tracked, so it can be waited on again. Once a confirmation has been returned, waiting on the
+    /// same tag again returns
+    /// [`Error::UntrackedDeliveryTag`](enum.Error.html#variant.UntrackedDeliveryTag). Tags do not
+    /// survive the channel being reopened. If publisher confirms are not enabled on this channel,
+    /// this returns
+    /// [`Error::PublisherConfirmsNotEnabled`](enum.Error.html#variant.PublisherConfirmsNotEnabled)
+    /// immediately.
+    pub fn wait_for_confirm(&self, delivery_tag: u64, timeout: Duration) -> Result<Confirmation> {
+        let inner = self.inner.borrow();
+        inner
+            .next_publish_tag()
+            .context(PublisherConfirmsNotEnabled)?;
+        inner.wait_for_confirm(delivery_tag, timeout)
+    }
+
+    /// Publish a message to `exchange` and block until the server has both routed it to at least
+    /// one queue and confirmed it, or until `timeout` elapses. This combines a `mandatory` publish
+    /// with [publisher confirms](#method.enable_publisher_confirms), which must already be enabled
+    /// on this channel; if they are not, this returns
+    /// [`Error::PublisherConfirmsNotEnabled`](enum.Error.html#variant.PublisherConfirmsNotEnabled)
+    /// without publishing.
+    ///
+    /// `publish` is always sent with `mandatory` set. Returns `Ok(())` if the server acked the
+    /// message without returning it,
+    /// [`Error::Unroutable`](enum.Error.html#variant.Unroutable) if it was returned because no
+    /// queue would accept it, [`Error::Nacked`](enum.Error.html#variant.Nacked) if the server
+    /// nacked it, or [`Error::ConfirmTimeout`](enum.Error.html#variant.ConfirmTimeout) (after
+    /// which the outcome can no longer be observed) if `timeout` elapses first. A returned
+    /// message is still also sent to any listener registered with
+    /// [`listen_for_returns`](#method.listen_for_returns).
+    ///
+    /// `basic.return` does not identify which publish it belongs to. The server always returns a
+    /// message before acking it, so a return is attributed to the oldest unconfirmed message
+    /// published this way on this channel with the same exchange and routing key. Other
+    /// `mandatory` publishes on this channel with the same exchange and routing key that are in
+    /// flight at the same time can therefore cause a return to be misattributed.
+    pub fn publish_confirmed_routable<S: Into<String>>(
+        &self,
+        exchange: S,
+        publish: Publish,
+        timeout: Duration,
+    ) -> Result<()> {
+        let exchange = exchange.into();
+        let delivery_tag = self
+            .handle()?
+            .track_next_mandatory_publish(exchange.clone(), publish.routing_key.clone())
+            .context(PublisherConfirmsNotEnabled)?;
+        let publish = Publish {
+            mandatory: true,
+            ..publish
+        };
+        let result = self
+            .basic_publish(exchange, publish)
+            .and_then(|()| self.wait_for_confirm(delivery_tag, timeout));
+        let inner = self.inner.borrow();
+        let returned = inner.take_returned(delivery_tag);
+        let confirmation = match result {
+            Ok(confirmation) => confirmation,
+            Err(err) => {
+                inner.untrack_confirm(delivery_tag);
+                return Err(err);
+            }
+        };
+        match (confirmation, returned) {
+            (Confirmation::Nack, _) => Nacked { delivery_tag }.fail(),
+            (Confirmation::Ack, Some((reply_code, reply_text))) => Unroutable {
+                reply_code,
+                reply_text,
+            }
+            .fail(),
+            (Confirmation::Ack, None) => Ok(()),
+        }
+    }
+
+    /// Publish every message in `messages` to `exchange`, returning a
+    /// [`PublishBatch`](struct.PublishBatch.html) that yields the server's confirmation of each
+    /// message as it arrives. All messages are sent (via [`batch`](#method.batch)) before any
+    /// confirmations are awaited, so many messages can be in flight at once instead of waiting
+    /// for a round trip per message.
+    ///
+    /// This enables [publisher confirms](#method.enable_publisher_confirms) if they are not
+    /// already enabled, and registers a new confirm listener, so any receiver previously returned
+    /// by [`listen_for_publisher_confirms`](#method.listen_for_publisher_confirms) will stop
+    /// receiving confirmations. Publishing on this channel from elsewhere while the batch is
+    /// outstanding does not affect the batch's results.
+    pub fn publish_batch<'a, S, I>(&self, exchange: S, messages: I) -> Result<PublishBatch>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Publish<'a>>,
+    {
+        let exchange = exchange.into();
+        let confirms = self.listen_for_publisher_confirms()?;
+        if self.handle()?.next_publish_tag().is_none() {
+            self.enable_publisher_confirms()?;
+        }
+        // enable_publisher_confirms guarantees we're tracking tags now.
+        let first_tag = self.handle()?.next_publish_tag().unwrap_or(1);
+        let mut len = 0;
+        self.batch(|chan| {
+            for publish in messages {
+                chan.basic_publish(exchange.clone(), publish)?;
+                len += 1;
+            }
+            Ok(())
+        })?;
+        Ok(PublishBatch::new(confirms, first_tag, len))
+    }
+
+    /// Publish a message directly to the queue named `queue` via the default exchange, using
+    /// `queue` as the routing key.
+    ///
+    /// The message is published as `mandatory`, so if no queue named `queue` exists it will be
+    /// returned to the listener registered via [`listen_for_returns`](#method.listen_for_returns)
+    /// instead of being silently dropped. Returns
+    /// [`Error::QueueNameRequired`](enum.Error.html#variant.QueueNameRequired) without publishing
+    /// anything if `queue` is empty.
+    pub fn publish_to_queue<S: Into<String>>(
+        &self,
+        queue: S,
+        body: &[u8],
+        properties: AmqpProperties,
+    ) -> Result<()> {
+        let queue = queue.into();
+        if queue.is_empty() {
+            return QueueNameRequired.fail();
+        }
+        self.basic_publish(
+            "",
+            Publish {
+                mandatory: true,
+                ..Publish::with_properties(body, queue, properties)
+            },
         )
     }
 
+    /// Publish a message to `exchange` without blocking.
+    ///
+    /// This is identical to [`basic_publish`](#method.basic_publish), except that if the I/O
+    /// thread is not currently accepting messages from this channel (because the number of
+    /// pending messages has reached the
+    /// [`mem_channel_bound`](struct.ConnectionTuning.html#structfield.mem_channel_bound), which is
+    /// 16 by default), this method returns
+    /// [`Error::OutboundFull`](enum.Error.html#variant.OutboundFull) instead of blocking. In that
+    /// case the message has not been sent, and the channel remains usable. This allows publishers
+    /// to shed load deterministically when the server cannot keep up; see the discussion on
+    /// [connection tuning](struct.Connection.html#tuning) for when the I/O thread stops accepting
+    /// messages. It also fails with `OutboundFull` while the server has paused this channel via
+    /// `channel.flow` (see [`is_flow_active`](#method.is_flow_active)). Note that if
+    /// `mem_channel_bound` is 0, this method will always fail.
+    pub fn try_basic_publish<S: Into<String>>(&self, exchange: S, publish: Publish) -> Result<()> {
+        self.publish_impl(exchange.into(), publish, true)
+    }
+
+    /// Send `method` on this channel exactly as given, without waiting for or interpreting any
+    /// reply. Requires the `raw-frames` feature.
+    ///
+    /// This bypasses all of the checks and bookkeeping done by the other methods on `Channel`,
+    /// and is intended only for protocol conformance testing (e.g., checking that a server
+    /// closes the channel or connection when sent a method it does not expect). The server's
+    /// response is handled by the I/O thread as usual: replies amiquip does not expect generally
+    /// cause the I/O thread to fail with
+    /// [`Error::FrameUnexpected`](enum.Error.html#variant.FrameUnexpected), and a server close
+    /// is reported by the next operation on this channel or connection.
+    #[cfg(feature = "raw-frames")]
+    pub fn send_raw_method(&self, method: AmqpClass) -> Result<()> {
+        self.handle()?.send_raw_method(method)
+    }
+
+    /// Send a content header frame on this channel announcing a body of `body_len` bytes,
+    /// without sending any method or body frames. Requires the `raw-frames` feature; see
+    /// [`send_raw_method`](#method.send_raw_method).
+    #[cfg(feature = "raw-frames")]
+    pub fn send_raw_content_header(
+        &self,
+        class_id: u16,
+        body_len: usize,
+        properties: &AmqpProperties,
+    ) -> Result<()> {
+        self.handle()?
+            .send_raw_content_header(class_id, body_len, properties)
+    }
+
+    /// Send `body` as a single content body frame on this channel, regardless of its size or of
+    /// whether a content header preceded it. Requires the `raw-frames` feature; see
+    /// [`send_raw_method`](#method.send_raw_method).
+    #[cfg(feature = "raw-frames")]
+    pub fn send_raw_content_body(&self, body: &[u8]) -> Result<()> {
+        self.handle()?.send_raw_content_body(body)
+    }
+
+    /// Returns the number of messages published on this channel that the server has not yet
+    /// acked or nacked. This is always 0 unless
+    /// [publisher confirms](#method.enable_publisher_confirms) are enabled; use
+    /// [`publisher_confirms_enabled`](#method.publisher_confirms_enabled) to tell the two cases
+    /// apart.
+    ///
+    /// The count includes messages the I/O thread has not yet written to the socket, so it can be
+    /// used to bound the number of in-flight publishes; e.g., by pausing to read from
+    /// [`listen_for_publisher_confirms`](#method.listen_for_publisher_confirms) whenever it
+    /// exceeds a limit.
+    pub fn pending_confirms(&self) -> usize {
+        self.inner.borrow().pending_confirms() as usize
+    }
+
+    /// Returns true if [publisher confirms](#method.enable_publisher_confirms) have been enabled
+    /// on this channel. Confirms cannot be disabled once enabled, so once this returns true it
+    /// will continue to do so.
+    pub fn publisher_confirms_enabled(&self) -> bool {
+        self.inner.borrow().next_publish_tag().is_some()
+    }
+
     /// Open a crossbeam channel to receive publisher confirmations from the server.
     ///
     /// You should call this method before either calling
@@ -197,7 +842,10 @@ impl Channel {
     /// been dropped, it will discard the confirmation
     pub fn listen_for_publisher_confirms(&self) -> Result<Receiver<Confirm>> {
         let (tx, rx) = crossbeam_channel::unbounded();
-        self.inner.borrow_mut().set_pub_confirm_handler(Some(tx))?;
+        self.handle()?.set_pub_confirm_handler(Some(tx.clone()))?;
+        if let Some(reopen) = &self.reopen {
+            reopen.borrow_mut().pub_confirm_handler = Some(tx);
+        }
         Ok(rx)
     }
 
@@ -205,18 +853,28 @@ impl Channel {
     /// channel. Confirmations will be delivered to the channel registered via
     /// [`listen_for_publisher_confirms`](#method.listen_for_publisher_confirms).
     pub fn enable_publisher_confirms(&self) -> Result<()> {
-        let mut inner = self.inner.borrow_mut();
-        inner
-            .call::<_, ConfirmSelectOk>(AmqpConfirm::Select(ConfirmSelect { nowait: false }))
-            .map(|_select_ok| ())
+        let mut inner = self.handle()?;
+        inner.expect_pub_confirms()?;
+        inner.call::<_, ConfirmSelectOk>(AmqpConfirm::Select(ConfirmSelect { nowait: false }))?;
+        self.record_publisher_confirms();
+        Ok(())
     }
 
     /// Asynchronously enable [publisher confirms](https://www.rabbitmq.com/confirms.html) on this
     /// channel. Confirmations will be delivered to the channel registered via
     /// [`listen_for_publisher_confirms`](#method.listen_for_publisher_confirms).
     pub fn enable_publisher_confirms_nowait(&self) -> Result<()> {
-        let mut inner = self.inner.borrow_mut();
-        inner.call_nowait(AmqpConfirm::Select(ConfirmSelect { nowait: true }))
+        let mut inner = self.handle()?;
+        inner.expect_pub_confirms()?;
+        inner.call_nowait(AmqpConfirm::Select(ConfirmSelect { nowait: true }))?;
+        self.record_publisher_confirms();
+        Ok(())
+    }
+
+    fn record_publisher_confirms(&self) {
+        if let Some(reopen) = &self.reopen {
+            reopen.borrow_mut().publisher_confirms = true;
+        }
     }
 
     /// Open a crossbeam channel to receive returned messages from the server (i.e., messages
@@ -231,22 +889,56 @@ impl Channel {
     /// has been dropped, it will discard the message.
     pub fn listen_for_returns(&self) -> Result<Receiver<Return>> {
         let (tx, rx) = crossbeam_channel::unbounded();
-        self.inner.borrow_mut().set_return_handler(Some(tx))?;
+        self.handle()?.set_return_handler(Some(tx.clone()))?;
+        if let Some(reopen) = &self.reopen {
+            reopen.borrow_mut().return_handler = Some(tx);
+        }
         Ok(rx)
     }
 
+    /// Register a callback to run whenever the server closes this channel (e.g., because a
+    /// declare failed or a delivery tag was acked twice), so closes can be logged or alerted on in
+    /// one place instead of at every call site. Operations on the channel still return
+    /// [`Error::ServerClosedChannel`](enum.Error.html#variant.ServerClosedChannel) as usual.
+    ///
+    /// There can be only one callback per channel; calling this again replaces the previous one.
+    /// On a channel opened with
+    /// [`Connection::open_auto_reopen_channel`](struct.Connection.html#method.open_auto_reopen_channel),
+    /// the callback stays registered across reopens. It is not called when the client closes the
+    /// channel or when the whole connection is closed.
+    ///
+    /// `callback` runs on the connection's I/O thread, which cannot service the connection until
+    /// it returns. It should be quick and must not block; in particular, it must not make
+    /// synchronous calls on any channel of this connection.
+    pub fn on_server_close<F>(&self, callback: F) -> Result<()>
+    where
+        F: Fn(ServerClose) + Send + Sync + 'static,
+    {
+        let callback: ServerCloseCallback = Arc::new(callback);
+        self.handle()?
+            .set_server_close_handler(Some(Arc::clone(&callback)))?;
+        if let Some(reopen) = &self.reopen {
+            reopen.borrow_mut().server_close_handler = Some(callback);
+        }
+        Ok(())
+    }
+
     /// Synchronously declare a queue named `queue` with the given options.
     ///
     /// If `queue` is `""` (the empty string), the server will assign an automatically generated
     /// queue name; use [`Queue::name`](struct.Queue.html#method.name) to get access to that name.
     ///
     /// If the server cannot declare the queue (e.g., if the queue already exists with options that
-    /// conflict with `options`), it will close this channel.
+    /// conflict with `options`), it will close this channel. Options that the requested
+    /// [`QueueType`](enum.QueueType.html) cannot support are rejected with
+    /// [`Error::IncompatibleQueueOptions`](enum.Error.html#variant.IncompatibleQueueOptions)
+    /// without contacting the server.
     pub fn queue_declare<S: Into<String>>(
         &self,
         queue: S,
         options: QueueDeclareOptions,
     ) -> Result<Queue> {
+        options.check_compatible()?;
         let declare = AmqpQueue::Declare(options.into_declare(queue.into(), false, false));
         let ok = self.call::<_, QueueDeclareOk>(declare)?;
         Ok(Queue::new(
@@ -260,7 +952,9 @@ impl Channel {
     /// Asynchronously declare a queue named `queue` with the given options.
     ///
     /// If the server cannot declare the queue (e.g., if the queue already exists with options that
-    /// conflict with `options`), it will close this channel.
+    /// conflict with `options`), it will close this channel. Options that the requested
+    /// [`QueueType`](enum.QueueType.html) cannot support are rejected as in
+    /// [`queue_declare`](#method.queue_declare).
     ///
     /// # Panics
     ///
@@ -276,6 +970,7 @@ impl Channel {
             queue != "",
             "cannot asynchronously declare auto-named queues"
         );
+        options.check_compatible()?;
         let declare = AmqpQueue::Declare(options.into_declare(queue.clone(), false, true));
         self.call_nowait(declare)?;
         Ok(Queue::new(self, queue, None, None))
@@ -283,6 +978,14 @@ impl Channel {
 
     /// Passively declare that a queue exists. This asks the server to confirm that a queue named
     /// `queue` already exists; it will close the channel if it does not.
+    ///
+    /// On success, the returned queue's
+    /// [`declared_message_count`](struct.Queue.html#method.declared_message_count) and
+    /// [`declared_consumer_count`](struct.Queue.html#method.declared_consumer_count) reflect the
+    /// queue's current state, so this is a convenient way to monitor queue depth. If the queue
+    /// does not exist, returns
+    /// [`Error::ServerClosedChannel`](enum.Error.html#variant.ServerClosedChannel) with code 404
+    /// (`NOT_FOUND`), and this channel can no longer be used.
     pub fn queue_declare_passive<S: Into<String>>(&self, queue: S) -> Result<Queue> {
         // per spec, if passive is set all other fields are ignored except nowait (which
         // must be false to be meaningful)
@@ -306,14 +1009,36 @@ impl Channel {
     /// will close this channel. Consider using one of the [`queue_declare`](#method.queue_declare)
     /// methods and then [`Queue::get`](struct.Queue.html#method.get) to avoid this.
     ///
-    /// On success, returns `Some(message)` if there was a message in the queue or `None` if there
-    /// were no messages in the queue. If `no_ack` is false, you are responsible for acknowledging
-    /// the returned message, typically via [`Get::ack`](struct.Get.html#method.ack).
+    /// On success, returns `Some(message)` if the server replied with `basic.get-ok` or `None` if
+    /// it replied with `basic.get-empty` (i.e., the queue exists but had no messages ready). Any
+    /// other outcome, including the server closing the channel, is returned as an error, never as
+    /// `None`. If `no_ack` is false, you are responsible for acknowledging the returned message,
+    /// typically via [`Get::ack`](struct.Get.html#method.ack).
     ///
     /// Prefer using [`basic_consume`](#method.basic_consume) to allow the server to push messages
     /// to you on demand instead of polling with `get`.
     pub fn basic_get<S: Into<String>>(&self, queue: S, no_ack: bool) -> Result<Option<Get>> {
-        self.inner.borrow_mut().get(AmqpGet {
+        self.handle()?.get(AmqpGet {
+            ticket: 0,
+            queue: queue.into(),
+            no_ack,
+        })
+    }
+
+    /// Synchronously get a single message from `queue`, receiving its body in chunks as it
+    /// arrives instead of buffering the whole message. Otherwise identical to
+    /// [`basic_get`](#method.basic_get).
+    ///
+    /// Returns once the message's properties have arrived; see
+    /// [`StreamingGet`](struct.StreamingGet.html) for how to read the body. Returns `None` if
+    /// there were no messages in the queue; a message with an empty body is returned as
+    /// `Some(_)` whose body receiver is already disconnected.
+    pub fn basic_get_streaming<S: Into<String>>(
+        &self,
+        queue: S,
+        no_ack: bool,
+    ) -> Result<Option<StreamingGet>> {
+        self.handle()?.get_streaming(AmqpGet {
             ticket: 0,
             queue: queue.into(),
             no_ack,
@@ -323,26 +1048,52 @@ impl Channel {
     /// Synchronously set up a consumer on `queue`. If the queue does not exist, the server will
     /// close this channel. Consider using one of the [`queue_declare`](#method.queue_declare)
     /// methods and then [`Queue::consume`](struct.Queue.html#method.consume) to avoid this.
+    ///
+    /// If the consumer conflicts with exclusive access to the queue (either another consumer
+    /// already has exclusive access, or `options.exclusive` is set and the queue already has
+    /// consumers), the server will close this channel and this method will fail with
+    /// [`Error::ConsumerExclusiveConflict`](enum.Error.html#variant.ConsumerExclusiveConflict).
     pub fn basic_consume<S: Into<String>>(
         &self,
         queue: S,
         options: ConsumerOptions,
     ) -> Result<Consumer> {
-        // NOTE: We currently don't support nowait consumers for two reasons:
-        // 1. We always let the server pick the consumption tag, so without
-        //    the consume-ok we don't have a tag to cancel.
-        // 2. The I/O loop allocates the channel to send deliveries when it
-        //    receives consume-ok.
-        let (tag, rx) = self.inner.borrow_mut().consume(Consume {
-            ticket: 0,
-            queue: queue.into(),
-            consumer_tag: String::new(),
-            no_local: options.no_local,
-            no_ack: options.no_ack,
-            exclusive: options.exclusive,
-            nowait: false,
-            arguments: options.arguments,
-        })?;
+        let queue = queue.into();
+        let config = options.config();
+        let (tag, rx) = self
+            .handle()?
+            .consume(options.into_consume(queue.clone(), false), config)
+            .map_err(|err| match err {
+                Error::ServerClosedChannel { code, message, .. }
+                    if is_exclusive_conflict(code, &message) =>
+                {
+                    Error::ConsumerExclusiveConflict { queue }
+                }
+                err => err,
+            })?;
+        Ok(Consumer::new(self, tag, rx))
+    }
+
+    /// Asynchronously set up a consumer on `queue`.
+    ///
+    /// Because the server does not reply to a nowait consume request, the consumer tag cannot be
+    /// assigned by the server; `options.consumer_tag` must be set to a nonempty tag that is not in
+    /// use by any other consumer on this channel, or this method will fail with
+    /// [`Error::ConsumerTagRequired`](enum.Error.html#variant.ConsumerTagRequired). If the tag is
+    /// already in use or the queue does not exist, the server will close this channel.
+    pub fn basic_consume_nowait<S: Into<String>>(
+        &self,
+        queue: S,
+        options: ConsumerOptions,
+    ) -> Result<Consumer> {
+        let tag = match &options.consumer_tag {
+            Some(tag) if !tag.is_empty() => tag.clone(),
+            _ => return ConsumerTagRequired.fail(),
+        };
+        let config = options.config();
+        let rx = self
+            .handle()?
+            .consume_nowait(options.into_consume(queue.into(), true), config)?;
         Ok(Consumer::new(self, tag, rx))
     }
 
@@ -352,6 +1103,11 @@ impl Channel {
     /// Consider using the [`queue_declare`](#method.queue_declare) and
     /// [`exchange_declare`](#method.exchange_declare) methods and then using
     /// [`Queue::bind`](struct.Queue.html#method.bind) to avoid this.
+    ///
+    /// Every queue is implicitly bound to the default exchange (named `""`) with its own name as
+    /// the routing key, and no other bindings to it are allowed. Binding to it returns
+    /// [`Error::ReservedExchange`](enum.Error.html#variant.ReservedExchange) without contacting
+    /// the server.
     pub fn queue_bind<S0: Into<String>, S1: Into<String>, S2: Into<String>>(
         &self,
         queue: S0,
@@ -359,10 +1115,12 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let exchange = exchange.into();
+        check_bind_exchange(&exchange, "bind to")?;
         let bind = AmqpQueue::Bind(QueueBind {
             ticket: 0,
             queue: queue.into(),
-            exchange: exchange.into(),
+            exchange,
             routing_key: routing_key.into(),
             nowait: false,
             arguments,
@@ -383,10 +1141,12 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let exchange = exchange.into();
+        check_bind_exchange(&exchange, "bind to")?;
         let bind = AmqpQueue::Bind(QueueBind {
             ticket: 0,
             queue: queue.into(),
-            exchange: exchange.into(),
+            exchange,
             routing_key: routing_key.into(),
             nowait: true,
             arguments,
@@ -400,6 +1160,10 @@ impl Channel {
     /// Consider using the [`queue_declare`](#method.queue_declare) and
     /// [`exchange_declare`](#method.exchange_declare) methods and then using
     /// [`Queue::unbind`](struct.Queue.html#method.unbind) to avoid this.
+    ///
+    /// The routing key and arguments must exactly match those of the binding being removed;
+    /// otherwise the server does nothing and still reports success. See
+    /// [`QueueBinding`](struct.QueueBinding.html).
     pub fn queue_unbind<S0: Into<String>, S1: Into<String>, S2: Into<String>>(
         &self,
         queue: S0,
@@ -407,10 +1171,12 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let exchange = exchange.into();
+        check_bind_exchange(&exchange, "unbind from")?;
         let unbind = AmqpQueue::Unbind(QueueUnbind {
             ticket: 0,
             queue: queue.into(),
-            exchange: exchange.into(),
+            exchange,
             routing_key: routing_key.into(),
             arguments,
         });
@@ -477,10 +1243,47 @@ impl Channel {
         self.call_nowait(delete)
     }
 
+    /// Delete `queue` if it exists, then declare it again with `options`, returning the new,
+    /// empty queue. This is mostly useful for setting up tests that need a clean queue; any
+    /// messages, consumers, and bindings the old queue had are gone.
+    ///
+    /// Deleting a queue that does not exist succeeds on RabbitMQ. Other servers may instead close
+    /// the channel with `NOT_FOUND`; if so, a channel opened with
+    /// [`Connection::open_auto_reopen_channel`](struct.Connection.html#method.open_auto_reopen_channel)
+    /// reopens itself and continues with the declare, while any other channel returns the error.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `queue` is `""` (the empty string), as there is no existing
+    /// queue to reset.
+    pub fn reset_queue<S: Into<String>>(
+        &self,
+        queue: S,
+        options: QueueDeclareOptions,
+    ) -> Result<Queue> {
+        let queue = queue.into();
+        assert!(queue != "", "reset_queue must be given a queue name");
+        match self.queue_delete(queue.clone(), QueueDeleteOptions::default()) {
+            Ok(_) => (),
+            Err(Error::ServerClosedChannel { code, .. })
+                if code == AMQPSoftError::NOTFOUND.get_id() && self.reopen.is_some() => {}
+            Err(err) => return Err(err),
+        }
+        self.queue_declare(queue, options)
+    }
+
     /// Synchronously declare an exchange named `exchange` with the given type and options.
     ///
     /// If the server cannot declare the exchange (e.g., if the exchange already exists with a
     /// different type or options that conflict with `options`), it will close this channel.
+    ///
+    /// The default exchange (named `""`) cannot be declared, and no exchange other than the
+    /// standard `amq.direct`, `amq.fanout`, `amq.topic`, `amq.headers`, `amq.match`, and
+    /// `amq.rabbitmq.trace` may be declared with the reserved `amq.` prefix (use
+    /// [`exchange_declare_passive`](#method.exchange_declare_passive) to check that other
+    /// `amq.` exchanges exist). Attempting either returns
+    /// [`Error::ReservedExchange`](enum.Error.html#variant.ReservedExchange) without contacting
+    /// the server.
     pub fn exchange_declare<S: Into<String>>(
         &self,
         type_: ExchangeType,
@@ -488,12 +1291,56 @@ impl Channel {
         options: ExchangeDeclareOptions,
     ) -> Result<Exchange> {
         let exchange = exchange.into();
+        check_declare_exchange(&exchange, false)?;
         let declare =
             AmqpExchange::Declare(options.into_declare(type_, exchange.clone(), false, false));
         self.call::<_, ExchangeDeclareOk>(declare)
             .map(|_ok| Exchange::new(self, exchange))
     }
 
+    /// Synchronously declare an exchange named `exchange` with the given type and options,
+    /// reporting a type conflict with an existing exchange as
+    /// [`Error::ExchangeTypeMismatch`](enum.Error.html#variant.ExchangeTypeMismatch).
+    ///
+    /// AMQP does not provide a way to ask the server for the type of an existing exchange (a
+    /// [passive declare](#method.exchange_declare_passive) only confirms that it exists), so the
+    /// mismatch is detected from the server's `PRECONDITION_FAILED` response to the declare. This
+    /// channel is therefore still closed by the server on a conflict, exactly as with
+    /// [`exchange_declare`](#method.exchange_declare); what this method adds is an error that
+    /// names the expected and actual types instead of an opaque
+    /// [`Error::ServerClosedChannel`](enum.Error.html#variant.ServerClosedChannel). Any other
+    /// failure, including conflicts in options other than the type, is returned unchanged.
+    ///
+    /// Detecting the actual type relies on the format of RabbitMQ's error messages.
+    pub fn exchange_declare_checked<S: Into<String>>(
+        &self,
+        type_: ExchangeType,
+        exchange: S,
+        options: ExchangeDeclareOptions,
+    ) -> Result<Exchange> {
+        let exchange = exchange.into();
+        let expected = type_.as_ref().to_string();
+        self.exchange_declare(type_, exchange.clone(), options)
+            .map_err(|err| {
+                let actual = match &err {
+                    Error::ServerClosedChannel { code, message, .. }
+                        if *code == AMQPSoftError::PRECONDITIONFAILED.get_id() =>
+                    {
+                        current_exchange_type(message)
+                    }
+                    _ => None,
+                };
+                match actual {
+                    Some(actual) => Error::ExchangeTypeMismatch {
+                        exchange,
+                        expected,
+                        actual,
+                    },
+                    None => err,
+                }
+            })
+    }
+
     /// Asynchronously declare an exchange named `exchange` with the given type and options.
     ///
     /// If the server cannot declare the exchange (e.g., if the exchange already exists with a
@@ -505,6 +1352,7 @@ impl Channel {
         options: ExchangeDeclareOptions,
     ) -> Result<Exchange> {
         let exchange = exchange.into();
+        check_declare_exchange(&exchange, false)?;
         let declare =
             AmqpExchange::Declare(options.into_declare(type_, exchange.clone(), false, true));
         self.call_nowait(declare)
@@ -513,8 +1361,14 @@ impl Channel {
 
     /// Passively declare that a exchange exists. This asks the server to confirm that a exchange
     /// named `exchange` already exists; it will close the channel if it does not.
+    ///
+    /// Any exchange with the reserved `amq.` prefix may be passively declared (e.g.,
+    /// `amq.rabbitmq.event`, which is declared by a plugin). The default exchange (named `""`)
+    /// cannot be, and returns [`Error::ReservedExchange`](enum.Error.html#variant.ReservedExchange)
+    /// without contacting the server.
     pub fn exchange_declare_passive<S: Into<String>>(&self, exchange: S) -> Result<Exchange> {
         let exchange = exchange.into();
+        check_declare_exchange(&exchange, true)?;
         // per spec, if passive is set all other fields are ignored except nowait (which
         // must be false to be meaningful)
         let type_ = ExchangeType::Direct;
@@ -547,10 +1401,14 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let destination = destination.into();
+        let source = source.into();
+        check_bind_exchange(&destination, "bind")?;
+        check_bind_exchange(&source, "bind to")?;
         let bind = AmqpExchange::Bind(ExchangeBind {
             ticket: 0,
-            destination: destination.into(),
-            source: source.into(),
+            destination,
+            source,
             routing_key: routing_key.into(),
             nowait: false,
             arguments,
@@ -575,10 +1433,14 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let destination = destination.into();
+        let source = source.into();
+        check_bind_exchange(&destination, "bind")?;
+        check_bind_exchange(&source, "bind to")?;
         let bind = AmqpExchange::Bind(ExchangeBind {
             ticket: 0,
-            destination: destination.into(),
-            source: source.into(),
+            destination,
+            source,
             routing_key: routing_key.into(),
             nowait: true,
             arguments,
@@ -603,10 +1465,14 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let destination = destination.into();
+        let source = source.into();
+        check_bind_exchange(&destination, "unbind")?;
+        check_bind_exchange(&source, "unbind from")?;
         let unbind = AmqpExchange::Unbind(ExchangeUnbind {
             ticket: 0,
-            destination: destination.into(),
-            source: source.into(),
+            destination,
+            source,
             routing_key: routing_key.into(),
             nowait: false,
             arguments,
@@ -633,10 +1499,14 @@ impl Channel {
         routing_key: S2,
         arguments: FieldTable,
     ) -> Result<()> {
+        let destination = destination.into();
+        let source = source.into();
+        check_bind_exchange(&destination, "unbind")?;
+        check_bind_exchange(&source, "unbind from")?;
         let unbind = AmqpExchange::Unbind(ExchangeUnbind {
             ticket: 0,
-            destination: destination.into(),
-            source: source.into(),
+            destination,
+            source,
             routing_key: routing_key.into(),
             nowait: true,
             arguments,
@@ -650,10 +1520,17 @@ impl Channel {
     ///
     /// If the server cannot delete the exchange (either because it does not exist or because
     /// `if_unused` was true and it has queue bindings), it will close this channel.
+    ///
+    /// The default exchange (named `""`) and exchanges with the reserved `amq.` prefix cannot be
+    /// deleted; attempting to do so returns
+    /// [`Error::ReservedExchange`](enum.Error.html#variant.ReservedExchange) without contacting
+    /// the server.
     pub fn exchange_delete<S: Into<String>>(&self, exchange: S, if_unused: bool) -> Result<()> {
+        let exchange = exchange.into();
+        check_delete_exchange(&exchange)?;
         let delete = AmqpExchange::Delete(ExchangeDelete {
             ticket: 0,
-            exchange: exchange.into(),
+            exchange,
             if_unused,
             nowait: false,
         });
@@ -671,9 +1548,11 @@ impl Channel {
         exchange: S,
         if_unused: bool,
     ) -> Result<()> {
+        let exchange = exchange.into();
+        check_delete_exchange(&exchange)?;
         let delete = AmqpExchange::Delete(ExchangeDelete {
             ticket: 0,
-            exchange: exchange.into(),
+            exchange,
             if_unused,
             nowait: true,
         });
@@ -683,15 +1562,50 @@ impl Channel {
     /// Asynchronously acknowledge all messages consumers on this channel have received that have
     /// not yet been acknowledged.
     pub fn ack_all(&self) -> Result<()> {
+        let _span = spans::ack(self.channel_id(), "ack", 0);
         self.call_nowait(AmqpBasic::Ack(Ack {
             delivery_tag: 0,
             multiple: true,
         }))
     }
 
+    /// Asynchronously acknowledge the delivery with the given
+    /// [`delivery_tag`](struct.Delivery.html#method.delivery_tag). If `multiple` is true, also
+    /// acknowledges all other unacknowledged deliveries on this channel with smaller tags.
+    ///
+    /// This allows deliveries to be acknowledged after being stored as plain tags (e.g., when
+    /// processing completes out of order or on another thread). Delivery tags are
+    /// channel-specific; `delivery_tag` must have come from a delivery received on this channel,
+    /// which this method cannot check. Acknowledging an unknown tag causes the server to close the
+    /// channel.
+    pub fn ack_tag(&self, delivery_tag: u64, multiple: bool) -> Result<()> {
+        self.basic_ack_tag(delivery_tag, multiple)
+    }
+
+    /// Asynchronously reject the delivery with the given
+    /// [`delivery_tag`](struct.Delivery.html#method.delivery_tag). If `multiple` is true, also
+    /// rejects all other unacknowledged deliveries on this channel with smaller tags. If `requeue`
+    /// is true, instructs the server to attempt to requeue the messages.
+    ///
+    /// The same caveats about tags belonging to this channel apply as for
+    /// [`ack_tag`](#method.ack_tag).
+    pub fn nack_tag(&self, delivery_tag: u64, multiple: bool, requeue: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id(), "nack", delivery_tag);
+        self.call_nowait(AmqpBasic::Nack(Nack {
+            delivery_tag,
+            multiple,
+            requeue,
+        }))
+    }
+
     pub(crate) fn basic_ack(&self, delivery: Delivery, multiple: bool) -> Result<()> {
+        self.basic_ack_tag(delivery.delivery_tag(), multiple)
+    }
+
+    pub(crate) fn basic_ack_tag(&self, delivery_tag: u64, multiple: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id(), "ack", delivery_tag);
         self.call_nowait(AmqpBasic::Ack(Ack {
-            delivery_tag: delivery.delivery_tag(),
+            delivery_tag,
             multiple,
         }))
     }
@@ -700,6 +1614,7 @@ impl Channel {
     /// not yet been acknowledged. If `requeue` is true, instructs the server to attempt to requeue
     /// all such messages.
     pub fn nack_all(&self, requeue: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id(), "nack", 0);
         self.call_nowait(AmqpBasic::Nack(Nack {
             delivery_tag: 0,
             multiple: true,
@@ -707,12 +1622,39 @@ impl Channel {
         }))
     }
 
+    /// Asynchronously reject `delivery` and all other unacknowledged deliveries received on this
+    /// channel with smaller [`delivery_tag`](struct.Delivery.html#method.delivery_tag)s, in a
+    /// single frame. If `requeue` is true, instructs the server to attempt to requeue the
+    /// messages.
+    ///
+    /// Unlike [`Delivery::nack_multiple`](struct.Delivery.html#method.nack_multiple), this does
+    /// not consume `delivery`, and returns
+    /// [`Error::DeliveryChannelMismatch`](enum.Error.html#variant.DeliveryChannelMismatch)
+    /// instead of panicking if `delivery` was received on a different channel.
+    pub fn nack_up_to(&self, delivery: &Delivery, requeue: bool) -> Result<()> {
+        let channel_id = self.channel_id();
+        if delivery.channel_id() != channel_id {
+            return DeliveryChannelMismatch {
+                delivery_channel_id: delivery.channel_id(),
+                channel_id,
+            }
+            .fail();
+        }
+        let _span = spans::ack(channel_id, "nack", delivery.delivery_tag());
+        self.call_nowait(AmqpBasic::Nack(Nack {
+            delivery_tag: delivery.delivery_tag(),
+            multiple: true,
+            requeue,
+        }))
+    }
+
     pub(crate) fn basic_nack(
         &self,
         delivery: Delivery,
         multiple: bool,
         requeue: bool,
     ) -> Result<()> {
+        let _span = spans::ack(self.channel_id(), "nack", delivery.delivery_tag());
         self.call_nowait(AmqpBasic::Nack(Nack {
             delivery_tag: delivery.delivery_tag(),
             multiple,
@@ -721,20 +1663,382 @@ impl Channel {
     }
 
     pub(crate) fn basic_reject(&self, delivery: Delivery, requeue: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id(), "reject", delivery.delivery_tag());
         self.call_nowait(AmqpBasic::Reject(Reject {
             delivery_tag: delivery.delivery_tag(),
             requeue,
         }))
     }
 
+    /// Get a cloneable, `Send` [`Acker`](struct.Acker.html) that can acknowledge deliveries
+    /// received on this channel from any thread.
+    pub fn acker(&self) -> Acker {
+        self.inner.borrow().acker()
+    }
+
+    pub(crate) fn consumer_canceller(&self, consumer_tag: String) -> ConsumerCanceller {
+        self.inner.borrow().consumer_canceller(consumer_tag)
+    }
+
     pub(crate) fn basic_cancel(&self, consumer: &Consumer) -> Result<()> {
-        // NOTE: We currently don't support nowait cancel for related reasons
-        // to not supproting nowait consume - we want the cancel-ok to clean
-        // up channels in the I/O loop.
         self.call::<_, CancelOk>(AmqpBasic::Cancel(Cancel {
             consumer_tag: consumer.consumer_tag().to_string(),
             nowait: false,
         }))
         .map(|_ok| ())
     }
+
+    pub(crate) fn basic_cancel_nowait(&self, consumer: &Consumer) -> Result<()> {
+        // The I/O loop sends the cancel and consumes the cancel-ok itself, so it can still
+        // clean up the consumer without us waiting for it.
+        self.handle()?
+            .cancel_consumer_nowait(consumer.consumer_tag().to_string())
+    }
+}
+
+#[cfg(all(test, feature = "mock-broker"))]
+mod mock_broker_tests {
+    use super::*;
+    use crate::mock_broker::{with_mock_chan, with_mock_conn};
+    use crate::{ConsumerMessage, MockBroker};
+
+    #[test]
+    fn ack_from_other_threads() {
+        let broker = MockBroker::new();
+        let mut connection = broker.connect().unwrap();
+        let channel = connection.open_channel(None).unwrap();
+
+        let queue = channel
+            .queue_declare("acked", QueueDeclareOptions::default())
+            .unwrap();
+        for body in &[b"a", b"b", b"c"] {
+            channel
+                .basic_publish("", Publish::new(&body[..], queue.name()))
+                .unwrap();
+        }
+        let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+
+        let channel_acker = channel.acker();
+        let mut workers = Vec::new();
+        for i in 0..3 {
+            let delivery = match consumer.receiver().recv_timeout(Duration::from_secs(5)) {
+                Ok(ConsumerMessage::Delivery(delivery)) => delivery,
+                other => panic!("unexpected consumer message {:?}", other),
+            };
+            let acker = if i == 0 {
+                channel_acker.clone()
+            } else {
+                delivery.acker().unwrap()
+            };
+            assert_eq!(acker.channel_id(), channel.channel_id());
+            let delivery_tag = delivery.delivery_tag();
+            workers.push(std::thread::spawn(move || {
+                acker.ack(delivery_tag, false).unwrap()
+            }));
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // closing the channel would requeue anything left unacked
+        channel.close().unwrap();
+        let channel = connection.open_channel(None).unwrap();
+        assert!(channel.basic_get("acked", true).unwrap().is_none());
+
+        connection.close().unwrap();
+        assert!(channel_acker.ack(1, false).is_err());
+    }
+
+    #[test]
+    fn configure_channel() {
+        with_mock_chan(|channel| {
+            channel
+                .configure(ChannelConfig {
+                    prefetch_count: 10,
+                    publisher_confirms: true,
+                    ..ChannelConfig::default()
+                })
+                .unwrap();
+
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            let (tx, rx) = crossbeam_channel::unbounded();
+            let tag = channel
+                .basic_publish_with_callback("", Publish::new(b"hello", queue.name()), move |c| {
+                    let _ = tx.send(c.unwrap());
+                })
+                .unwrap();
+            assert_eq!(tag, 1);
+            assert_eq!(
+                rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+                Confirmation::Ack
+            );
+        })
+    }
+
+    #[test]
+    fn confirms_enabled_guard() {
+        with_mock_chan(|channel| {
+            assert!(!channel.publisher_confirms_enabled());
+            match channel.wait_for_confirm(1, Duration::from_secs(5)) {
+                Err(Error::PublisherConfirmsNotEnabled) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            channel.enable_publisher_confirms().unwrap();
+            assert!(channel.publisher_confirms_enabled());
+        })
+    }
+
+    #[test]
+    fn default_publish_properties() {
+        with_mock_chan(|channel| {
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            channel.set_default_properties(
+                AmqpProperties::default()
+                    .with_app_id("mock-test".to_string())
+                    .with_content_type("text/plain".to_string()),
+            );
+            channel
+                .basic_publish(
+                    "",
+                    Publish::with_properties(
+                        b"hello",
+                        queue.name(),
+                        AmqpProperties::default().with_content_type("text/html".to_string()),
+                    ),
+                )
+                .unwrap();
+
+            let delivery = queue.get(true).unwrap().unwrap().delivery;
+            assert_eq!(delivery.properties.app_id(), &Some("mock-test".to_string()));
+            assert_eq!(
+                delivery.properties.content_type(),
+                &Some("text/html".to_string())
+            );
+        })
+    }
+
+    #[test]
+    fn forget_channels() {
+        with_mock_conn(|connection| {
+            let channels = (0..8)
+                .map(|_| connection.open_channel(None).unwrap())
+                .collect::<Vec<_>>();
+            for channel in channels {
+                channel.forget().unwrap();
+            }
+
+            // The I/O loop must survive the handles going away before their close-oks arrive.
+            let channel = connection.open_channel(None).unwrap();
+            channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            channel.close().unwrap();
+            assert_eq!(connection.channels().unwrap(), Vec::<u16>::new());
+        })
+    }
+
+    #[test]
+    fn reset_queue() {
+        with_mock_chan(|channel| {
+            // Resetting a queue that does not exist just declares it.
+            let queue = channel
+                .reset_queue("reset", QueueDeclareOptions::default())
+                .unwrap();
+            assert_eq!(queue.declared_message_count(), Some(0));
+
+            for _ in 0..3 {
+                channel
+                    .basic_publish("", Publish::new(b"hello", "reset"))
+                    .unwrap();
+            }
+            let queue = channel
+                .reset_queue("reset", QueueDeclareOptions::default())
+                .unwrap();
+            assert_eq!(queue.declared_message_count(), Some(0));
+            assert!(queue.get(false).unwrap().is_none());
+        })
+    }
+
+    #[cfg(feature = "raw-frames")]
+    #[test]
+    fn send_raw_method() {
+        with_mock_chan(|channel| {
+            let declare =
+                QueueDeclareOptions::default().into_declare("raw".to_string(), false, true);
+            channel
+                .send_raw_method(AmqpClass::Queue(AmqpQueue::Declare(declare)))
+                .unwrap();
+            channel.queue_declare_passive("raw").unwrap();
+        })
+    }
+
+    #[cfg(feature = "raw-frames")]
+    #[test]
+    fn raw_publish_keeps_confirm_tags_in_step() {
+        with_mock_chan(|channel| {
+            channel.enable_publisher_confirms().unwrap();
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+
+            channel
+                .send_raw_method(AmqpClass::Basic(AmqpBasic::Publish(AmqpPublish {
+                    ticket: 0,
+                    exchange: String::new(),
+                    routing_key: queue.name().to_string(),
+                    mandatory: false,
+                    immediate: false,
+                })))
+                .unwrap();
+            channel
+                .send_raw_content_header(AmqpPublish::get_class_id(), 3, &AmqpProperties::default())
+                .unwrap();
+            channel.send_raw_content_body(b"raw").unwrap();
+
+            // the raw publish took delivery tag 1
+            let tag = channel
+                .basic_publish_tracked("", Publish::new(b"tracked", queue.name()))
+                .unwrap();
+            assert_eq!(tag, 2);
+            assert_eq!(
+                channel
+                    .wait_for_confirm(tag, Duration::from_secs(5))
+                    .unwrap(),
+                Confirmation::Ack
+            );
+            assert_eq!(channel.pending_confirms(), 0);
+        })
+    }
+
+    #[test]
+    fn batch_interrupted_by_reopen() {
+        with_mock_conn(|connection| {
+            let channel = connection.open_auto_reopen_channel().unwrap();
+            let queue = channel
+                .queue_declare("batched", QueueDeclareOptions::default())
+                .unwrap();
+
+            let result = channel.batch(|channel| {
+                channel.basic_publish("", Publish::new(b"before", "batched"))?;
+                assert!(channel.queue_declare_passive("missing").is_err());
+                // reopens the channel, so this is sent outside the batch
+                channel.basic_publish("", Publish::new(b"after", "batched"))
+            });
+            match result {
+                Err(Error::BatchInterrupted { .. }) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            // the channel is usable, and batches work again
+            channel
+                .batch(|channel| channel.basic_publish("", Publish::new(b"again", "batched")))
+                .unwrap();
+            assert_eq!(
+                queue.declare_passive().unwrap().declared_message_count(),
+                Some(3)
+            );
+        })
+    }
+
+    #[test]
+    fn publish_confirmed_routable() {
+        with_mock_chan(|channel| {
+            let timeout = Duration::from_secs(5);
+
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            match channel.publish_confirmed_routable("", Publish::new(b"a", queue.name()), timeout)
+            {
+                Err(Error::PublisherConfirmsNotEnabled) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            channel.enable_publisher_confirms().unwrap();
+            let returns = channel.listen_for_returns().unwrap();
+            channel
+                .publish_confirmed_routable("", Publish::new(b"a", queue.name()), timeout)
+                .unwrap();
+            match channel.publish_confirmed_routable("", Publish::new(b"b", "missing"), timeout) {
+                Err(Error::Unroutable { reply_code, .. }) => {
+                    assert_eq!(reply_code, AMQPSoftError::NOROUTE.get_id());
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+            assert_eq!(returns.recv_timeout(timeout).unwrap().content, b"b");
+
+            // an earlier return does not leak into later publishes
+            channel
+                .publish_confirmed_routable("", Publish::new(b"c", queue.name()), timeout)
+                .unwrap();
+            assert_eq!(queue.get(true).unwrap().unwrap().delivery.body, b"a");
+            assert_eq!(queue.get(true).unwrap().unwrap().delivery.body, b"c");
+        })
+    }
+
+    #[test]
+    fn server_close_callback() {
+        with_mock_conn(|connection| {
+            let channel = connection.open_channel(None).unwrap();
+
+            let (tx, rx) = crossbeam_channel::unbounded();
+            channel
+                .on_server_close(move |close| {
+                    let _ = tx.send(close);
+                })
+                .unwrap();
+            assert!(channel.queue_declare_passive("nonexistent").is_err());
+
+            let close = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(close.channel_id, channel.channel_id());
+            assert_eq!(close.reply_code, AMQPSoftError::NOTFOUND.get_id());
+            assert!(close.reply_text.contains("nonexistent"));
+
+            // client-initiated closes do not invoke the callback
+            let channel = connection.open_channel(None).unwrap();
+            let (tx, rx) = crossbeam_channel::unbounded();
+            channel
+                .on_server_close(move |close| {
+                    let _ = tx.send(close);
+                })
+                .unwrap();
+            channel.close().unwrap();
+            assert!(rx.recv().is_err());
+        })
+    }
+
+    #[test]
+    fn closed_channel_vs_dropped_io_loop() {
+        let broker = MockBroker::new();
+        let mut connection = broker.connect().unwrap();
+        let closed = connection.open_channel(None).unwrap();
+        let open = connection.open_channel(None).unwrap();
+
+        // every operation after a server channel close reports that close, not EventLoopDropped
+        assert!(closed.queue_declare_passive("nonexistent").is_err());
+        for _ in 0..2 {
+            match closed.queue_declare_passive("nonexistent").unwrap_err() {
+                Error::ServerClosedChannel { code, .. } => {
+                    assert_eq!(code, AMQPSoftError::NOTFOUND.get_id())
+                }
+                err => panic!("unexpected error {}", err),
+            }
+        }
+
+        // once the I/O loop is gone, operations report that instead
+        connection.close().unwrap();
+        match open.queue_declare_passive("nonexistent").unwrap_err() {
+            Error::ClientClosedConnection | Error::EventLoopDropped => (),
+            err => panic!("unexpected error {}", err),
+        }
+        match open.queue_declare_passive("nonexistent").unwrap_err() {
+            Error::EventLoopDropped => (),
+            err => panic!("unexpected error {}", err),
+        }
+    }
 }
@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use crossbeam_channel::Receiver;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::mem;
 
 /// Payload for a publisher confirmation message (either an [ack](enum.Confirm.html#variant.Ack) or
 /// a [nack](enum.Confirm.html#variant.Nack)) from the server.
@@ -221,6 +223,129 @@ where
     }
 }
 
+/// The server's confirmation of a single message published via
+/// [`Channel::basic_publish_tracked`](struct.Channel.html#method.basic_publish_tracked); see
+/// [`Channel::wait_for_confirm`](struct.Channel.html#method.wait_for_confirm).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// The server received the message.
+    Ack,
+
+    /// The server rejected the message.
+    Nack,
+}
+
+/// The server's confirmation of a single message published via
+/// [`Channel::publish_batch`](struct.Channel.html#method.publish_batch), identified by the
+/// message's index in the batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchConfirm {
+    /// The server received the message at this index.
+    Ack(usize),
+
+    /// The server rejected the message at this index.
+    Nack(usize),
+}
+
+/// Tracks the publisher confirmations for a batch of messages published via
+/// [`Channel::publish_batch`](struct.Channel.html#method.publish_batch).
+///
+/// `PublishBatch` is an iterator that blocks waiting for confirmations from the server, yielding
+/// one [`BatchConfirm`](enum.BatchConfirm.html) per message in the order the server confirms
+/// them (which is not necessarily the order they were published). Confirmations the server sends
+/// for several messages at once are split up per message. Iteration ends once every message has
+/// been confirmed, or early if the channel is closed; in that case
+/// [`unconfirmed`](#method.unconfirmed) lists the messages whose fate is unknown.
+#[derive(Debug)]
+pub struct PublishBatch {
+    rx: Receiver<Confirm>,
+    first_tag: u64,
+    len: usize,
+    unconfirmed: BTreeSet<u64>,
+    ready: VecDeque<BatchConfirm>,
+}
+
+impl PublishBatch {
+    pub(crate) fn new(rx: Receiver<Confirm>, first_tag: u64, len: usize) -> PublishBatch {
+        PublishBatch {
+            rx,
+            first_tag,
+            len,
+            unconfirmed: (first_tag..first_tag + len as u64).collect(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// The number of messages in the batch.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the batch contains no messages.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Indices of messages in the batch that the server has not yet confirmed.
+    pub fn unconfirmed(&self) -> impl Iterator<Item = usize> + '_ {
+        let first_tag = self.first_tag;
+        self.unconfirmed
+            .iter()
+            .map(move |tag| (tag - first_tag) as usize)
+    }
+
+    fn process(&mut self, confirm: Confirm) {
+        let (payload, to_confirm): (_, fn(usize) -> BatchConfirm) = match confirm {
+            Confirm::Ack(payload) => (payload, BatchConfirm::Ack),
+            Confirm::Nack(payload) => (payload, BatchConfirm::Nack),
+        };
+        // Confirms for messages published before this batch are ignored. Per the spec, a
+        // multiple confirm with a tag of 0 covers every outstanding message.
+        let confirmed = if !payload.multiple {
+            if self.unconfirmed.remove(&payload.delivery_tag) {
+                vec![payload.delivery_tag]
+            } else {
+                Vec::new()
+            }
+        } else if payload.delivery_tag == 0 {
+            mem::replace(&mut self.unconfirmed, BTreeSet::new())
+                .into_iter()
+                .collect()
+        } else {
+            let still_unconfirmed = self.unconfirmed.split_off(&(payload.delivery_tag + 1));
+            mem::replace(&mut self.unconfirmed, still_unconfirmed)
+                .into_iter()
+                .collect()
+        };
+        let first_tag = self.first_tag;
+        self.ready.extend(
+            confirmed
+                .into_iter()
+                .map(|tag| to_confirm((tag - first_tag) as usize)),
+        );
+    }
+}
+
+impl Iterator for PublishBatch {
+    type Item = BatchConfirm;
+
+    fn next(&mut self) -> Option<BatchConfirm> {
+        loop {
+            if let Some(confirm) = self.ready.pop_front() {
+                return Some(confirm);
+            }
+            if self.unconfirmed.is_empty() {
+                return None;
+            }
+            // If the channel is closed, the I/O thread drops the sending side.
+            let confirm = self.rx.recv().ok()?;
+            self.process(confirm);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -315,4 +440,44 @@ mod tests {
         let expected = vec![single(3, Confirm::Ack)];
         assert_eq!(expected, one.collect::<Vec<_>>());
     }
+
+    #[test]
+    fn publish_batch() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut batch = PublishBatch::new(rx, 3, 5);
+        assert_eq!(batch.len(), 5);
+
+        // a confirm for a message published before the batch is ignored, as is the part of a
+        // multiple confirm that covers such messages
+        tx.send(single(2, Confirm::Ack)).unwrap();
+        tx.send(single(5, Confirm::Nack)).unwrap();
+        tx.send(multiple(4, Confirm::Ack)).unwrap();
+        assert_eq!(batch.next(), Some(BatchConfirm::Nack(2)));
+        assert_eq!(batch.next(), Some(BatchConfirm::Ack(0)));
+        assert_eq!(batch.next(), Some(BatchConfirm::Ack(1)));
+        assert_eq!(batch.unconfirmed().collect::<Vec<_>>(), vec![3, 4]);
+
+        // the channel closing ends iteration early
+        tx.send(single(7, Confirm::Ack)).unwrap();
+        drop(tx);
+        assert_eq!(batch.next(), Some(BatchConfirm::Ack(4)));
+        assert_eq!(batch.next(), None);
+        assert_eq!(batch.unconfirmed().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn publish_batch_multiple_zero() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let batch = PublishBatch::new(rx, 1, 3);
+        tx.send(multiple(0, Confirm::Ack)).unwrap();
+        let confirms = batch.collect::<Vec<_>>();
+        assert_eq!(
+            confirms,
+            vec![
+                BatchConfirm::Ack(0),
+                BatchConfirm::Ack(1),
+                BatchConfirm::Ack(2)
+            ]
+        );
+    }
 }
@@ -1,8 +1,10 @@
 use crate::errors::*;
-use crate::Sasl;
+use crate::{IoStream, Sasl, ServerCapabilities};
 use amq_protocol::protocol::connection::{Open, Start, StartOk, Tune, TuneOk};
 use amq_protocol::protocol::constants::FRAME_MIN_SIZE;
 use amq_protocol::types::{AMQPValue, FieldTable};
+use snafu::ResultExt;
+use std::thread::Builder;
 use std::time::Duration;
 
 /// Options that control the overall AMQP connection.
@@ -22,6 +24,13 @@ use std::time::Duration;
 ///     .heartbeat(60)
 ///     .connection_timeout(None)
 ///     .information(None)
+///     .client_product(None)
+///     .client_version(None)
+///     .client_platform(None)
+///     .tcp_nodelay(true)
+///     .tcp_keepalive(None)
+///     .io_thread_name(None)
+///     .io_thread_stack_size(None)
 /// # }
 /// ```
 #[derive(Clone, Debug, PartialEq)]
@@ -34,6 +43,13 @@ pub struct ConnectionOptions<Auth: Sasl> {
     pub(crate) heartbeat: u16,
     pub(crate) connection_timeout: Option<Duration>,
     information: Option<String>,
+    client_product: Option<String>,
+    client_version: Option<String>,
+    client_platform: Option<String>,
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    io_thread_name: Option<String>,
+    io_thread_stack_size: Option<usize>,
 }
 
 impl<Auth: Sasl> Default for ConnectionOptions<Auth> {
@@ -48,6 +64,13 @@ impl<Auth: Sasl> Default for ConnectionOptions<Auth> {
             heartbeat: 60,
             connection_timeout: None,
             information: None,
+            client_product: None,
+            client_version: None,
+            client_platform: None,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            io_thread_name: None,
+            io_thread_stack_size: None,
         }
     }
 }
@@ -94,7 +117,9 @@ impl<Auth: Sasl> ConnectionOptions<Auth> {
     /// `frame_max` bytes will be broken up into multiple frames.
     ///
     /// Note that AMQP specifies a minimum frame_max of 4096; attempting to set a value lower than
-    /// this will result in an error when attempting to open the connection.
+    /// this will result in an
+    /// [`Error::InvalidOptions`](enum.Error.html#variant.InvalidOptions) when attempting to open
+    /// the connection.
     pub fn frame_max(self, frame_max: u32) -> Self {
         ConnectionOptions { frame_max, ..self }
     }
@@ -106,8 +131,11 @@ impl<Auth: Sasl> ConnectionOptions<Auth> {
         ConnectionOptions { heartbeat, ..self }
     }
 
-    /// Sets the timeout for the initial TCP connection. If None (the default), there is no
-    /// timeout.
+    /// Sets the timeout for establishing the connection. The TCP connection, TLS handshake (if
+    /// any), and AMQP handshake must all complete within this time, or opening the connection
+    /// fails with [`Error::ConnectionTimeout`](enum.Error.html#variant.ConnectionTimeout). This
+    /// bounds the total time spent, even if the server is responding slowly rather than not at
+    /// all. If None (the default), there is no timeout.
     pub fn connection_timeout(self, connection_timeout: Option<Duration>) -> Self {
         ConnectionOptions {
             connection_timeout,
@@ -125,6 +153,141 @@ impl<Auth: Sasl> ConnectionOptions<Auth> {
         }
     }
 
+    /// Sets the "product" string reported during handshaking to the server, which the RabbitMQ
+    /// management interface displays as the client of a connection. If None (the default),
+    /// reports `amiquip`.
+    pub fn client_product(self, client_product: Option<String>) -> Self {
+        ConnectionOptions {
+            client_product,
+            ..self
+        }
+    }
+
+    /// Sets the "version" string reported during handshaking to the server. If None (the
+    /// default), reports the version of amiquip.
+    pub fn client_version(self, client_version: Option<String>) -> Self {
+        ConnectionOptions {
+            client_version,
+            ..self
+        }
+    }
+
+    /// Sets the "platform" string reported during handshaking to the server. If None (the
+    /// default), reports the target OS and the version of Rust amiquip was compiled with.
+    pub fn client_platform(self, client_platform: Option<String>) -> Self {
+        ConnectionOptions {
+            client_platform,
+            ..self
+        }
+    }
+
+    /// Sets whether `TCP_NODELAY` is enabled on the connection's socket, disabling Nagle's
+    /// algorithm. Defaults to true, as AMQP traffic typically consists of many small frames that
+    /// are sensitive to the latency Nagle's algorithm introduces.
+    ///
+    /// This option is applied via [`IoStream::set_tcp_options`](trait.IoStream.html#method.set_tcp_options), so it has
+    /// no effect on custom stream types that do not implement that method.
+    pub fn tcp_nodelay(self, tcp_nodelay: bool) -> Self {
+        ConnectionOptions {
+            tcp_nodelay,
+            ..self
+        }
+    }
+
+    /// Sets the OS-level TCP keepalive interval on the connection's socket. If None (the default),
+    /// keepalive is disabled. This is independent of AMQP-level
+    /// [`heartbeat`](#method.heartbeat)s.
+    ///
+    /// This option is applied via [`IoStream::set_tcp_options`](trait.IoStream.html#method.set_tcp_options), so it has
+    /// no effect on custom stream types that do not implement that method.
+    pub fn tcp_keepalive(self, tcp_keepalive: Option<Duration>) -> Self {
+        ConnectionOptions {
+            tcp_keepalive,
+            ..self
+        }
+    }
+
+    /// Sets the name of the connection's I/O thread, which shows up in panic messages, debuggers,
+    /// and (on most platforms) OS tools like `top`. If None (the default), the thread is named
+    /// `amiquip-io`. Naming it after the server (e.g., `amiquip-io-rabbit1`) makes it easier to
+    /// tell connections apart in processes that open several.
+    pub fn io_thread_name(self, io_thread_name: Option<String>) -> Self {
+        ConnectionOptions {
+            io_thread_name,
+            ..self
+        }
+    }
+
+    /// Sets the stack size in bytes of the connection's I/O thread. If None (the default), the
+    /// thread uses the standard library's default stack size.
+    pub fn io_thread_stack_size(self, io_thread_stack_size: Option<usize>) -> Self {
+        ConnectionOptions {
+            io_thread_stack_size,
+            ..self
+        }
+    }
+
+    pub(crate) fn io_thread_builder(&self) -> Builder {
+        let name = self
+            .io_thread_name
+            .clone()
+            .unwrap_or_else(|| "amiquip-io".to_string());
+        let builder = Builder::new().name(name);
+        match self.io_thread_stack_size {
+            Some(size) => builder.stack_size(size),
+            None => builder,
+        }
+    }
+
+    // Check settings against the spec's requirements before we connect, so mistakes are reported
+    // clearly instead of as a failure partway through the handshake.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let invalid = |field: &'static str, detail: String| -> Result<()> {
+            InvalidOptions { field, detail }.fail()
+        };
+        if self.frame_max != 0 && self.frame_max < u32::from(FRAME_MIN_SIZE) {
+            return invalid(
+                "frame_max",
+                format!(
+                    "{} is below the AMQP minimum of {} (use 0 to let the server choose)",
+                    self.frame_max, FRAME_MIN_SIZE
+                ),
+            );
+        }
+        if self.locale.is_empty() {
+            return invalid("locale", "must not be empty".to_string());
+        }
+        if self.connection_timeout == Some(Duration::from_secs(0)) {
+            return invalid(
+                "connection_timeout",
+                "must be nonzero (use None to disable the timeout)".to_string(),
+            );
+        }
+        if let Some(name) = &self.io_thread_name {
+            if name.contains('\0') {
+                return invalid("io_thread_name", "must not contain NUL bytes".to_string());
+            }
+        }
+        if let Some(keepalive) = self.tcp_keepalive {
+            if keepalive.as_secs() == 0 {
+                return invalid(
+                    "tcp_keepalive",
+                    format!(
+                        "{:?} is less than one second (use None to disable keepalive)",
+                        keepalive
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn configure_stream<S: IoStream>(&self, stream: &S) -> Result<()> {
+        stream
+            .set_tcp_options(self.tcp_nodelay, self.tcp_keepalive)
+            .context(ConfigureTcpStream)
+    }
+
     pub(crate) fn make_start_ok(&self, start: Start) -> Result<(StartOk, FieldTable)> {
         // helper to search space-separated strings (mechanisms and locales)
         fn server_supports(server: &str, client: &str) -> bool {
@@ -153,25 +316,40 @@ impl<Auth: Sasl> ConnectionOptions<Auth> {
         let mut set_prop = |k: &str, v: String| {
             client_properties.insert(k.to_string(), AMQPValue::LongString(v));
         };
-        set_prop("product", crate::built_info::PKG_NAME.to_string());
-        set_prop("version", crate::built_info::PKG_VERSION.to_string());
+        set_prop(
+            "product",
+            self.client_product
+                .clone()
+                .unwrap_or_else(|| crate::built_info::PKG_NAME.to_string()),
+        );
+        set_prop(
+            "version",
+            self.client_version
+                .clone()
+                .unwrap_or_else(|| crate::built_info::PKG_VERSION.to_string()),
+        );
         set_prop(
             "platform",
-            format!(
-                "{} / {}",
-                crate::built_info::CFG_OS,
-                crate::built_info::RUSTC_VERSION
-            ),
+            self.client_platform.clone().unwrap_or_else(|| {
+                format!(
+                    "{} / {}",
+                    crate::built_info::CFG_OS,
+                    crate::built_info::RUSTC_VERSION
+                )
+            }),
         );
         if let Some(information) = &self.information {
             set_prop("information", information.to_string());
         }
+        // Only announce extensions the server advertises; a server that lacks one won't send
+        // the corresponding methods, and we shouldn't claim to rely on them.
+        let server_capabilities = ServerCapabilities::new(&start.server_properties);
         let mut capabilities = FieldTable::new();
-        let mut set_cap = |k: &str| {
-            capabilities.insert(k.to_string(), AMQPValue::Boolean(true));
-        };
-        set_cap("consumer_cancel_notify");
-        set_cap("connection.blocked");
+        for &cap in &["consumer_cancel_notify", "connection.blocked"] {
+            if server_capabilities.supports(cap) {
+                capabilities.insert(cap.to_string(), AMQPValue::Boolean(true));
+            }
+        }
         client_properties.insert(
             "capabilities".to_string(),
             AMQPValue::FieldTable(capabilities),
@@ -241,6 +419,87 @@ mod tests {
     use super::*;
     use crate::auth::Auth;
 
+    #[test]
+    fn tcp_options() {
+        use mio::net::TcpStream;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(&listener.local_addr().unwrap()).unwrap();
+
+        let options = ConnectionOptions::<Auth>::default();
+        options.configure_stream(&stream).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(stream.keepalive().unwrap(), None);
+
+        let keepalive = Some(Duration::from_secs(30));
+        let options = ConnectionOptions::<Auth>::default()
+            .tcp_nodelay(false)
+            .tcp_keepalive(keepalive);
+        options.configure_stream(&stream).unwrap();
+        assert!(!stream.nodelay().unwrap());
+        assert_eq!(stream.keepalive().unwrap(), keepalive);
+    }
+
+    #[test]
+    fn validate() {
+        fn invalid_field(options: ConnectionOptions<Auth>) -> &'static str {
+            match options.validate() {
+                Err(Error::InvalidOptions { field, .. }) => field,
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        let options = ConnectionOptions::<Auth>::default;
+        options().validate().unwrap();
+        options()
+            .frame_max(u32::from(FRAME_MIN_SIZE))
+            .tcp_keepalive(Some(Duration::from_secs(1)))
+            .connection_timeout(Some(Duration::from_millis(1)))
+            .validate()
+            .unwrap();
+
+        assert_eq!(
+            invalid_field(options().frame_max(u32::from(FRAME_MIN_SIZE) - 1)),
+            "frame_max"
+        );
+        assert_eq!(invalid_field(options().locale("")), "locale");
+        assert_eq!(
+            invalid_field(options().connection_timeout(Some(Duration::from_secs(0)))),
+            "connection_timeout"
+        );
+        assert_eq!(
+            invalid_field(options().tcp_keepalive(Some(Duration::from_millis(500)))),
+            "tcp_keepalive"
+        );
+        assert_eq!(
+            invalid_field(options().io_thread_name(Some("amiquip\0io".to_string()))),
+            "io_thread_name"
+        );
+    }
+
+    #[test]
+    fn io_thread_builder() {
+        let spawn_name = |options: ConnectionOptions<Auth>| {
+            options
+                .io_thread_builder()
+                .spawn(|| std::thread::current().name().map(str::to_string))
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        let options = ConnectionOptions::<Auth>::default;
+        assert_eq!(spawn_name(options()), Some("amiquip-io".to_string()));
+        assert_eq!(
+            spawn_name(
+                options()
+                    .io_thread_name(Some("amiquip-io-rabbit1".to_string()))
+                    .io_thread_stack_size(Some(256 << 10))
+            ),
+            Some("amiquip-io-rabbit1".to_string())
+        );
+    }
+
     #[test]
     fn channel_max() {
         fn tune_with_channel_max(channel_max: u16) -> Tune {
@@ -277,6 +536,33 @@ mod tests {
         assert_eq!(tune_ok.channel_max, 10);
     }
 
+    #[test]
+    fn frame_max() {
+        fn tune_with_frame_max(frame_max: u32) -> Tune {
+            Tune {
+                channel_max: 0,
+                frame_max,
+                heartbeat: 60,
+            }
+        }
+
+        let options = ConnectionOptions::<Auth>::default().frame_max(0);
+        let tune_ok = options.make_tune_ok(tune_with_frame_max(1 << 17)).unwrap();
+        assert_eq!(tune_ok.frame_max, 1 << 17);
+
+        let options = ConnectionOptions::<Auth>::default().frame_max(4096);
+        let tune_ok = options.make_tune_ok(tune_with_frame_max(1 << 17)).unwrap();
+        assert_eq!(tune_ok.frame_max, 4096);
+
+        let options = ConnectionOptions::<Auth>::default().frame_max(4096);
+        let tune_ok = options.make_tune_ok(tune_with_frame_max(0)).unwrap();
+        assert_eq!(tune_ok.frame_max, 4096);
+
+        let options = ConnectionOptions::<Auth>::default().frame_max(1 << 20);
+        let tune_ok = options.make_tune_ok(tune_with_frame_max(1 << 17)).unwrap();
+        assert_eq!(tune_ok.frame_max, 1 << 17);
+    }
+
     #[test]
     fn unsupported_auth_mechanism() {
         let options = ConnectionOptions::<Auth>::default();
@@ -298,6 +584,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn client_properties() {
+        fn start() -> Start {
+            Start {
+                version_major: 0,
+                version_minor: 9,
+                server_properties: FieldTable::new(),
+                mechanisms: Auth::default().mechanism(),
+                locales: "en_US".to_string(),
+            }
+        }
+        fn prop(start_ok: &StartOk, key: &str) -> String {
+            match start_ok.client_properties.get(key) {
+                Some(AMQPValue::LongString(value)) => value.clone(),
+                other => panic!("unexpected {} property {:?}", key, other),
+            }
+        }
+
+        let options = ConnectionOptions::<Auth>::default();
+        let (start_ok, _) = options.make_start_ok(start()).unwrap();
+        assert_eq!(prop(&start_ok, "product"), crate::built_info::PKG_NAME);
+        assert_eq!(prop(&start_ok, "version"), crate::built_info::PKG_VERSION);
+        assert!(prop(&start_ok, "platform").contains(crate::built_info::RUSTC_VERSION));
+
+        let options = ConnectionOptions::<Auth>::default()
+            .client_product(Some("my-service".to_string()))
+            .client_version(Some("1.2.3".to_string()))
+            .client_platform(Some("Rust".to_string()));
+        let (start_ok, _) = options.make_start_ok(start()).unwrap();
+        assert_eq!(prop(&start_ok, "product"), "my-service");
+        assert_eq!(prop(&start_ok, "version"), "1.2.3");
+        assert_eq!(prop(&start_ok, "platform"), "Rust");
+    }
+
+    #[test]
+    fn client_capabilities() {
+        fn client_capabilities(server_capabilities: &[&str]) -> Vec<String> {
+            let mut capabilities = FieldTable::new();
+            for &cap in server_capabilities {
+                capabilities.insert(cap.to_string(), AMQPValue::Boolean(true));
+            }
+            let mut server_properties = FieldTable::new();
+            server_properties.insert(
+                "capabilities".to_string(),
+                AMQPValue::FieldTable(capabilities),
+            );
+            let start = Start {
+                version_major: 0,
+                version_minor: 9,
+                server_properties,
+                mechanisms: Auth::default().mechanism(),
+                locales: "en_US".to_string(),
+            };
+            let (start_ok, _) = ConnectionOptions::<Auth>::default()
+                .make_start_ok(start)
+                .unwrap();
+            match start_ok.client_properties.get("capabilities") {
+                Some(AMQPValue::FieldTable(capabilities)) => capabilities.keys().cloned().collect(),
+                other => panic!("unexpected capabilities {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            client_capabilities(&[
+                "consumer_cancel_notify",
+                "connection.blocked",
+                "publisher_confirms"
+            ]),
+            vec!["connection.blocked", "consumer_cancel_notify"]
+        );
+        assert_eq!(
+            client_capabilities(&["publisher_confirms"]),
+            Vec::<String>::new()
+        );
+    }
+
     #[test]
     fn unsupported_locale() {
         let server_locales = "en_US es_ES";
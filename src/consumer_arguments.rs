@@ -0,0 +1,160 @@
+use crate::{AmqpValue, FieldTable};
+
+/// Where a consumer on a [RabbitMQ stream](https://www.rabbitmq.com/streams.html) should start
+/// reading, sent as the `x-stream-offset` consumer argument.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamOffset {
+    /// Start from the first message available in the stream.
+    First,
+
+    /// Start from the last chunk of messages written to the stream.
+    Last,
+
+    /// Start from the next message written to the stream after the consumer starts.
+    Next,
+
+    /// Start from the message at the given offset.
+    Offset(u64),
+
+    /// Start from the first chunk of messages written at or after the given time, in seconds
+    /// since the Unix epoch.
+    Timestamp(u64),
+}
+
+impl StreamOffset {
+    fn into_value(self) -> AmqpValue {
+        match self {
+            StreamOffset::First => AmqpValue::LongString("first".to_string()),
+            StreamOffset::Last => AmqpValue::LongString("last".to_string()),
+            StreamOffset::Next => AmqpValue::LongString("next".to_string()),
+            StreamOffset::Offset(offset) => {
+                AmqpValue::LongLongInt(offset.min(i64::max_value() as u64) as i64)
+            }
+            StreamOffset::Timestamp(seconds) => AmqpValue::Timestamp(seconds),
+        }
+    }
+}
+
+/// Builder for the [`arguments`](struct.ConsumerOptions.html#structfield.arguments) passed when
+/// starting a consumer.
+///
+/// The server rejects consumer arguments that are encoded with an unexpected AMQP field type
+/// (e.g., a stream offset sent as a string that is not one of `first`, `last`, or `next`). This
+/// builder encodes the commonly used RabbitMQ arguments with the types the server expects;
+/// [`argument`](#method.argument) can be used to set anything else.
+///
+/// # Example
+///
+/// ```rust
+/// # use amiquip::{ConsumerArguments, ConsumerOptions, StreamOffset};
+/// let options = ConsumerOptions {
+///     arguments: ConsumerArguments::new()
+///         .priority(10)
+///         .stream_offset(StreamOffset::First)
+///         .into_arguments(),
+///     ..ConsumerOptions::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsumerArguments {
+    arguments: FieldTable,
+}
+
+impl ConsumerArguments {
+    /// Create an empty set of consumer arguments.
+    pub fn new() -> ConsumerArguments {
+        ConsumerArguments::default()
+    }
+
+    /// Set the [consumer priority](https://www.rabbitmq.com/consumer-priority.html)
+    /// (`x-priority`).
+    ///
+    /// While a higher-priority consumer on a queue has capacity (i.e., its prefetch limit has not
+    /// been reached), the server delivers messages only to it; lower-priority consumers receive
+    /// messages only when every higher-priority consumer is busy or gone. Consumers with equal
+    /// priority share messages round-robin, and consumers without `x-priority` have priority 0.
+    /// Giving an active consumer a higher priority than its standby replicas is a simple way to
+    /// get active/standby failover without any coordination between them.
+    ///
+    /// The priority is sent as a signed 32-bit integer; RabbitMQ rejects the consume and closes
+    /// the channel if `x-priority` has any other type.
+    pub fn priority(self, priority: i32) -> ConsumerArguments {
+        self.argument("x-priority", AmqpValue::LongInt(priority))
+    }
+
+    /// Set whether the server should cancel this consumer when the mirrored queue it is consuming
+    /// from fails over (`x-cancel-on-ha-failover`).
+    pub fn cancel_on_ha_failover(self, cancel: bool) -> ConsumerArguments {
+        self.argument("x-cancel-on-ha-failover", AmqpValue::Boolean(cancel))
+    }
+
+    /// Set where a consumer on a stream queue should start reading (`x-stream-offset`).
+    pub fn stream_offset(self, offset: StreamOffset) -> ConsumerArguments {
+        self.argument("x-stream-offset", offset.into_value())
+    }
+
+    /// Set an arbitrary argument, replacing any previous value for `key`.
+    pub fn argument<K: Into<String>>(mut self, key: K, value: AmqpValue) -> ConsumerArguments {
+        self.arguments.insert(key.into(), value);
+        self
+    }
+
+    /// Convert these arguments into the `FieldTable` expected by
+    /// [`ConsumerOptions`](struct.ConsumerOptions.html).
+    pub fn into_arguments(self) -> FieldTable {
+        self.arguments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_arguments() {
+        let arguments = ConsumerArguments::new()
+            .priority(5)
+            .cancel_on_ha_failover(true)
+            .stream_offset(StreamOffset::Next)
+            .into_arguments();
+        let mut expected = FieldTable::new();
+        expected.insert("x-priority".to_string(), AmqpValue::LongInt(5));
+        expected.insert(
+            "x-cancel-on-ha-failover".to_string(),
+            AmqpValue::Boolean(true),
+        );
+        expected.insert(
+            "x-stream-offset".to_string(),
+            AmqpValue::LongString("next".to_string()),
+        );
+        assert_eq!(arguments, expected);
+    }
+
+    #[test]
+    fn stream_offset_types() {
+        let offset = |offset| {
+            ConsumerArguments::new()
+                .stream_offset(offset)
+                .into_arguments()
+                .remove("x-stream-offset")
+                .unwrap()
+        };
+        assert_eq!(
+            offset(StreamOffset::First),
+            AmqpValue::LongString("first".to_string())
+        );
+        assert_eq!(
+            offset(StreamOffset::Last),
+            AmqpValue::LongString("last".to_string())
+        );
+        assert_eq!(offset(StreamOffset::Offset(42)), AmqpValue::LongLongInt(42));
+        assert_eq!(
+            offset(StreamOffset::Offset(u64::max_value())),
+            AmqpValue::LongLongInt(i64::max_value())
+        );
+        assert_eq!(
+            offset(StreamOffset::Timestamp(1_600_000_000)),
+            AmqpValue::Timestamp(1_600_000_000)
+        );
+    }
+}
@@ -1,17 +1,27 @@
-use crate::{AmqpProperties, Channel, Result};
+use crate::errors::*;
+use crate::io_loop::Acker;
+use crate::timestamp::from_amqp_timestamp;
+use crate::{AmqpProperties, Channel};
 use amq_protocol::protocol::basic::{Deliver, GetOk};
+use amq_protocol::types::AMQPValue;
+use snafu::OptionExt;
+use std::time::SystemTime;
 
 /// A message delivered to a consumer.
 #[derive(Clone, Debug)]
 pub struct Delivery {
     channel_id: u16,
     delivery_tag: u64,
+    consumer_tag: Option<String>,
+    acker: Option<Acker>,
+    // True if the server considers this delivery acknowledged as soon as it was sent.
+    no_ack: bool,
 
     /// If true, this message has previously been delivered to this or another consumer.
     pub redelivered: bool,
 
-    /// The name of the exchange this message was originally published to. May be an empty string
-    /// (the default exhange).
+    /// The name of the exchange this message was originally published to, exactly as sent by the
+    /// server. This is an empty string for messages published to the default exchange.
     pub exchange: String,
 
     /// The routing key specified when this message was published.
@@ -32,10 +42,13 @@ impl Delivery {
         properties: AmqpProperties,
     ) -> (String, Delivery) {
         (
-            deliver.consumer_tag,
+            deliver.consumer_tag.clone(),
             Delivery {
                 channel_id,
                 delivery_tag: deliver.delivery_tag,
+                consumer_tag: Some(deliver.consumer_tag),
+                acker: None,
+                no_ack: false,
                 redelivered: deliver.redelivered,
                 exchange: deliver.exchange,
                 routing_key: deliver.routing_key,
@@ -54,6 +67,9 @@ impl Delivery {
         Delivery {
             channel_id,
             delivery_tag: get_ok.delivery_tag,
+            consumer_tag: None,
+            acker: None,
+            no_ack: false,
             redelivered: get_ok.redelivered,
             exchange: get_ok.exchange,
             routing_key: get_ok.routing_key,
@@ -62,12 +78,173 @@ impl Delivery {
         }
     }
 
+    pub(crate) fn with_acker(self, acker: Acker, no_ack: bool) -> Delivery {
+        debug_assert_eq!(self.channel_id, acker.channel_id());
+        Delivery {
+            acker: Some(acker),
+            no_ack,
+            ..self
+        }
+    }
+
+    #[inline]
+    pub(crate) fn no_ack(&self) -> bool {
+        self.no_ack
+    }
+
     /// The server-assigned delivery tag for this message. Delivery tags are channel-specific.
+    ///
+    /// A tag can be stored and used to acknowledge or reject this message later without keeping
+    /// the `Delivery` itself; see [`Channel::ack_tag`](struct.Channel.html#method.ack_tag) and
+    /// [`Channel::nack_tag`](struct.Channel.html#method.nack_tag).
     #[inline]
     pub fn delivery_tag(&self) -> u64 {
         self.delivery_tag
     }
 
+    /// The tag of the consumer this message was delivered to, as sent by the server in
+    /// `basic.deliver`. This identifies the consumer when deliveries from several consumers are
+    /// handled together (e.g., via a [`ConsumerSet`](struct.ConsumerSet.html)). Returns `None` for
+    /// messages fetched with [`Channel::basic_get`](struct.Channel.html#method.basic_get).
+    #[inline]
+    pub fn consumer_tag(&self) -> Option<&str> {
+        self.consumer_tag.as_ref().map(String::as_str)
+    }
+
+    #[inline]
+    pub(crate) fn channel_id(&self) -> u16 {
+        self.channel_id
+    }
+
+    /// The name of the exchange this message was originally published to. This is an empty
+    /// string for messages published to the default exchange, so it can be used to tell those
+    /// apart from messages routed through a named exchange.
+    #[inline]
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    /// The routing key specified when this message was published. For messages published to the
+    /// default exchange, this is the name of the queue they were routed to.
+    #[inline]
+    pub fn routing_key(&self) -> &str {
+        &self.routing_key
+    }
+
+    /// The content body of this message.
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Consume this delivery, returning its content body without copying it.
+    ///
+    /// Acknowledging a delivery also consumes it, so if this message must be acknowledged, use
+    /// [`body`](#method.body) instead to read it before calling [`ack`](#method.ack).
+    #[inline]
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// Iterate over the entries of this message's `headers` property, sorted by key. Yields
+    /// nothing if the message has no headers.
+    pub fn headers_iter(&self) -> impl Iterator<Item = (&str, &AMQPValue)> {
+        self.properties
+            .headers()
+            .iter()
+            .flat_map(|headers| headers.iter())
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Look up a header by name, returning the raw field value.
+    pub fn headers_get(&self, key: &str) -> Option<&AMQPValue> {
+        self.properties.headers().as_ref()?.get(key)
+    }
+
+    /// Look up a string header. Returns `None` if the header is missing or is not a string.
+    pub fn headers_get_str(&self, key: &str) -> Option<&str> {
+        match self.headers_get(key)? {
+            AMQPValue::LongString(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Look up an integer header. Any of the AMQP integer types is accepted as long as its value
+    /// fits in an `i64`; returns `None` if the header is missing or is not an integer.
+    pub fn headers_get_i64(&self, key: &str) -> Option<i64> {
+        match *self.headers_get(key)? {
+            AMQPValue::ShortShortInt(n) => Some(i64::from(n)),
+            AMQPValue::ShortShortUInt(n) => Some(i64::from(n)),
+            AMQPValue::ShortInt(n) => Some(i64::from(n)),
+            AMQPValue::ShortUInt(n) => Some(i64::from(n)),
+            AMQPValue::LongInt(n) => Some(i64::from(n)),
+            AMQPValue::LongUInt(n) => Some(i64::from(n)),
+            AMQPValue::LongLongInt(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Look up a boolean header. Returns `None` if the header is missing or is not a boolean.
+    pub fn headers_get_bool(&self, key: &str) -> Option<bool> {
+        match *self.headers_get(key)? {
+            AMQPValue::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Look up a timestamp header. Returns `None` if the header is missing, is not an AMQP
+    /// timestamp, or is too far in the future to be represented as a `SystemTime`.
+    pub fn headers_get_timestamp(&self, key: &str) -> Option<SystemTime> {
+        match *self.headers_get(key)? {
+            AMQPValue::Timestamp(seconds) => from_amqp_timestamp(seconds),
+            _ => None,
+        }
+    }
+
+    /// The message's `timestamp` property, if the publisher set one and it can be represented as
+    /// a `SystemTime`. AMQP timestamps have a resolution of one second.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.properties.timestamp().and_then(from_amqp_timestamp)
+    }
+
+    /// The number of times this message has previously failed to be processed, according to the
+    /// headers RabbitMQ adds to it.
+    ///
+    /// This is the `count` field of the most recent (first) entry in the `x-death` header, which
+    /// RabbitMQ updates each time the message is [dead-lettered](https://www.rabbitmq.com/dlx.html),
+    /// plus the `x-delivery-count` header, which [quorum
+    /// queues](https://www.rabbitmq.com/quorum-queues.html) update each time the message is
+    /// requeued. Messages requeued on classic queues are not marked by the server, so for those,
+    /// only dead-lettering is counted. Missing or malformed headers count as zero.
+    ///
+    /// Only the most recent `x-death` entry is used because RabbitMQ keeps a separate entry for
+    /// each queue (and reason) a message has been dead-lettered from. In a typical retry loop,
+    /// where rejected messages are dead-lettered to a delay queue whose expired messages are
+    /// dead-lettered back, every retry bumps the count of both entries; adding them up would
+    /// count each retry twice.
+    pub fn retry_count(&self) -> u64 {
+        let headers = match self.properties.headers() {
+            Some(headers) => headers,
+            None => return 0,
+        };
+
+        let deaths = match headers.get("x-death") {
+            Some(AMQPValue::FieldArray(deaths)) => match deaths.first() {
+                Some(AMQPValue::FieldTable(death)) => {
+                    death.get("count").and_then(as_u64).unwrap_or(0)
+                }
+                _ => 0,
+            },
+            _ => 0,
+        };
+        let deliveries = headers
+            .get("x-delivery-count")
+            .and_then(as_u64)
+            .unwrap_or(0);
+
+        deaths.saturating_add(deliveries)
+    }
+
     /// Acknowledge this delivery, which must have been received on the given channel. If
     /// `multiple` is true, acks this delivery and all other deliveries received on this channel
     /// with smaller [`delivery_tag`](#method.delivery_tag)s.
@@ -111,7 +288,10 @@ impl Delivery {
     }
 
     /// Reject this delivery, which must have been received on the given channel. If `requeue` is
-    /// true, instructs the server to attempt to requeue the message.
+    /// true, instructs the server to attempt to requeue the message. If `requeue` is false and the
+    /// queue the message was delivered from has a [dead letter
+    /// exchange](https://www.rabbitmq.com/dlx.html) configured, the server will republish the
+    /// message to that exchange; otherwise, the message is discarded.
     ///
     /// # Panics
     ///
@@ -153,7 +333,10 @@ impl Delivery {
     }
 
     /// Reject this delivery, which must have been received on the given channel. If `requeue` is
-    /// true, instructs the server to attempt to requeue the message.
+    /// true, instructs the server to attempt to requeue the message. If `requeue` is false and the
+    /// queue the message was delivered from has a [dead letter
+    /// exchange](https://www.rabbitmq.com/dlx.html) configured, the server will republish the
+    /// message to that exchange; otherwise, the message is discarded.
     ///
     /// # Panics
     ///
@@ -171,4 +354,251 @@ impl Delivery {
         );
         channel.basic_reject(self, requeue)
     }
+
+    /// Acknowledge this delivery without needing the channel it was received on.
+    ///
+    /// This is only possible for deliveries received via a [`Consumer`](struct.Consumer.html);
+    /// deliveries returned by [`Channel::basic_get`](struct.Channel.html#method.basic_get) will
+    /// return [`Error::DeliveryNotFromConsumer`](enum.Error.html#variant.DeliveryNotFromConsumer).
+    /// If the channel has since been closed, returns
+    /// [`Error::AckOnClosedChannel`](enum.Error.html#variant.AckOnClosedChannel).
+    pub fn ack_direct(self) -> Result<()> {
+        self.direct_acker()?.ack(self.delivery_tag, false)
+    }
+
+    /// Nack this delivery without needing the channel it was received on. If `requeue` is true,
+    /// instructs the server to attempt to requeue the message. See
+    /// [`reject`](#method.reject) for how the server handles messages that are not requeued.
+    ///
+    /// This is subject to the same restrictions as [`ack_direct`](#method.ack_direct).
+    pub fn nack_direct(self, requeue: bool) -> Result<()> {
+        self.direct_acker()?.nack(self.delivery_tag, false, requeue)
+    }
+
+    /// Reject this delivery without needing the channel it was received on. If `requeue` is true,
+    /// instructs the server to attempt to requeue the message. See
+    /// [`reject`](#method.reject) for how the server handles messages that are not requeued.
+    ///
+    /// This is subject to the same restrictions as [`ack_direct`](#method.ack_direct).
+    pub fn reject_direct(self, requeue: bool) -> Result<()> {
+        self.direct_acker()?.reject(self.delivery_tag, requeue)
+    }
+
+    /// A cloneable [`Acker`](struct.Acker.html) for the channel this delivery was received on,
+    /// allowing it (or other deliveries from the same channel) to be acknowledged from another
+    /// thread after this delivery has been moved or dropped. Returns `None` for deliveries that
+    /// were not received via a [`Consumer`](struct.Consumer.html).
+    pub fn acker(&self) -> Option<Acker> {
+        self.acker.clone()
+    }
+
+    fn direct_acker(&self) -> Result<&Acker> {
+        self.acker.as_ref().context(DeliveryNotFromConsumer)
+    }
+}
+
+// RabbitMQ encodes counts as signed 64-bit integers, but be lenient about other integer types.
+fn as_u64(value: &AMQPValue) -> Option<u64> {
+    match *value {
+        AMQPValue::ShortShortUInt(n) => Some(u64::from(n)),
+        AMQPValue::ShortUInt(n) => Some(u64::from(n)),
+        AMQPValue::LongUInt(n) => Some(u64::from(n)),
+        AMQPValue::ShortShortInt(n) if n >= 0 => Some(n as u64),
+        AMQPValue::ShortInt(n) if n >= 0 => Some(n as u64),
+        AMQPValue::LongInt(n) if n >= 0 => Some(n as u64),
+        AMQPValue::LongLongInt(n) if n >= 0 => Some(n as u64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FieldTable;
+
+    fn delivery_with_headers(headers: Option<FieldTable>) -> Delivery {
+        let mut properties = AmqpProperties::default();
+        if let Some(headers) = headers {
+            properties = properties.with_headers(headers);
+        }
+        let deliver = Deliver {
+            consumer_tag: "tag".to_string(),
+            delivery_tag: 1,
+            redelivered: true,
+            exchange: String::new(),
+            routing_key: "work".to_string(),
+        };
+        Delivery::new(1, deliver, Vec::new(), properties).1
+    }
+
+    fn death(queue: &str, reason: &str, count: AMQPValue) -> AMQPValue {
+        let mut death = FieldTable::new();
+        death.insert("count".to_string(), count);
+        death.insert(
+            "queue".to_string(),
+            AMQPValue::LongString(queue.to_string()),
+        );
+        death.insert(
+            "reason".to_string(),
+            AMQPValue::LongString(reason.to_string()),
+        );
+        AMQPValue::FieldTable(death)
+    }
+
+    #[test]
+    fn body() {
+        let deliver = Deliver {
+            consumer_tag: "tag".to_string(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: String::new(),
+            routing_key: "work".to_string(),
+        };
+        let body = b"hello".to_vec();
+        let body_ptr = body.as_ptr();
+        let delivery = Delivery::new(1, deliver, body, AmqpProperties::default()).1;
+        assert_eq!(delivery.body(), b"hello");
+        let body = delivery.into_body();
+        assert_eq!(body, b"hello");
+        assert_eq!(body.as_ptr(), body_ptr);
+    }
+
+    #[test]
+    fn deliver_metadata() {
+        for exchange in &["", "logs"] {
+            let deliver = Deliver {
+                consumer_tag: "tag".to_string(),
+                delivery_tag: 7,
+                redelivered: true,
+                exchange: exchange.to_string(),
+                routing_key: "db.error".to_string(),
+            };
+            let (consumer_tag, delivery) =
+                Delivery::new(1, deliver, Vec::new(), AmqpProperties::default());
+            assert_eq!(consumer_tag, "tag");
+            assert_eq!(delivery.consumer_tag(), Some("tag"));
+            assert_eq!(delivery.exchange(), *exchange);
+            assert_eq!(delivery.routing_key(), "db.error");
+            assert_eq!(delivery.delivery_tag(), 7);
+            assert!(delivery.redelivered);
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        use crate::Publish;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let sent = UNIX_EPOCH + Duration::from_millis(1_600_000_000_900);
+        let properties = Publish::new(b"", "key")
+            .timestamp(sent)
+            .timestamp_header("x-created", sent)
+            .properties;
+        let deliver = Deliver {
+            consumer_tag: "tag".to_string(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: String::new(),
+            routing_key: "key".to_string(),
+        };
+        let delivery = Delivery::new(1, deliver, Vec::new(), properties).1;
+        let expected = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(delivery.timestamp(), Some(expected));
+        assert_eq!(delivery.headers_get_timestamp("x-created"), Some(expected));
+        assert_eq!(delivery.headers_get_timestamp("x-missing"), None);
+        assert_eq!(delivery_with_headers(None).timestamp(), None);
+
+        // the broker may hand us timestamps too large for SystemTime
+        let mut headers = FieldTable::new();
+        headers.insert(
+            "x-created".to_string(),
+            AMQPValue::Timestamp(u64::max_value()),
+        );
+        let mut delivery = delivery_with_headers(Some(headers));
+        delivery.properties = delivery.properties.with_timestamp(u64::max_value());
+        assert_eq!(delivery.timestamp(), None);
+        assert_eq!(delivery.headers_get_timestamp("x-created"), None);
+    }
+
+    #[test]
+    fn retry_count() {
+        assert_eq!(delivery_with_headers(None).retry_count(), 0);
+        assert_eq!(
+            delivery_with_headers(Some(FieldTable::new())).retry_count(),
+            0
+        );
+
+        // a retry loop through a delay queue: each retry bumps both entries, so only the most
+        // recent one is counted
+        let mut headers = FieldTable::new();
+        headers.insert(
+            "x-death".to_string(),
+            AMQPValue::FieldArray(vec![
+                death("work.retry", "expired", AMQPValue::LongLongInt(3)),
+                death("work", "rejected", AMQPValue::LongLongInt(3)),
+            ]),
+        );
+        assert_eq!(
+            delivery_with_headers(Some(headers.clone())).retry_count(),
+            3
+        );
+
+        headers.insert("x-delivery-count".to_string(), AMQPValue::LongInt(4));
+        assert_eq!(delivery_with_headers(Some(headers)).retry_count(), 7);
+
+        // malformed entries and counts count as zero; a timestamp is not a count
+        for first in [
+            AMQPValue::LongString("garbage".to_string()),
+            death("work", "rejected", AMQPValue::LongString("7".to_string())),
+            death("work", "rejected", AMQPValue::Timestamp(7)),
+        ]
+        .iter()
+        {
+            let mut headers = FieldTable::new();
+            headers.insert(
+                "x-death".to_string(),
+                AMQPValue::FieldArray(vec![
+                    first.clone(),
+                    death("work", "rejected", AMQPValue::LongLongInt(3)),
+                ]),
+            );
+            assert_eq!(delivery_with_headers(Some(headers)).retry_count(), 0);
+        }
+
+        let mut headers = FieldTable::new();
+        headers.insert(
+            "x-death".to_string(),
+            AMQPValue::LongString("not an array".to_string()),
+        );
+        assert_eq!(delivery_with_headers(Some(headers)).retry_count(), 0);
+    }
+
+    #[test]
+    fn typed_headers() {
+        assert_eq!(delivery_with_headers(None).headers_iter().count(), 0);
+        assert_eq!(delivery_with_headers(None).headers_get_str("a"), None);
+
+        let mut headers = FieldTable::new();
+        headers.insert("str".to_string(), AMQPValue::LongString("x".to_string()));
+        headers.insert("int".to_string(), AMQPValue::LongInt(-7));
+        headers.insert("uint".to_string(), AMQPValue::LongUInt(7));
+        headers.insert("long".to_string(), AMQPValue::LongLongInt(1 << 40));
+        headers.insert("bool".to_string(), AMQPValue::Boolean(true));
+        let delivery = delivery_with_headers(Some(headers));
+
+        assert_eq!(delivery.headers_get_str("str"), Some("x"));
+        assert_eq!(delivery.headers_get_i64("int"), Some(-7));
+        assert_eq!(delivery.headers_get_i64("uint"), Some(7));
+        assert_eq!(delivery.headers_get_i64("long"), Some(1 << 40));
+        assert_eq!(delivery.headers_get_bool("bool"), Some(true));
+
+        // wrong types and missing keys
+        assert_eq!(delivery.headers_get_str("int"), None);
+        assert_eq!(delivery.headers_get_i64("bool"), None);
+        assert_eq!(delivery.headers_get_bool("str"), None);
+        assert_eq!(delivery.headers_get_bool("missing"), None);
+
+        let keys: Vec<&str> = delivery.headers_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["bool", "int", "long", "str", "uint"]);
+    }
 }
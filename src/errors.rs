@@ -1,3 +1,4 @@
+use amq_protocol::protocol::AMQPHardError;
 use snafu::Snafu;
 //use std::sync::Arc;
 use std::{io, result};
@@ -39,14 +40,40 @@ pub enum Error {
     #[snafu(display("I/O error while writing socket: {}", source))]
     IoErrorWritingSocket { source: io::Error },
 
+    /// Failed to apply TCP options (e.g., `TCP_NODELAY`) to the socket.
+    #[snafu(display("failed to configure TCP socket: {}", source))]
+    ConfigureTcpStream { source: io::Error },
+
+    /// The server did not respond with an AMQP 0-9-1 frame. If `received` begins with `AMQP`, the
+    /// server does not support AMQP 0-9-1 and `received` is the protocol header it sent back
+    /// describing the version it does support; otherwise, the server is likely not an AMQP
+    /// server at all.
+    #[snafu(display(
+        "server does not support AMQP 0-9-1 (received {:?})",
+        String::from_utf8_lossy(received)
+    ))]
+    ProtocolVersionMismatch { received: Vec<u8> },
+
     /// We received data that could not be parsed as an AMQP frame.
     #[snafu(display("received malformed data - expected AMQP frame"))]
     MalformedFrame,
 
+    /// We received an AMQP frame with a valid header whose contents could not be decoded. The
+    /// I/O thread will send a `FRAME_ERROR` connection close to the server before exiting.
+    #[snafu(display("could not decode frame (type={} channel={})", frame_type, channel_id))]
+    FrameDecode { frame_type: u8, channel_id: u16 },
+
     /// Failed to resolve a URL into an IP address (or addresses).
     #[snafu(display("URL did not resolve to an IP address: {}", url))]
     UrlNoSocketAddrs { url: Url },
 
+    /// None of the URLs passed to
+    /// [`Connection::insecure_open_cluster_tuned`](struct.Connection.html#method.insecure_open_cluster_tuned)
+    /// (or a related method) could be connected to. `errors` contains each URL tried and the
+    /// error encountered, in the order they were tried.
+    #[snafu(display("failed to connect to any of {} broker URLs", errors.len()))]
+    ClusterConnectFailed { errors: Vec<(String, Error)> },
+
     /// Error resolving a URL into an IP address (or addresses).
     #[snafu(display("failed to resolve IP address of {}: {}", url, source))]
     ResolveUrlToSocketAddr { url: Url, source: io::Error },
@@ -55,6 +82,18 @@ pub enum Error {
     #[snafu(display("failed to connect to {}: {}", url, source))]
     FailedToConnect { url: Url, source: io::Error },
 
+    /// Error resolving an address passed to
+    /// [`Connection::open_tls_with_config`](struct.Connection.html#method.open_tls_with_config)
+    /// into an IP address (or addresses).
+    #[snafu(display("failed to resolve IP address of {}: {}", addr, source))]
+    ResolveAddr { addr: String, source: io::Error },
+
+    /// Failed to open a TCP connection to any of the IP addresses an address resolved to. If the
+    /// address resolved to more than one IP address, `source` is the error from the last one
+    /// tried.
+    #[snafu(display("failed to connect to {}: {}", addr, source))]
+    FailedToConnectAddr { addr: String, source: io::Error },
+
     /// Failed to set the port on a URL.
     #[snafu(display("cannot specify port for URL {}", url))]
     SpecifyUrlPort { url: Url },
@@ -85,6 +124,13 @@ pub enum Error {
         source: std::num::ParseIntError,
     },
 
+    /// Could not parse frame_max parameter of URL.
+    #[snafu(display("could not parse frame_max parameter of URL {}: {}", url, source))]
+    UrlParseFrameMax {
+        url: Url,
+        source: std::num::ParseIntError,
+    },
+
     /// Could not parse connection_timeout parameter of URL.
     #[snafu(display(
         "could not parse connection_timeout parameter of URL {}: {}",
@@ -164,8 +210,14 @@ pub enum Error {
     ))]
     FrameMaxTooSmall { min: u32, requested: u32 },
 
-    /// Timeout occurred while performing the initial TCP connection.
-    #[snafu(display("timeout occurred while waiting for TCP connection"))]
+    /// A [`ConnectionOptions`](struct.ConnectionOptions.html) setting is outside the range allowed
+    /// by the AMQP spec or otherwise cannot be used. This is detected before connecting.
+    #[snafu(display("invalid connection option {}: {}", field, detail))]
+    InvalidOptions { field: &'static str, detail: String },
+
+    /// The connection could not be established within the
+    /// [connection timeout](struct.ConnectionOptions.html#method.connection_timeout).
+    #[snafu(display("timeout occurred while establishing connection"))]
     ConnectionTimeout,
 
     /// The server requested a Secure/Secure-Ok exchange, which are currently unsupported.
@@ -176,6 +228,16 @@ pub enum Error {
     #[snafu(display("invalid credentials"))]
     InvalidCredentials,
 
+    /// The server refused access to the requested virtual host during the connection handshake,
+    /// either because it does not exist or because the user does not have permission to access
+    /// it.
+    #[snafu(display(
+        "server refused access to virtual host (code={} message={})",
+        code,
+        message
+    ))]
+    AccessRefused { code: u16, message: String },
+
     /// The server missed too many successive heartbeats.
     #[snafu(display("missed heartbeats from server"))]
     MissedServerHeartbeats,
@@ -205,6 +267,13 @@ pub enum Error {
     #[snafu(display("channel has been closed"))]
     ClientClosedChannel,
 
+    /// A nonblocking send (e.g.,
+    /// [`Channel::try_basic_publish`](struct.Channel.html#method.try_basic_publish)) failed
+    /// because the I/O thread's inbound queue for this channel is full. See
+    /// [`ConnectionTuning`](struct.ConnectionTuning.html) for how to configure this queue's bound.
+    #[snafu(display("outbound queue for channel {} is full", channel_id))]
+    OutboundFull { channel_id: u16 },
+
     /// The I/O loop attempted to send a message to a caller that did not exist. This
     /// indicates either a bug in amiquip or a connection that is in a bad state and in the process
     /// of tearing down.
@@ -270,14 +339,232 @@ pub enum Error {
         consumer_tag: String,
     },
 
+    /// Attempted to ack, nack, or reject a [`Delivery`](struct.Delivery.html) directly, but the
+    /// delivery was not received via a [`Consumer`](struct.Consumer.html).
+    #[snafu(display("delivery was not received via a consumer and cannot be acked directly"))]
+    DeliveryNotFromConsumer,
+
+    /// Attempted to ack, nack, or reject a [`Delivery`](struct.Delivery.html) directly after the
+    /// channel it was received on was closed.
+    #[snafu(display("cannot ack delivery - channel {} is closed", channel_id))]
+    AckOnClosedChannel { channel_id: u16 },
+
+    /// Attempted to ack or nack deliveries on a channel other than the one they were received on.
+    /// Delivery tags are channel-specific, so the server would apply the request to unrelated
+    /// messages (or close the channel).
+    #[snafu(display(
+        "delivery was received on channel {} but was used on channel {}",
+        delivery_channel_id,
+        channel_id
+    ))]
+    DeliveryChannelMismatch {
+        delivery_channel_id: u16,
+        channel_id: u16,
+    },
+
+    /// A nowait consumer was requested without specifying a consumer tag.
+    #[snafu(display("a consumer tag must be specified to start a nowait consumer"))]
+    ConsumerTagRequired,
+
+    /// An exchange could not be declared because it already exists with a different type. The
+    /// server closes the channel when this happens.
+    #[snafu(display(
+        "exchange {} already exists with type {} (tried to declare as {})",
+        exchange,
+        actual,
+        expected
+    ))]
+    ExchangeTypeMismatch {
+        exchange: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// An [`RpcClient`](struct.RpcClient.html) did not receive a reply to a request before its
+    /// timeout expired.
+    #[snafu(display("timed out waiting for RPC reply (correlation id {})", correlation_id))]
+    RpcTimeout { correlation_id: String },
+
+    /// The consumer on an [`RpcClient`](struct.RpcClient.html)'s reply queue was cancelled, so no
+    /// further replies can be received.
+    #[snafu(display("RPC reply consumer was cancelled"))]
+    RpcReplyConsumerEnded,
+
+    /// A consumer could not be started because another consumer has exclusive access to the queue
+    /// (or, when requesting exclusive access, because the queue already has consumers). The
+    /// server closes the channel when this happens.
+    #[snafu(display("queue {} is in exclusive use by another consumer", queue))]
+    ConsumerExclusiveConflict { queue: String },
+
+    /// A queue could not be declared because the requested queue type (e.g., `quorum`) does not
+    /// support one of the other requested options. The server would close the channel if such a
+    /// declaration were sent.
+    #[snafu(display("{} queues cannot be declared {}", queue_type, option))]
+    IncompatibleQueueOptions {
+        queue_type: String,
+        option: &'static str,
+    },
+
+    /// A message was published directly to a queue without naming the queue. Publishing to the
+    /// default exchange with an empty routing key would silently drop the message.
+    #[snafu(display("a queue name must be specified to publish directly to a queue"))]
+    QueueNameRequired,
+
+    /// A value could not be serialized to JSON for publishing (requires the `serde` feature).
+    #[snafu(display("could not serialize message body: {}", source))]
+    Serialize {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// A message body could not be deserialized from JSON (requires the `serde` feature).
+    #[snafu(display("could not deserialize message body: {}", source))]
+    Deserialize {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// A message body could not be compressed or decompressed (requires the `gzip` feature).
+    #[snafu(display("could not compress or decompress message body: {}", source))]
+    ContentEncoding { source: io::Error },
+
+    /// A message body is encoded with a `content_encoding` amiquip cannot decode (requires the
+    /// `gzip` feature).
+    #[snafu(display("unsupported content encoding: {}", encoding))]
+    UnsupportedContentEncoding { encoding: String },
+
+    /// A compressed message body decompressed to more than the allowed number of bytes
+    /// (requires the `gzip` feature).
+    #[snafu(display("decompressed message body is larger than {} bytes", max_len))]
+    DecodedBodyTooLarge { max_len: usize },
+
+    /// [`Channel::basic_publish_tracked`](struct.Channel.html#method.basic_publish_tracked) was
+    /// called on a channel that does not have publisher confirms enabled.
+    ///
+    /// This is also returned by
+    /// [`Channel::wait_for_confirm`](struct.Channel.html#method.wait_for_confirm) on such a
+    /// channel.
+    #[snafu(display("publisher confirms are not enabled on this channel"))]
+    PublisherConfirmsNotEnabled,
+
+    /// [`Channel::wait_for_confirm`](struct.Channel.html#method.wait_for_confirm) was given a
+    /// delivery tag that is not being tracked on this channel, either because it was not returned
+    /// by [`Channel::basic_publish_tracked`](struct.Channel.html#method.basic_publish_tracked) or
+    /// because its confirmation has already been returned.
+    #[snafu(display("delivery tag {} is not being tracked for confirmation", delivery_tag))]
+    UntrackedDeliveryTag { delivery_tag: u64 },
+
+    /// Timed out in [`Channel::wait_for_confirm`](struct.Channel.html#method.wait_for_confirm)
+    /// before the server confirmed the message.
+    #[snafu(display("timed out waiting for confirmation of delivery tag {}", delivery_tag))]
+    ConfirmTimeout { delivery_tag: u64 },
+
+    /// A message published via
+    /// [`Channel::publish_confirmed_routable`](struct.Channel.html#method.publish_confirmed_routable)
+    /// could not be routed to any queue, so the server returned it.
+    #[snafu(display("message was returned as unroutable: {} {}", reply_code, reply_text))]
+    Unroutable { reply_code: u16, reply_text: String },
+
+    /// A message published via
+    /// [`Channel::publish_confirmed_routable`](struct.Channel.html#method.publish_confirmed_routable)
+    /// was nacked by the server.
+    #[snafu(display("server nacked delivery tag {}", delivery_tag))]
+    Nacked { delivery_tag: u64 },
+
+    /// The server sent a frame larger than the negotiated `frame_max`. The I/O thread will send a
+    /// `FRAME_ERROR` connection close to the server before exiting.
+    #[snafu(display(
+        "received frame of {} bytes, larger than negotiated frame_max {}",
+        size,
+        frame_max
+    ))]
+    FrameTooLarge { size: usize, frame_max: usize },
+
+    /// The server cancelled a consumer (e.g., because its queue was deleted). Returned by
+    /// [`Consumer::next_delivery`](struct.Consumer.html#method.next_delivery).
+    #[snafu(display("server cancelled consumer {}", consumer_tag))]
+    ServerCancelledConsumer { consumer_tag: String },
+
+    /// A segment passed to [`RoutingKey`](struct.RoutingKey.html) was empty or contained `.`,
+    /// `*`, or `#`.
+    #[snafu(display("invalid routing key segment {:?}", segment))]
+    InvalidRoutingKeySegment { segment: String },
+
+    /// A [`RoutingKey`](struct.RoutingKey.html) would be longer than the 255 bytes AMQP allows.
+    #[snafu(display("routing key of {} bytes exceeds maximum of 255", len))]
+    RoutingKeyTooLong { len: usize },
+
+    /// An operation was attempted on the default exchange (named `""`) or an exchange in the
+    /// reserved `amq.` namespace that the server would refuse with `ACCESS_REFUSED`, closing the
+    /// channel. Nothing was sent to the server.
+    #[snafu(display("cannot {} reserved exchange {:?}", operation, exchange))]
+    ReservedExchange {
+        exchange: String,
+        operation: &'static str,
+    },
+
+    /// The I/O thread closed the connection because `count` consecutive waits on the server
+    /// timed out, exceeding
+    /// [`ConnectionTuning::max_consecutive_timeouts`](struct.ConnectionTuning.html#structfield.max_consecutive_timeouts).
+    #[snafu(display("closed connection after {} consecutive timeouts", count))]
+    ConsecutiveTimeouts { count: u32 },
+
+    /// The channel reopened itself while running
+    /// [`Channel::batch`](struct.Channel.html#method.batch), so frames held back by the batch
+    /// before the server closed the channel were discarded.
+    #[snafu(display("channel was reopened (as channel {}) during a batch", channel_id))]
+    BatchInterrupted { channel_id: u16 },
+
     #[doc(hidden)]
     __Nonexhaustive,
 }
 
+impl Error {
+    /// Returns true if this error indicates the connection was lost because heartbeats were
+    /// missed, either because we stopped hearing from the server
+    /// ([`MissedServerHeartbeats`](#variant.MissedServerHeartbeats)) or because the server closed
+    /// the connection with an `INTERNAL_ERROR` (541) citing missed heartbeats from us.
+    ///
+    /// A heartbeat timeout on our side usually means the thread driving the connection was
+    /// starved. Note that some servers (including RabbitMQ) drop the socket without sending
+    /// `connection.close` when client heartbeats are missed; that surfaces as
+    /// [`UnexpectedSocketClose`](#variant.UnexpectedSocketClose) and cannot be distinguished from
+    /// other disconnects.
+    pub fn is_heartbeat_timeout(&self) -> bool {
+        match self {
+            Error::MissedServerHeartbeats => true,
+            Error::ServerClosedConnection { code, message } => {
+                *code == AMQPHardError::INTERNALERROR.get_id()
+                    && message.to_lowercase().contains("heartbeat")
+            }
+            _ => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn heartbeat_timeout() {
+        assert!(Error::MissedServerHeartbeats.is_heartbeat_timeout());
+        assert!(Error::ServerClosedConnection {
+            code: 541,
+            message: "INTERNAL_ERROR - missed heartbeats from client".to_string(),
+        }
+        .is_heartbeat_timeout());
+        assert!(!Error::ServerClosedConnection {
+            code: 541,
+            message: "INTERNAL_ERROR".to_string(),
+        }
+        .is_heartbeat_timeout());
+        assert!(!Error::ServerClosedConnection {
+            code: 320,
+            message: "CONNECTION_FORCED - heartbeat".to_string(),
+        }
+        .is_heartbeat_timeout());
+        assert!(!Error::UnexpectedSocketClose.is_heartbeat_timeout());
+    }
+
     #[test]
     fn our_error_impls_std_error() {
         fn is_err<T: std::error::Error>() {}
@@ -0,0 +1,216 @@
+use crate::{AmqpValue, FieldTable};
+
+/// Builder for a [`FieldTable`](type.FieldTable.html), including tables and arrays nested inside
+/// it.
+///
+/// Arguments for queues, exchanges, bindings, and consumers, as well as message headers, are all
+/// field tables. Most are flat, but some (e.g., federation and shovel parameters, or `x-death`
+/// style headers) contain nested tables and arrays, which are awkward to build by hand. Each
+/// value set through this builder is encoded with the AMQP field type named by the method used;
+/// [`value`](#method.value) can be used for any other type.
+///
+/// # Example
+///
+/// ```rust
+/// # use amiquip::{FieldTableBuilder, QueueDeclareOptions};
+/// let upstream = FieldTableBuilder::new()
+///     .string("uri", "amqp://upstream")
+///     .long("expires", 3_600_000);
+/// let options = QueueDeclareOptions {
+///     arguments: FieldTableBuilder::new()
+///         .table("x-upstream", upstream)
+///         .array(
+///             "x-tags",
+///             vec![
+///                 FieldTableBuilder::new().string("name", "a").into_value(),
+///                 FieldTableBuilder::new().string("name", "b").into_value(),
+///             ],
+///         )
+///         .build(),
+///     ..QueueDeclareOptions::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldTableBuilder {
+    table: FieldTable,
+}
+
+impl FieldTableBuilder {
+    /// Create an empty table.
+    pub fn new() -> FieldTableBuilder {
+        FieldTableBuilder::default()
+    }
+
+    /// Set `key` to a string, encoded as an AMQP long string.
+    pub fn string<K: Into<String>, V: Into<String>>(self, key: K, value: V) -> FieldTableBuilder {
+        self.value(key, AmqpValue::LongString(value.into()))
+    }
+
+    /// Set `key` to a boolean.
+    pub fn bool<K: Into<String>>(self, key: K, value: bool) -> FieldTableBuilder {
+        self.value(key, AmqpValue::Boolean(value))
+    }
+
+    /// Set `key` to a signed 32-bit integer.
+    pub fn int<K: Into<String>>(self, key: K, value: i32) -> FieldTableBuilder {
+        self.value(key, AmqpValue::LongInt(value))
+    }
+
+    /// Set `key` to a signed 64-bit integer. RabbitMQ expects this type for most numeric
+    /// arguments (e.g., `x-message-ttl` or `x-max-length`).
+    pub fn long<K: Into<String>>(self, key: K, value: i64) -> FieldTableBuilder {
+        self.value(key, AmqpValue::LongLongInt(value))
+    }
+
+    /// Set `key` to a nested table.
+    pub fn table<K: Into<String>>(self, key: K, table: FieldTableBuilder) -> FieldTableBuilder {
+        self.value(key, table.into_value())
+    }
+
+    /// Set `key` to an array of values. Use [`into_value`](#method.into_value) to include
+    /// tables in the array.
+    pub fn array<K, I>(self, key: K, values: I) -> FieldTableBuilder
+    where
+        K: Into<String>,
+        I: IntoIterator<Item = AmqpValue>,
+    {
+        self.value(key, AmqpValue::FieldArray(values.into_iter().collect()))
+    }
+
+    /// Set `key` to an arbitrary value, replacing any previous value for `key`.
+    pub fn value<K: Into<String>>(mut self, key: K, value: AmqpValue) -> FieldTableBuilder {
+        self.table.insert(key.into(), value);
+        self
+    }
+
+    /// Convert this builder into a table value, for use as an element of an
+    /// [`array`](#method.array) or as a message header.
+    pub fn into_value(self) -> AmqpValue {
+        AmqpValue::FieldTable(self.table)
+    }
+
+    /// Finish building the table.
+    pub fn build(self) -> FieldTable {
+        self.table
+    }
+}
+
+impl From<FieldTableBuilder> for FieldTable {
+    fn from(builder: FieldTableBuilder) -> FieldTable {
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::OutputBuffer;
+    use amq_protocol::frame::{parse_frame, AMQPFrame};
+    use amq_protocol::protocol::queue::{AMQPMethod as AmqpQueue, Declare};
+    use amq_protocol::protocol::AMQPClass;
+
+    fn nested() -> FieldTable {
+        FieldTableBuilder::new()
+            .string("x-queue-mode", "lazy")
+            .long("x-max-length", 1_000)
+            .table(
+                "x-upstream",
+                FieldTableBuilder::new()
+                    .string("uri", "amqp://upstream")
+                    .bool("trust-user-id", false)
+                    .table("inner", FieldTableBuilder::new().int("depth", 2)),
+            )
+            .array(
+                "x-upstreams",
+                vec![
+                    FieldTableBuilder::new().string("name", "a").into_value(),
+                    AmqpValue::FieldArray(vec![AmqpValue::LongInt(1), AmqpValue::LongInt(2)]),
+                    AmqpValue::LongString("b".to_string()),
+                ],
+            )
+            .build()
+    }
+
+    #[test]
+    fn builds_nested_tables() {
+        let table = nested();
+        match &table["x-upstream"] {
+            AmqpValue::FieldTable(upstream) => match &upstream["inner"] {
+                AmqpValue::FieldTable(inner) => {
+                    assert_eq!(inner["depth"], AmqpValue::LongInt(2));
+                }
+                other => panic!("unexpected value {:?}", other),
+            },
+            other => panic!("unexpected value {:?}", other),
+        }
+        match &table["x-upstreams"] {
+            AmqpValue::FieldArray(values) => assert_eq!(values.len(), 3),
+            other => panic!("unexpected value {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_tables_round_trip() {
+        let arguments = nested();
+        let mut buf = OutputBuffer::empty();
+        buf.push_method(
+            1,
+            AmqpQueue::Declare(Declare {
+                ticket: 0,
+                queue: "nested".to_string(),
+                passive: false,
+                durable: false,
+                exclusive: false,
+                auto_delete: false,
+                nowait: false,
+                arguments: arguments.clone(),
+            }),
+        );
+        match parse_frame(&buf[0..]) {
+            Ok((_, AMQPFrame::Method(1, AMQPClass::Queue(AmqpQueue::Declare(declare))))) => {
+                assert_eq!(declare.arguments, arguments);
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock-broker"))]
+mod mock_broker_tests {
+    use super::*;
+    use crate::mock_broker::with_mock_chan;
+    use crate::{Error, QueueDeclareOptions};
+    use amq_protocol::protocol::AMQPSoftError;
+
+    #[test]
+    fn nested_queue_arguments() {
+        with_mock_chan(|channel| {
+            let arguments = |uri: &str| QueueDeclareOptions {
+                arguments: FieldTableBuilder::new()
+                    .table(
+                        "x-upstream",
+                        FieldTableBuilder::new()
+                            .string("uri", uri)
+                            .array("tags", vec![AmqpValue::LongInt(1)]),
+                    )
+                    .build(),
+                ..QueueDeclareOptions::default()
+            };
+            channel
+                .queue_declare("nested", arguments("amqp://a"))
+                .unwrap();
+            // the broker only accepts a redeclare if the arguments it decodes are identical
+            channel
+                .queue_declare("nested", arguments("amqp://a"))
+                .unwrap();
+            channel.queue_declare_passive("nested").unwrap();
+            match channel.queue_declare("nested", arguments("amqp://b")) {
+                Err(Error::ServerClosedChannel { code, message, .. }) => {
+                    assert_eq!(code, AMQPSoftError::PRECONDITIONFAILED.get_id());
+                    assert!(message.contains("'x-upstream'"), "{}", message);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        })
+    }
+}
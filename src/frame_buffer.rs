@@ -1,9 +1,9 @@
 use crate::errors::*;
+use crate::logging::trace;
 use amq_protocol::frame::{parse_frame, AMQPFrame};
 use amq_protocol::types::parsing::parse_long_uint;
 use bytes::Buf;
 use input_buffer::{InputBuffer, MIN_READ};
-use log::trace;
 use snafu::ResultExt;
 use std::io;
 use std::marker::PhantomData;
@@ -15,6 +15,12 @@ impl FrameBuffer {
         FrameBuffer(Inner::new())
     }
 
+    // Reject frames larger than `frame_max` bytes (including the frame header and end
+    // marker) instead of buffering them. There is no limit until this is called.
+    pub fn set_frame_max(&mut self, frame_max: usize) {
+        self.0.frame_max = frame_max;
+    }
+
     pub fn read_from<S, F>(&mut self, stream: &mut S, handler: F) -> Result<usize>
     where
         S: io::Read,
@@ -43,12 +49,36 @@ enum AmqpFrameKind {}
 impl AmqpFrameKind {
     // position (from start of frame) where the "size of frame" bytes are located
     const AMQP_FRAME_SIZE_POS: std::ops::Range<usize> = 3..7;
+
+    // length of the protocol header a server sends if it does not support the version we
+    // requested (e.g., "AMQP\x00\x00\x09\x01")
+    const PROTOCOL_HEADER_LEN: usize = 8;
+
+    // The first byte of every AMQP frame is its type: method (1), content header (2),
+    // content body (3), or heartbeat (8). Anything else means the peer is not speaking
+    // AMQP 0-9-1 to us; most commonly, it's a server rejecting our protocol header by
+    // sending back its own, but it could also be something else entirely (e.g., an HTTP
+    // server).
+    fn is_frame_type(b: u8) -> bool {
+        match b {
+            1 | 2 | 3 | 8 => true,
+            _ => false,
+        }
+    }
 }
 
 impl FrameKind for AmqpFrameKind {
     type Frame = AMQPFrame;
 
     fn parse_size(buf: &[u8]) -> Option<usize> {
+        // If this isn't an AMQP frame, don't trust the size bytes (which could ask us to
+        // buffer gigabytes of data); instead, ask for enough data to report back what the
+        // server sent, and let parse_frame() fail.
+        if let Some(&b) = buf.first() {
+            if !Self::is_frame_type(b) {
+                return Some(Self::PROTOCOL_HEADER_LEN);
+            }
+        }
         if buf.len() < Self::AMQP_FRAME_SIZE_POS.end {
             None
         } else {
@@ -62,6 +92,13 @@ impl FrameKind for AmqpFrameKind {
     }
 
     fn parse_frame(buf: &[u8]) -> Result<AMQPFrame> {
+        if !Self::is_frame_type(buf[0]) {
+            return ProtocolVersionMismatch {
+                received: buf.to_vec(),
+            }
+            .fail();
+        }
+
         // parse is only successful if there were no errors _and_ it consumed
         // all of `buf` (Inner calls us with exactly the size of `buf` we said
         // we need from parse_size()).
@@ -70,12 +107,20 @@ impl FrameKind for AmqpFrameKind {
                 return Ok(frame);
             }
         }
-        MalformedFrame.fail()
+
+        // parse_size() guarantees we have at least the 7 byte frame header: a 1 byte
+        // frame type, a 2 byte channel id, and the 4 byte size.
+        FrameDecode {
+            frame_type: buf[0],
+            channel_id: u16::from_be_bytes([buf[1], buf[2]]),
+        }
+        .fail()
     }
 }
 
 struct Inner<Kind: FrameKind> {
     buf: InputBuffer,
+    frame_max: usize,
     phantom: PhantomData<Kind>,
 }
 
@@ -83,6 +128,7 @@ impl<Kind: FrameKind> Inner<Kind> {
     fn new() -> Inner<Kind> {
         Inner {
             buf: InputBuffer::new(),
+            frame_max: usize::max_value(),
             phantom: PhantomData,
         }
     }
@@ -102,6 +148,13 @@ impl<Kind: FrameKind> Inner<Kind> {
             // if we already have enough data buffered to read a frame, do that before
             // trying to read from the stream.
             if let Some(frame_size) = frame_size {
+                if frame_size > self.frame_max {
+                    return FrameTooLarge {
+                        size: frame_size,
+                        frame_max: self.frame_max,
+                    }
+                    .fail();
+                }
                 if bytes.len() >= frame_size {
                     let frame = Kind::parse_frame(&bytes[..frame_size])?;
                     handler(frame)?;
@@ -132,7 +185,7 @@ impl<Kind: FrameKind> Inner<Kind> {
 
 #[cfg(test)]
 mod tests {
-    use super::{FrameKind, Inner, Result};
+    use super::{AmqpFrameKind, FrameKind, Inner, Result};
     use crate::errors::*;
     use mockstream::FailingMockStream;
     use std::io::{self, Cursor, Read};
@@ -273,6 +326,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn frame_too_large() {
+        let mut c = Cursor::new(b"a\x04aab\x05bbb").chain(would_block());
+
+        let mut got = Vec::new();
+        let mut buf = make_buffer();
+        buf.frame_max = 4;
+        let res = buf.read_from(&mut c, |frame| {
+            got.push(frame);
+            Ok(())
+        });
+        assert_eq!(got, vec![b"a\x04aa".to_vec()]);
+        match res.unwrap_err() {
+            Error::FrameTooLarge {
+                size: 5,
+                frame_max: 4,
+            } => (),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
     #[test]
     fn callback_fail() {
         let mut c = Cursor::new(b"a\x04aa").chain(would_block());
@@ -316,4 +390,53 @@ mod tests {
             err => panic!("unexpected error {}", err),
         }
     }
+
+    #[test]
+    fn protocol_header_mismatch() {
+        let header = b"AMQP\x00\x00\x09\x01";
+        let mut c = Cursor::new(header).chain(would_block());
+
+        let mut buf = Inner::<AmqpFrameKind>::new();
+        let res = buf.read_from(&mut c, |_| panic!("should not be called"));
+        match res.unwrap_err() {
+            Error::ProtocolVersionMismatch { received } => assert_eq!(received, &header[..]),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn non_amqp_response() {
+        let response = b"HTTP/1.1 400 Bad Request\r\n\r\n";
+        let mut c = Cursor::new(&response[..]).chain(would_block());
+
+        let mut buf = Inner::<AmqpFrameKind>::new();
+        let res = buf.read_from(&mut c, |_| panic!("should not be called"));
+        match res.unwrap_err() {
+            Error::ProtocolVersionMismatch { received } => {
+                assert_eq!(received, &response[..8])
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn frame_decode_fail() {
+        // method frame on channel 3 with a 4 byte payload that is not a valid method
+        // and an invalid frame-end byte
+        let frame = b"\x01\x00\x03\x00\x00\x00\x04abcd\x00";
+        let mut c = Cursor::new(frame).chain(would_block());
+
+        let mut buf = Inner::<AmqpFrameKind>::new();
+        let res = buf.read_from(&mut c, |_| panic!("should not be called"));
+        match res.unwrap_err() {
+            Error::FrameDecode {
+                frame_type,
+                channel_id,
+            } => {
+                assert_eq!(frame_type, 1);
+                assert_eq!(channel_id, 3);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use amq_protocol::frame::{parse_frame, AMQPFrame};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The direction of a frame passed to a
+/// [frame observer](struct.Connection.html#method.set_frame_observer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDirection {
+    /// The frame was received from the server.
+    Inbound,
+
+    /// The frame is being sent to the server.
+    Outbound,
+}
+
+type Observer = Box<dyn Fn(FrameDirection, &AMQPFrame) + Send + Sync>;
+
+// Shared between a Connection and its I/O thread. The flag lets the I/O thread skip locking (and,
+// for outgoing data, reparsing frames) entirely when no observer is installed.
+#[derive(Default)]
+pub(crate) struct FrameObserver {
+    installed: AtomicBool,
+    observer: Mutex<Option<Observer>>,
+}
+
+impl FrameObserver {
+    // Length of the protocol header we send before any frames.
+    const PROTOCOL_HEADER_LEN: usize = 8;
+
+    fn lock(&self) -> MutexGuard<Option<Observer>> {
+        self.observer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn set(&self, observer: Option<Observer>) {
+        let mut slot = self.lock();
+        self.installed.store(observer.is_some(), Ordering::SeqCst);
+        *slot = observer;
+    }
+
+    #[inline]
+    pub(crate) fn is_installed(&self) -> bool {
+        self.installed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn observe(&self, direction: FrameDirection, frame: &AMQPFrame) {
+        if let Some(observer) = &*self.lock() {
+            observer(direction, frame);
+        }
+    }
+
+    // Observe every frame serialized in `buf`, which must start on a frame boundary (or with the
+    // protocol header). Returns the number of bytes consumed; parsing stops early only if `buf`
+    // contains something other than whole frames, which would be a bug in our serialization.
+    pub(crate) fn observe_outgoing(&self, buf: &[u8]) -> usize {
+        let mut rest = buf;
+        if rest.starts_with(b"AMQP") && rest.len() >= Self::PROTOCOL_HEADER_LEN {
+            rest = &rest[Self::PROTOCOL_HEADER_LEN..];
+        }
+        while !rest.is_empty() {
+            match parse_frame(rest) {
+                Ok((remaining, frame)) => {
+                    self.observe(FrameDirection::Outbound, &frame);
+                    rest = remaining;
+                }
+                Err(_) => break,
+            }
+        }
+        buf.len() - rest.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::OutputBuffer;
+    use std::sync::Arc;
+
+    #[test]
+    fn observe_outgoing() {
+        let observer = FrameObserver::default();
+        assert!(!observer.is_installed());
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen2 = Arc::clone(&seen);
+        observer.set(Some(Box::new(move |direction, frame| {
+            let is_heartbeat = match frame {
+                AMQPFrame::Heartbeat(_) => true,
+                _ => false,
+            };
+            seen2.lock().unwrap().push((direction, is_heartbeat));
+        })));
+        assert!(observer.is_installed());
+
+        let mut buf = OutputBuffer::with_protocol_header();
+        buf.push_heartbeat();
+        buf.push_heartbeat();
+        assert_eq!(observer.observe_outgoing(&buf[0..]), buf.len());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (FrameDirection::Outbound, true),
+                (FrameDirection::Outbound, true)
+            ]
+        );
+
+        observer.set(None);
+        assert!(!observer.is_installed());
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{Channel, Delivery, Result};
+use crossbeam_channel::Receiver;
+use std::time::Duration;
 
 /// A message delivered in response to a [`get`](struct.Queue.html#method.get) request.
 #[derive(Clone, Debug)]
@@ -6,11 +8,24 @@ pub struct Get {
     /// The message.
     pub delivery: Delivery,
 
-    /// The number of messages present in the queue at the time the get was serviced.
+    /// The number of messages remaining in the queue at the time the get was serviced, not
+    /// counting this one.
     pub message_count: u32,
 }
 
 impl Get {
+    /// Calls [`Delivery::body`](struct.Delivery.html#method.body) on `self.delivery`.
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        self.delivery.body()
+    }
+
+    /// Calls [`Delivery::into_body`](struct.Delivery.html#method.into_body) on `self.delivery`.
+    #[inline]
+    pub fn into_body(self) -> Vec<u8> {
+        self.delivery.into_body()
+    }
+
     /// Calls [`Delivery::ack`](struct.Delivery.html#method.ack) on `self.delivery`.
     #[inline]
     pub fn ack(self, channel: &Channel) -> Result<()> {
@@ -43,3 +58,217 @@ impl Get {
         self.delivery.reject(channel, requeue)
     }
 }
+
+/// A message delivered in response to a
+/// [`get_streaming`](struct.Queue.html#method.get_streaming) request, whose body is handed over
+/// in chunks as it arrives from the server instead of being buffered in full.
+///
+/// `delivery` carries the message's properties and delivery tag but has an empty body; read the
+/// body from [`receiver`](#method.receiver), which yields each chunk in order and disconnects
+/// after the last one. If the channel or connection closes before the whole body has arrived, the
+/// receiver disconnects early; compare the number of bytes received against `body_size` to detect
+/// a truncated body.
+#[derive(Debug)]
+pub struct StreamingGet {
+    /// The message, with an empty body.
+    pub delivery: Delivery,
+
+    /// The number of messages remaining in the queue at the time the get was serviced, not
+    /// counting this one.
+    pub message_count: u32,
+
+    /// The total size of the message body in bytes.
+    pub body_size: u64,
+
+    body: Receiver<Vec<u8>>,
+}
+
+impl StreamingGet {
+    pub(crate) fn new(get: Get, body_size: u64, body: Receiver<Vec<u8>>) -> StreamingGet {
+        StreamingGet {
+            delivery: get.delivery,
+            message_count: get.message_count,
+            body_size,
+            body,
+        }
+    }
+
+    /// The crossbeam channel receiver on which body chunks are delivered. It is already
+    /// disconnected if the message has an empty body.
+    #[inline]
+    pub fn receiver(&self) -> &Receiver<Vec<u8>> {
+        &self.body
+    }
+
+    /// Block until the rest of the body has arrived and return it as a [`Get`](struct.Get.html)
+    /// with the body filled in. Returns `None` if the body was truncated because the channel or
+    /// connection closed first.
+    pub fn into_get(self) -> Option<Get> {
+        let mut buf = Vec::new();
+        for mut chunk in self.body.iter() {
+            buf.append(&mut chunk);
+        }
+        if buf.len() as u64 != self.body_size {
+            return None;
+        }
+        let mut delivery = self.delivery;
+        delivery.body = buf;
+        Some(Get {
+            delivery,
+            message_count: self.message_count,
+        })
+    }
+
+    /// Calls [`Delivery::ack`](struct.Delivery.html#method.ack) on `self.delivery`.
+    #[inline]
+    pub fn ack(self, channel: &Channel) -> Result<()> {
+        self.delivery.ack(channel)
+    }
+
+    /// Calls [`Delivery::nack`](struct.Delivery.html#method.nack) on `self.delivery`.
+    #[inline]
+    pub fn nack(self, channel: &Channel, requeue: bool) -> Result<()> {
+        self.delivery.nack(channel, requeue)
+    }
+
+    /// Calls [`Delivery::reject`](struct.Delivery.html#method.reject) on `self.delivery`.
+    #[inline]
+    pub fn reject(self, channel: &Channel, requeue: bool) -> Result<()> {
+        self.delivery.reject(channel, requeue)
+    }
+}
+
+/// Backoff schedule used by [`Queue::get_with_backoff`](struct.Queue.html#method.get_with_backoff)
+/// while the queue is empty.
+///
+/// After each empty get, the delay before the next attempt is multiplied by `multiplier`, up to
+/// `max`. The [`default`](#impl-Default) implementation starts at 10 milliseconds, doubles after
+/// each empty get, and is capped at 1 second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GetBackoff {
+    /// Delay after the first empty get.
+    pub initial: Duration,
+
+    /// Maximum delay between gets.
+    pub max: Duration,
+
+    /// Factor by which the delay grows after each empty get.
+    pub multiplier: u32,
+}
+
+impl Default for GetBackoff {
+    fn default() -> GetBackoff {
+        GetBackoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+            multiplier: 2,
+        }
+    }
+}
+
+impl GetBackoff {
+    pub(crate) fn next_delay(&self, delay: Duration) -> Duration {
+        delay
+            .checked_mul(self.multiplier)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_capped() {
+        let backoff = GetBackoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(350),
+            multiplier: 2,
+        };
+        let mut delay = backoff.initial;
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            delays.push(delay);
+            delay = backoff.next_delay(delay);
+        }
+        let millis = |ms| Duration::from_millis(ms);
+        assert_eq!(
+            delays,
+            vec![millis(100), millis(200), millis(350), millis(350)]
+        );
+
+        let huge = Duration::from_secs(u64::max_value());
+        assert_eq!(backoff.next_delay(huge), backoff.max);
+    }
+}
+
+#[cfg(all(test, feature = "mock-broker"))]
+mod mock_broker_tests {
+    use super::*;
+    use crate::mock_broker::{with_mock_chan, FRAME_MAX};
+    use crate::{Error, Publish, QueueDeclareOptions};
+    use amq_protocol::protocol::AMQPSoftError;
+
+    #[test]
+    fn get_streaming() {
+        with_mock_chan(|channel| {
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            assert!(queue.get_streaming(true).unwrap().is_none());
+
+            let big = (0..FRAME_MAX * 2).map(|i| i as u8).collect::<Vec<_>>();
+            channel
+                .basic_publish("", Publish::new(&big, queue.name()))
+                .unwrap();
+            channel
+                .basic_publish("", Publish::new(b"", queue.name()))
+                .unwrap();
+
+            let get = queue.get_streaming(false).unwrap().unwrap();
+            assert_eq!(get.body_size, big.len() as u64);
+            assert_eq!(get.message_count, 1);
+            let chunks = get.receiver().iter().collect::<Vec<_>>();
+            assert!(chunks.len() > 1);
+            assert_eq!(chunks.concat(), big);
+            get.ack(&channel).unwrap();
+
+            // an empty body is a message, not an empty queue
+            let get = queue.get_streaming(true).unwrap().unwrap();
+            assert_eq!(get.body_size, 0);
+            assert!(get.receiver().recv().is_err());
+            assert!(get.into_get().unwrap().body().is_empty());
+            assert!(queue.get_streaming(true).unwrap().is_none());
+        })
+    }
+
+    #[test]
+    fn get_empty_and_message_count() {
+        with_mock_chan(|channel| {
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            assert!(queue.get(true).unwrap().is_none());
+
+            for body in &[b"a", b"b", b"c"] {
+                channel
+                    .basic_publish("", Publish::new(&body[..], queue.name()))
+                    .unwrap();
+            }
+            for (body, remaining) in &[(b"a", 2), (b"b", 1), (b"c", 0)] {
+                let get = channel.basic_get(queue.name(), true).unwrap().unwrap();
+                assert_eq!(get.body(), &body[..]);
+                assert_eq!(get.message_count, *remaining);
+            }
+            assert!(channel.basic_get(queue.name(), true).unwrap().is_none());
+
+            // a missing queue is an error, not an empty get
+            match channel.basic_get("missing", true) {
+                Err(Error::ServerClosedChannel { code, .. }) => {
+                    assert_eq!(code, AMQPSoftError::NOTFOUND.get_id());
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        })
+    }
+}
@@ -0,0 +1,175 @@
+use crate::errors::*;
+use crate::{Channel, Delivery, Get, Publish};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use snafu::ResultExt;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+const GZIP: &str = "gzip";
+
+// Largest body decoded_body will decompress; matches RabbitMQ's default max_message_size, so any
+// message the server accepted uncompressed fits.
+const DEFAULT_MAX_DECODED_LEN: usize = 128 * 1024 * 1024;
+
+impl Channel {
+    /// Compress the body of `publish` with gzip and publish it to `exchange` with a
+    /// `content_encoding` of `gzip`, so that consumers (amiquip or otherwise) know to decompress
+    /// it. Requires the `gzip` feature.
+    ///
+    /// Any `content_encoding` already set on `publish` is replaced; all other properties are sent
+    /// unchanged. If compression fails, nothing is published and this method returns
+    /// [`Error::ContentEncoding`](enum.Error.html#variant.ContentEncoding). Otherwise, this
+    /// behaves like [`basic_publish`](#method.basic_publish).
+    pub fn publish_gzip<S: Into<String>>(&self, exchange: S, publish: Publish) -> Result<()> {
+        let body = compress(publish.body)?;
+        self.basic_publish(
+            exchange,
+            Publish {
+                body: &body,
+                routing_key: publish.routing_key,
+                mandatory: publish.mandatory,
+                immediate: publish.immediate,
+                properties: publish.properties.with_content_encoding(GZIP.to_string()),
+            },
+        )
+    }
+}
+
+impl Delivery {
+    /// Return the body of this message, decompressed according to its `content_encoding`.
+    /// Requires the `gzip` feature.
+    ///
+    /// A body with no `content_encoding` (or `identity`) is returned as-is without copying. A
+    /// `gzip` body is decompressed; if it is not valid gzip data, this returns
+    /// [`Error::ContentEncoding`](enum.Error.html#variant.ContentEncoding). Any other encoding
+    /// returns
+    /// [`Error::UnsupportedContentEncoding`](enum.Error.html#variant.UnsupportedContentEncoding).
+    /// In either error case the delivery must still be acknowledged or rejected.
+    ///
+    /// To guard against small messages that decompress to huge bodies, decompression stops
+    /// after 128 MiB (RabbitMQ's default maximum message size) and returns
+    /// [`Error::DecodedBodyTooLarge`](enum.Error.html#variant.DecodedBodyTooLarge); use
+    /// [`decoded_body_with_limit`](#method.decoded_body_with_limit) to choose a different limit.
+    pub fn decoded_body(&self) -> Result<Cow<[u8]>> {
+        self.decoded_body_with_limit(DEFAULT_MAX_DECODED_LEN)
+    }
+
+    /// Like [`decoded_body`](#method.decoded_body), but fails with
+    /// [`Error::DecodedBodyTooLarge`](enum.Error.html#variant.DecodedBodyTooLarge) if the body
+    /// decompresses to more than `max_len` bytes. Bodies that are not compressed are returned
+    /// regardless of their size. Requires the `gzip` feature.
+    pub fn decoded_body_with_limit(&self, max_len: usize) -> Result<Cow<[u8]>> {
+        let encoding = match self.properties.content_encoding() {
+            Some(encoding) => encoding.trim(),
+            None => return Ok(Cow::Borrowed(&self.body)),
+        };
+        if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+            Ok(Cow::Borrowed(&self.body))
+        } else if encoding.eq_ignore_ascii_case(GZIP) {
+            decompress(&self.body, max_len).map(Cow::Owned)
+        } else {
+            UnsupportedContentEncoding { encoding }.fail()
+        }
+    }
+}
+
+impl Get {
+    /// Calls [`Delivery::decoded_body`](struct.Delivery.html#method.decoded_body) on
+    /// `self.delivery`. Requires the `gzip` feature.
+    #[inline]
+    pub fn decoded_body(&self) -> Result<Cow<[u8]>> {
+        self.delivery.decoded_body()
+    }
+
+    /// Calls
+    /// [`Delivery::decoded_body_with_limit`](struct.Delivery.html#method.decoded_body_with_limit)
+    /// on `self.delivery`. Requires the `gzip` feature.
+    #[inline]
+    pub fn decoded_body_with_limit(&self, max_len: usize) -> Result<Cow<[u8]>> {
+        self.delivery.decoded_body_with_limit(max_len)
+    }
+}
+
+fn compress(body: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body).context(ContentEncoding)?;
+    encoder.finish().context(ContentEncoding)
+}
+
+fn decompress(body: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    // Read one byte past the limit so we can tell a body of exactly max_len from a larger one.
+    GzDecoder::new(body)
+        .take((max_len as u64).saturating_add(1))
+        .read_to_end(&mut decoded)
+        .context(ContentEncoding)?;
+    if decoded.len() > max_len {
+        return DecodedBodyTooLarge { max_len }.fail();
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AmqpProperties;
+    use amq_protocol::protocol::basic::Deliver;
+
+    fn delivery(body: Vec<u8>, encoding: Option<&str>) -> Delivery {
+        let deliver = Deliver {
+            consumer_tag: "tag".to_string(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: String::new(),
+            routing_key: "gzip".to_string(),
+        };
+        let mut properties = AmqpProperties::default();
+        if let Some(encoding) = encoding {
+            properties = properties.with_content_encoding(encoding.to_string());
+        }
+        Delivery::new(1, deliver, body, properties).1
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let body = b"hello hello hello hello".to_vec();
+        let compressed = compress(&body).unwrap();
+        assert_ne!(compressed, body);
+
+        let decoded = delivery(compressed, Some("gzip")).decoded_body().unwrap();
+        assert_eq!(&*decoded, &body[..]);
+    }
+
+    #[test]
+    fn gzip_size_limit() {
+        let body = vec![0; 4096];
+        let delivery = delivery(compress(&body).unwrap(), Some("gzip"));
+
+        assert_eq!(&*delivery.decoded_body_with_limit(4096).unwrap(), &body[..]);
+        match delivery.decoded_body_with_limit(4095) {
+            Err(Error::DecodedBodyTooLarge { max_len }) => assert_eq!(max_len, 4095),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn identity_and_unsupported_encodings() {
+        for encoding in &[None, Some("identity"), Some("")] {
+            match delivery(b"plain".to_vec(), *encoding).decoded_body() {
+                Ok(Cow::Borrowed(body)) => assert_eq!(body, b"plain"),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        match delivery(b"plain".to_vec(), Some("br")).decoded_body() {
+            Err(Error::UnsupportedContentEncoding { encoding }) => assert_eq!(encoding, "br"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match delivery(b"not gzip".to_vec(), Some("gzip")).decoded_body() {
+            Err(Error::ContentEncoding { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{AmqpValue, FieldTable};
+
+/// Matching arguments for binding a queue or exchange to a headers exchange.
+///
+/// Headers exchanges route messages based on the `headers` property of the message instead of its
+/// routing key. A binding to a headers exchange carries the header values to match against along
+/// with an `x-match` argument specifying whether a message must match all of them or just one.
+///
+/// # Example
+///
+/// ```rust
+/// # use amiquip::{AmqpValue, HeadersMatch};
+/// let headers_match = HeadersMatch::all()
+///     .header("format", AmqpValue::LongString("pdf".to_string()))
+///     .header("version", AmqpValue::LongInt(2));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadersMatch {
+    match_all: bool,
+    headers: FieldTable,
+}
+
+impl HeadersMatch {
+    /// Create a match that requires a message's headers to match all of the given values
+    /// (`x-match: all`).
+    pub fn all() -> HeadersMatch {
+        HeadersMatch {
+            match_all: true,
+            headers: FieldTable::new(),
+        }
+    }
+
+    /// Create a match that requires a message's headers to match at least one of the given values
+    /// (`x-match: any`).
+    pub fn any() -> HeadersMatch {
+        HeadersMatch {
+            match_all: false,
+            headers: FieldTable::new(),
+        }
+    }
+
+    /// Add a header that must be present with the given value. Header names beginning with `x-`
+    /// are reserved by the server and are not used for matching.
+    pub fn header<K: Into<String>>(mut self, key: K, value: AmqpValue) -> HeadersMatch {
+        self.headers.insert(key.into(), value);
+        self
+    }
+
+    /// Convert this match into the `FieldTable` of binding arguments expected by the server.
+    pub fn into_arguments(self) -> FieldTable {
+        let x_match = if self.match_all { "all" } else { "any" };
+        let mut arguments = self.headers;
+        arguments.insert(
+            "x-match".to_string(),
+            AmqpValue::LongString(x_match.to_string()),
+        );
+        arguments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_arguments() {
+        let arguments = HeadersMatch::all()
+            .header("format", AmqpValue::LongString("pdf".to_string()))
+            .header("version", AmqpValue::LongInt(2))
+            .into_arguments();
+        let mut expected = FieldTable::new();
+        expected.insert(
+            "x-match".to_string(),
+            AmqpValue::LongString("all".to_string()),
+        );
+        expected.insert(
+            "format".to_string(),
+            AmqpValue::LongString("pdf".to_string()),
+        );
+        expected.insert("version".to_string(), AmqpValue::LongInt(2));
+        assert_eq!(arguments, expected);
+
+        let arguments = HeadersMatch::any().into_arguments();
+        let mut expected = FieldTable::new();
+        expected.insert(
+            "x-match".to_string(),
+            AmqpValue::LongString("any".to_string()),
+        );
+        assert_eq!(arguments, expected);
+    }
+}
@@ -1,4 +1,4 @@
-use log::trace;
+use crate::logging::trace;
 use mio_extras::timer::{Timeout, Timer};
 use std::fmt::Debug;
 use std::time::{Duration, Instant};
@@ -0,0 +1,357 @@
+use super::with_conn;
+use crate::{
+    AmqpProperties, BatchConfirm, Confirmation, ConsumerOptions, Error, Publish,
+    QueueDeclareOptions,
+};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_server_close_reason() {
+    with_conn(|conn| {
+        assert!(conn.is_open());
+
+        let chan = conn.open_channel(None).unwrap();
+        assert!(chan.is_open());
+        assert!(chan.server_close_reason().is_none());
+
+        // publishing to a nonexistent exchange causes the server to close the channel
+        chan.basic_publish("amiquip-test-does-not-exist", Publish::new(&[], ""))
+            .unwrap();
+
+        let mut tries = 0;
+        while chan.is_open() {
+            tries += 1;
+            assert!(tries < 50, "server did not close channel");
+            thread::sleep(Duration::from_millis(100));
+        }
+        let (code, _) = chan.server_close_reason().unwrap();
+        assert_eq!(code, 404);
+
+        // the close error is still reported by the next operation
+        match chan.flush() {
+            Err(Error::ServerClosedChannel { code, .. }) => assert_eq!(code, 404),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(conn.is_open());
+    })
+}
+
+#[test]
+fn test_publish_to_queue() {
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let returns = chan.listen_for_returns().unwrap();
+
+        match chan.publish_to_queue("", b"hello", AmqpProperties::default()) {
+            Err(Error::QueueNameRequired) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let name = "amiquip-test-publish-to-queue";
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        chan.publish_to_queue(name, b"hello", AmqpProperties::default())
+            .unwrap();
+        let get = queue.get(true).unwrap().unwrap();
+        assert_eq!(get.delivery.body, b"hello");
+
+        // Publishing to a queue that does not exist returns the message.
+        chan.publish_to_queue(
+            "amiquip-test-does-not-exist",
+            b"lost",
+            AmqpProperties::default(),
+        )
+        .unwrap();
+        let returned = returns.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(returned.routing_key, "amiquip-test-does-not-exist");
+        assert_eq!(returned.content, b"lost");
+    })
+}
+
+#[test]
+fn test_auto_reopen_channel() {
+    with_conn(|conn| {
+        let chan = conn.open_auto_reopen_channel().unwrap();
+        let returns = chan.listen_for_returns().unwrap();
+
+        match chan.queue_declare_passive("amiquip-test-does-not-exist") {
+            Err(Error::ServerClosedChannel { code: 404, .. }) => (),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("passive declare of a missing queue should fail"),
+        }
+        assert!(!chan.is_open());
+
+        // The next operation reopens the channel.
+        let name = "amiquip-test-auto-reopen-channel";
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        assert!(chan.is_open());
+        assert_eq!(queue.name(), name);
+
+        // The return listener was restored on the new channel.
+        chan.publish_to_queue(
+            "amiquip-test-does-not-exist",
+            b"lost",
+            AmqpProperties::default(),
+        )
+        .unwrap();
+        let returned = returns.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(returned.content, b"lost");
+    })
+}
+
+#[test]
+fn test_nack_up_to() {
+    let name = "amiquip-test-nack-up-to";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let other = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        let queue = chan.queue_declare(name, options).unwrap();
+        for _ in 0..3 {
+            chan.basic_publish("", Publish::new(b"hello", name))
+                .unwrap();
+        }
+
+        let mut last = None;
+        for _ in 0..3 {
+            let get = queue.get(false).unwrap().expect("message available");
+            assert!(!get.delivery.redelivered);
+            last = Some(get.delivery);
+        }
+        let last = last.unwrap();
+
+        match other.nack_up_to(&last, true) {
+            Err(Error::DeliveryChannelMismatch {
+                delivery_channel_id,
+                channel_id,
+            }) => {
+                assert_eq!(delivery_channel_id, chan.channel_id());
+                assert_eq!(channel_id, other.channel_id());
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        chan.nack_up_to(&last, true).unwrap();
+        for _ in 0..3 {
+            let get = queue.get(true).unwrap().expect("message requeued");
+            assert!(get.delivery.redelivered);
+        }
+        assert!(queue.get(true).unwrap().is_none());
+    })
+}
+
+#[test]
+fn test_batch_publish() {
+    let name = "amiquip-test-batch-publish";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        let queue = chan.queue_declare(name, options).unwrap();
+        chan.flush().unwrap();
+
+        let before = conn.metrics();
+        chan.batch(|chan| {
+            for _ in 0..50 {
+                chan.basic_publish("", Publish::new(b"hello", name))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        chan.flush().unwrap();
+        let after = conn.metrics();
+        assert_eq!(after.messages_published - before.messages_published, 50);
+        assert!(after.socket_writes - before.socket_writes < 5);
+
+        let consumer = queue
+            .consume(ConsumerOptions {
+                no_ack: true,
+                ..ConsumerOptions::default()
+            })
+            .unwrap();
+        let batch = consumer.recv_batch(50, Duration::from_secs(5));
+        assert_eq!(batch.len(), 50);
+    })
+}
+
+#[test]
+fn test_ack_and_nack_by_tag() {
+    let name = "amiquip-test-ack-and-nack-by-tag";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        let queue = chan.queue_declare(name, options).unwrap();
+        for body in &[b"one", b"two"] {
+            chan.basic_publish("", Publish::new(&body[..], name))
+                .unwrap();
+        }
+
+        let tags = (0..2)
+            .map(|_| {
+                let get = queue.get(false).unwrap().expect("message available");
+                get.delivery.delivery_tag()
+            })
+            .collect::<Vec<_>>();
+
+        // ack the second message before the first, and requeue the first
+        chan.ack_tag(tags[1], false).unwrap();
+        chan.nack_tag(tags[0], false, true).unwrap();
+
+        let get = queue.get(true).unwrap().expect("message requeued");
+        assert_eq!(get.delivery.body(), b"one");
+        assert!(get.delivery.redelivered);
+        assert!(queue.get(true).unwrap().is_none());
+        assert!(chan.is_open());
+    })
+}
+
+#[test]
+fn test_publish_batch() {
+    let name = "amiquip-test-publish-batch";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        chan.queue_declare(name, options).unwrap();
+
+        // publishes before the batch don't confuse its confirm tracking
+        let batch = chan
+            .publish_batch("", vec![Publish::new(b"first", name)])
+            .unwrap();
+        assert_eq!(batch.collect::<Vec<_>>(), vec![BatchConfirm::Ack(0)]);
+        chan.basic_publish("", Publish::new(b"unbatched", name))
+            .unwrap();
+
+        let bodies = (0..100).map(|i| format!("{}", i)).collect::<Vec<_>>();
+        let batch = chan
+            .publish_batch(
+                "",
+                bodies
+                    .iter()
+                    .map(|body| Publish::new(body.as_bytes(), name)),
+            )
+            .unwrap();
+        assert_eq!(batch.len(), 100);
+        let mut acked = batch
+            .map(|confirm| match confirm {
+                BatchConfirm::Ack(i) => i,
+                BatchConfirm::Nack(i) => panic!("message {} was nacked", i),
+            })
+            .collect::<Vec<_>>();
+        acked.sort();
+        assert_eq!(acked, (0..100).collect::<Vec<_>>());
+    })
+}
+
+#[test]
+fn test_wait_for_confirm() {
+    let name = "amiquip-test-wait-for-confirm";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        chan.queue_declare(name, options).unwrap();
+
+        match chan.basic_publish_tracked("", Publish::new(b"hello", name)) {
+            Err(Error::PublisherConfirmsNotEnabled) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        chan.enable_publisher_confirms().unwrap();
+        let first = chan
+            .basic_publish_tracked("", Publish::new(b"first", name))
+            .unwrap();
+        chan.basic_publish("", Publish::new(b"untracked", name))
+            .unwrap();
+        let third = chan
+            .basic_publish_tracked("", Publish::new(b"third", name))
+            .unwrap();
+        assert_eq!((first, third), (1, 3));
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            chan.wait_for_confirm(third, timeout).unwrap(),
+            Confirmation::Ack
+        );
+        assert_eq!(
+            chan.wait_for_confirm(first, timeout).unwrap(),
+            Confirmation::Ack
+        );
+        match chan.wait_for_confirm(2, timeout) {
+            Err(Error::UntrackedDeliveryTag { delivery_tag: 2 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    })
+}
+
+#[test]
+fn test_publish_with_callback() {
+    let name = "amiquip-test-publish-with-callback";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        chan.queue_declare(name, options).unwrap();
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let on_confirm = |tag: u64| {
+            let tx = tx.clone();
+            move |outcome: crate::Result<Confirmation>| tx.send((tag, outcome)).unwrap()
+        };
+        match chan.basic_publish_with_callback("", Publish::new(b"hello", name), on_confirm(0)) {
+            Err(Error::PublisherConfirmsNotEnabled) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        chan.enable_publisher_confirms().unwrap();
+        for tag in 1..=5 {
+            let delivery_tag = chan
+                .basic_publish_with_callback("", Publish::new(b"hello", name), on_confirm(tag))
+                .unwrap();
+            assert_eq!(delivery_tag, tag);
+        }
+        let timeout = Duration::from_secs(5);
+        for tag in 1..=5 {
+            match rx.recv_timeout(timeout).unwrap() {
+                (confirmed, Ok(Confirmation::Ack)) => assert_eq!(confirmed, tag),
+                other => panic!("unexpected confirm {:?}", other),
+            }
+        }
+    })
+}
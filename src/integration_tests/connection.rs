@@ -0,0 +1,178 @@
+use super::{with_conn, with_test_url};
+use crate::{
+    AmqpFrame, Connection, Error, FrameDirection, Publish, QueueDeclareOptions, QueueDeleteOptions,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+#[test]
+fn test_nonexistent_vhost_refused() {
+    with_test_url(|url| {
+        let mut url = Url::parse(url).unwrap();
+        url.set_path("/amiquip-test-no-such-vhost");
+        match Connection::insecure_open(url.as_str()) {
+            Err(Error::AccessRefused { .. }) => (),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("connection to nonexistent vhost should fail"),
+        }
+    })
+}
+
+#[test]
+fn test_metrics() {
+    with_conn(|conn| {
+        let before = conn.metrics();
+        assert!(before.frames_received > 0);
+        assert!(before.bytes_received > 0);
+
+        let chan = conn.open_channel(None).unwrap();
+        let queue = chan
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        chan.enable_publisher_confirms().unwrap();
+        chan.basic_publish("", Publish::new(b"hello", queue.name()))
+            .unwrap();
+        let get = queue.get(false).unwrap().unwrap();
+        get.ack(&chan).unwrap();
+        chan.flush().unwrap();
+
+        let after = conn.metrics();
+        assert_eq!(after.messages_published, before.messages_published + 1);
+        assert_eq!(after.deliveries_received, before.deliveries_received + 1);
+        assert_eq!(after.acks_sent, before.acks_sent + 1);
+        assert!(after.frames_sent > before.frames_sent);
+        assert!(after.bytes_sent > before.bytes_sent);
+        // the get-ok implies the server processed the publish and sent its confirm first
+        assert_eq!(after.unconfirmed, 0);
+    })
+}
+
+#[test]
+fn test_queue_declare_or_get() {
+    with_conn(|conn| {
+        let name = "amiquip-test-queue-declare-or-get";
+        let options = QueueDeclareOptions {
+            auto_delete: true,
+            ..QueueDeclareOptions::default()
+        };
+
+        let status = conn.queue_declare_or_get(name, options.clone()).unwrap();
+        assert_eq!(status.name, name);
+        assert!(status.created);
+
+        let chan = conn.open_channel(None).unwrap();
+        let confirms = chan.listen_for_publisher_confirms().unwrap();
+        chan.enable_publisher_confirms().unwrap();
+        chan.basic_publish("", Publish::new(b"hello", name))
+            .unwrap();
+        confirms.recv().unwrap();
+
+        // Options that conflict with the existing queue are ignored.
+        let status = conn
+            .queue_declare_or_get(name, QueueDeclareOptions::default())
+            .unwrap();
+        assert!(!status.created);
+        assert_eq!(status.message_count, 1);
+        assert!(chan.is_open());
+
+        chan.queue_delete(name, QueueDeleteOptions::default())
+            .unwrap();
+    })
+}
+
+#[test]
+fn test_capabilities() {
+    with_conn(|conn| {
+        let capabilities = conn.capabilities();
+        assert!(capabilities.publisher_confirms());
+        assert!(capabilities.basic_nack());
+        assert!(capabilities.consumer_cancel_notify());
+        assert!(!capabilities.supports("amiquip-test-no-such-capability"));
+    })
+}
+
+#[test]
+fn test_open_cluster_fails_over() {
+    with_test_url(|url| {
+        let conn = Connection::insecure_open_cluster(&["amqp://127.0.0.1:1", url]).unwrap();
+        conn.close().unwrap();
+    })
+}
+
+#[test]
+fn test_small_frame_max() {
+    let name = "amiquip-test-small-frame-max";
+
+    with_test_url(|url| {
+        let mut url = Url::parse(url).unwrap();
+        url.query_pairs_mut().append_pair("frame_max", "4096");
+        let mut conn = Connection::insecure_open(url.as_str()).unwrap();
+        let chan = conn.open_channel(None).unwrap();
+        let options = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        let queue = chan.queue_declare(name, options).unwrap();
+
+        // large enough to be split into several body frames in both directions
+        let body = (0..20_000).map(|i| i as u8).collect::<Vec<_>>();
+        chan.basic_publish("", Publish::new(&body, name)).unwrap();
+        let get = queue.get(true).unwrap().unwrap();
+        assert_eq!(get.delivery.body, body);
+
+        conn.close().unwrap();
+    })
+}
+
+#[test]
+fn test_frame_observer() {
+    with_conn(|conn| {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let frames2 = Arc::clone(&frames);
+        conn.set_frame_observer(move |direction, frame| {
+            if let AmqpFrame::Method(channel_id, _) = frame {
+                frames2.lock().unwrap().push((direction, *channel_id));
+            }
+        });
+
+        let chan = conn.open_channel(Some(7)).unwrap();
+        conn.clear_frame_observer();
+        chan.close().unwrap();
+
+        // channel.open and channel.open-ok, but nothing from the close
+        assert_eq!(
+            *frames.lock().unwrap(),
+            vec![(FrameDirection::Outbound, 7), (FrameDirection::Inbound, 7)]
+        );
+    })
+}
+
+#[test]
+fn test_io_thread_panic() {
+    with_test_url(|url| {
+        let mut conn = Connection::insecure_open(url).unwrap();
+        assert_ne!(conn.io_thread_id(), std::thread::current().id());
+        assert!(!conn.io_thread_panicked());
+
+        conn.set_frame_observer(|_, _| panic!("observer panic"));
+        assert!(conn.open_channel(None).is_err());
+        // the flag is set as the thread finishes unwinding, which may be after our request
+        // failed
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !conn.io_thread_panicked() {
+            assert!(Instant::now() < deadline, "panic flag was never set");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        match conn.close() {
+            Err(Error::IoThreadPanic) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    })
+}
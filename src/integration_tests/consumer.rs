@@ -0,0 +1,402 @@
+use super::{with_chan, with_conn};
+use crate::{
+    ConsumerMessage, ConsumerOptions, ConsumerSet, Error, Publish, QueueDeclareOptions,
+    StreamingDelivery,
+};
+use std::thread;
+use std::time::Duration;
+
+fn exclusive() -> QueueDeclareOptions {
+    QueueDeclareOptions {
+        exclusive: true,
+        ..QueueDeclareOptions::default()
+    }
+}
+
+#[test]
+fn test_consume_with_tag() {
+    let name = "amiquip-test-consume-with-tag";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let consumer = queue
+            .consume(ConsumerOptions {
+                consumer_tag: Some("amiquip-tag".to_string()),
+                ..ConsumerOptions::default()
+            })
+            .unwrap();
+        assert_eq!(consumer.consumer_tag(), "amiquip-tag");
+    })
+}
+
+#[test]
+fn test_consume_nowait() {
+    let name = "amiquip-test-consume-nowait";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+
+        match queue.consume_nowait(ConsumerOptions::default()) {
+            Err(Error::ConsumerTagRequired) => (),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("nowait consume without a tag should fail"),
+        }
+
+        let consumer = queue
+            .consume_nowait(ConsumerOptions {
+                no_ack: true,
+                consumer_tag: Some("amiquip-nowait-tag".to_string()),
+                ..ConsumerOptions::default()
+            })
+            .unwrap();
+        assert_eq!(consumer.consumer_tag(), "amiquip-nowait-tag");
+
+        chan.basic_publish("", Publish::new(b"hello", name))
+            .unwrap();
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::Delivery(delivery) => assert_eq!(delivery.body, b"hello"),
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+
+        consumer.cancel().unwrap();
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::ClientCancelled => (),
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+    })
+}
+
+#[test]
+fn test_ack_all_outstanding() {
+    let name = "amiquip-test-ack-all-outstanding";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        for _ in 0..3 {
+            chan.basic_publish("", Publish::new(b"hello", name))
+                .unwrap();
+        }
+
+        let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+        let mut last_delivery_tag = 0;
+        for _ in 0..3 {
+            match consumer.receiver().recv().unwrap() {
+                ConsumerMessage::Delivery(delivery) => {
+                    last_delivery_tag = delivery.delivery_tag();
+                }
+                other => panic!("unexpected consumer message {:?}", other),
+            }
+        }
+        consumer.ack_all_outstanding(last_delivery_tag).unwrap();
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::ClientCancelled => (),
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+
+        // everything was acked, so nothing should be left in the queue
+        assert!(queue.get(false).unwrap().is_none());
+    })
+}
+
+#[test]
+fn test_consumer_set() {
+    let name1 = "amiquip-test-consumer-set-1";
+    let name2 = "amiquip-test-consumer-set-2";
+
+    with_chan(|chan| {
+        let queue1 = chan.queue_declare(name1, exclusive()).unwrap();
+        let queue2 = chan.queue_declare(name2, exclusive()).unwrap();
+        let no_ack = || ConsumerOptions {
+            no_ack: true,
+            ..ConsumerOptions::default()
+        };
+        let consumer1 = queue1.consume(no_ack()).unwrap();
+        let consumer2 = queue2.consume(no_ack()).unwrap();
+        let tag1 = consumer1.consumer_tag().to_string();
+        let tag2 = consumer2.consumer_tag().to_string();
+
+        let mut set = ConsumerSet::new();
+        set.insert(consumer1);
+        set.insert(consumer2);
+
+        chan.basic_publish("", Publish::new(b"one", name1)).unwrap();
+        chan.basic_publish("", Publish::new(b"two", name2)).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            match set.recv().unwrap() {
+                (tag, ConsumerMessage::Delivery(delivery)) => {
+                    assert_eq!(delivery.consumer_tag(), Some(tag.as_str()));
+                    received.push((tag, delivery.body))
+                }
+                (_, other) => panic!("unexpected consumer message {:?}", other),
+            }
+        }
+        received.sort();
+        let mut expected = vec![(tag1.clone(), b"one".to_vec()), (tag2, b"two".to_vec())];
+        expected.sort();
+        assert_eq!(received, expected);
+
+        // cancelling one consumer should not affect the other
+        set.consumers()[0].cancel().unwrap();
+        match set.recv().unwrap() {
+            (ref tag, ConsumerMessage::ClientCancelled) if *tag == tag1 => (),
+            (_, other) => panic!("unexpected consumer message {:?}", other),
+        }
+        chan.basic_publish("", Publish::new(b"three", name2))
+            .unwrap();
+        match set.recv().unwrap() {
+            (_, ConsumerMessage::Delivery(delivery)) => assert_eq!(delivery.body, b"three"),
+            (_, other) => panic!("unexpected consumer message {:?}", other),
+        }
+
+        set.cancel_all().unwrap();
+        match set.recv().unwrap() {
+            (_, ConsumerMessage::ClientCancelled) => (),
+            (_, other) => panic!("unexpected consumer message {:?}", other),
+        }
+        assert!(set.recv_timeout(Duration::from_millis(10)).is_err());
+    })
+}
+
+#[test]
+fn test_streaming_consumer() {
+    let name = "amiquip-test-streaming-consumer";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let consumer = queue
+            .consume(ConsumerOptions {
+                streaming: true,
+                ..ConsumerOptions::default()
+            })
+            .unwrap();
+
+        // large enough to be split across several body frames
+        let body = (0..300_000).map(|i| i as u8).collect::<Vec<_>>();
+        chan.basic_publish("", Publish::new(&body, name)).unwrap();
+        chan.basic_publish("", Publish::new(b"", name)).unwrap();
+
+        for expected in &[&body[..], b""] {
+            let delivery = match consumer.receiver().recv().unwrap() {
+                ConsumerMessage::StreamingDelivery(StreamingDelivery::Start {
+                    delivery,
+                    body_size,
+                }) => {
+                    assert!(delivery.body.is_empty());
+                    assert_eq!(body_size, expected.len() as u64);
+                    delivery
+                }
+                other => panic!("unexpected consumer message {:?}", other),
+            };
+            let mut received = Vec::new();
+            loop {
+                match consumer.receiver().recv().unwrap() {
+                    ConsumerMessage::StreamingDelivery(StreamingDelivery::Body(chunk)) => {
+                        received.extend_from_slice(&chunk)
+                    }
+                    ConsumerMessage::StreamingDelivery(StreamingDelivery::Complete) => break,
+                    other => panic!("unexpected consumer message {:?}", other),
+                }
+            }
+            assert_eq!(&received[..], *expected);
+            consumer.ack(delivery).unwrap();
+        }
+    })
+}
+
+#[test]
+fn test_consumer_max_body_size() {
+    let name = "amiquip-test-consumer-max-body-size";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let consumer = queue
+            .consume(ConsumerOptions {
+                max_body_size: Some(10),
+                ..ConsumerOptions::default()
+            })
+            .unwrap();
+
+        chan.basic_publish("", Publish::new(&[0; 300_000], name))
+            .unwrap();
+        chan.basic_publish("", Publish::new(b"small", name))
+            .unwrap();
+
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::OversizedDelivery {
+                delivery,
+                body_size,
+            } => {
+                assert!(delivery.body.is_empty());
+                assert_eq!(body_size, 300_000);
+                consumer.reject(delivery, false).unwrap();
+            }
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::Delivery(delivery) => {
+                assert_eq!(delivery.body, b"small");
+                consumer.ack(delivery).unwrap();
+            }
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+    })
+}
+
+#[test]
+fn test_exclusive_consumer_conflict() {
+    with_conn(|conn| {
+        let name = "amiquip-test-exclusive-consumer";
+        let chan1 = conn.open_channel(None).unwrap();
+        let chan2 = conn.open_channel(None).unwrap();
+
+        let queue = chan1.queue_declare(name, exclusive()).unwrap();
+        let _consumer = queue
+            .consume(ConsumerOptions {
+                exclusive: true,
+                ..ConsumerOptions::default()
+            })
+            .unwrap();
+
+        match chan2.basic_consume(name, ConsumerOptions::default()) {
+            Err(Error::ConsumerExclusiveConflict { queue }) => assert_eq!(queue, name),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("second consumer should have been refused"),
+        }
+    })
+}
+
+#[test]
+fn test_consumer_shutdown_handle() {
+    let name = "amiquip-test-consumer-shutdown-handle";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+
+        let handle = consumer.shutdown_handle();
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            assert!(handle.shutdown());
+            // Repeated shutdowns are ignored.
+            assert!(handle.shutdown());
+        });
+
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::ClientCancelled => (),
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+        thread.join().unwrap();
+
+        // The channel is still usable for RPCs, including the cancel sent on drop.
+        chan.queue_declare_passive(name).unwrap();
+        consumer.cancel().unwrap();
+        chan.queue_declare_passive(name).unwrap();
+    })
+}
+
+#[test]
+fn test_recv_batch() {
+    let name = "amiquip-test-recv-batch";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        for _ in 0..5 {
+            chan.basic_publish("", Publish::new(b"hello", name))
+                .unwrap();
+        }
+
+        let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+        let batch = consumer.recv_batch(3, Duration::from_secs(5));
+        assert_eq!(batch.len(), 3);
+
+        // only 2 messages remain, so the second batch is cut short by the timeout
+        let batch = consumer.recv_batch(3, Duration::from_millis(500));
+        assert_eq!(batch.len(), 2);
+        consumer
+            .ack_multiple(batch.into_iter().last().unwrap())
+            .unwrap();
+
+        let batch = consumer.recv_batch(3, Duration::from_millis(100));
+        assert!(batch.is_empty());
+    })
+}
+
+#[test]
+fn test_drop_cancels_consumer() {
+    let name = "amiquip-test-drop-cancels-consumer";
+
+    with_chan(|chan| {
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+        drop(consumer);
+
+        // the server handles the cancel before this declare, so the consumer is gone
+        let refreshed = queue.declare_passive().unwrap();
+        assert_eq!(refreshed.declared_consumer_count(), Some(0));
+
+        chan.basic_publish("", Publish::new(b"hello", name))
+            .unwrap();
+        let get = queue.get(false).unwrap().unwrap();
+        assert_eq!(get.delivery.body, b"hello");
+    })
+}
+
+#[test]
+fn test_per_consumer_and_global_qos() {
+    let name = "amiquip-test-per-consumer-and-global-qos";
+    let timeout = Duration::from_millis(500);
+    let expect_delivery =
+        |consumer: &crate::Consumer| match consumer.receiver().recv_timeout(timeout) {
+            Ok(ConsumerMessage::Delivery(delivery)) => delivery,
+            other => panic!("expected delivery, got {:?}", other),
+        };
+
+    // With a per-consumer prefetch of 1, a slow consumer holding an unacked message does
+    // not stop a fast one on the same channel from receiving everything else.
+    with_chan(|chan| {
+        chan.qos(0, 1, false).unwrap();
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let slow = queue.consume(ConsumerOptions::default()).unwrap();
+        let fast = queue.consume(ConsumerOptions::default()).unwrap();
+        for i in 0..4 {
+            chan.basic_publish("", Publish::new(format!("{}", i).as_bytes(), name))
+                .unwrap();
+        }
+
+        let held = expect_delivery(&slow);
+        for _ in 0..3 {
+            let delivery = expect_delivery(&fast);
+            fast.ack(delivery).unwrap();
+        }
+        assert!(slow.receiver().recv_timeout(timeout).is_err());
+        slow.ack(held).unwrap();
+    });
+
+    // With a channel-wide prefetch of 1, the message held by one consumer blocks delivery
+    // to the other.
+    with_chan(|chan| {
+        chan.qos(0, 1, true).unwrap();
+        let queue = chan.queue_declare(name, exclusive()).unwrap();
+        let first = queue.consume(ConsumerOptions::default()).unwrap();
+        let second = queue.consume(ConsumerOptions::default()).unwrap();
+        for i in 0..2 {
+            chan.basic_publish("", Publish::new(format!("{}", i).as_bytes(), name))
+                .unwrap();
+        }
+
+        let (holder, held, idle) = match first.receiver().recv_timeout(timeout) {
+            Ok(ConsumerMessage::Delivery(delivery)) => (&first, delivery, &second),
+            _ => (&second, expect_delivery(&second), &first),
+        };
+        assert!(idle.receiver().recv_timeout(timeout).is_err());
+        assert!(holder.receiver().recv_timeout(timeout).is_err());
+
+        holder.ack(held).unwrap();
+        let delivery = match first.receiver().recv_timeout(timeout) {
+            Ok(ConsumerMessage::Delivery(delivery)) => delivery,
+            _ => expect_delivery(&second),
+        };
+        assert_eq!(delivery.body, b"1");
+    });
+}
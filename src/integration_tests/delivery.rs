@@ -0,0 +1,92 @@
+use super::with_chan;
+use crate::{
+    AmqpValue, ConsumerMessage, ConsumerOptions, Error, FieldTable, GetBackoff, Publish,
+    QueueDeclareOptions,
+};
+use std::time::Duration;
+
+#[test]
+fn test_reject_direct_dead_letters() {
+    let dead_name = "amiquip-test-reject-direct-dead";
+    let live_name = "amiquip-test-reject-direct-live";
+
+    with_chan(|chan| {
+        let exclusive = QueueDeclareOptions {
+            exclusive: true,
+            ..QueueDeclareOptions::default()
+        };
+        let dead = chan.queue_declare(dead_name, exclusive.clone()).unwrap();
+
+        let mut arguments = FieldTable::new();
+        arguments.insert(
+            "x-dead-letter-exchange".to_string(),
+            AmqpValue::LongString(String::new()),
+        );
+        arguments.insert(
+            "x-dead-letter-routing-key".to_string(),
+            AmqpValue::LongString(dead_name.to_string()),
+        );
+        let live = chan
+            .queue_declare(
+                live_name,
+                QueueDeclareOptions {
+                    arguments,
+                    ..exclusive
+                },
+            )
+            .unwrap();
+
+        chan.basic_publish("", Publish::new(b"hello", live_name))
+            .unwrap();
+
+        let consumer = live.consume(ConsumerOptions::default()).unwrap();
+        match consumer.receiver().recv().unwrap() {
+            ConsumerMessage::Delivery(delivery) => delivery.reject_direct(false).unwrap(),
+            other => panic!("unexpected consumer message {:?}", other),
+        }
+
+        // Dead-lettering happens asynchronously on the server; poll until it shows up.
+        let get = dead
+            .get_with_backoff(
+                true,
+                GetBackoff::default(),
+                &crossbeam_channel::after(Duration::from_secs(5)),
+            )
+            .unwrap()
+            .expect("rejected message was not dead-lettered");
+        assert_eq!(get.delivery.body, b"hello");
+    })
+}
+
+#[test]
+fn test_ack_direct_requires_consumer() {
+    let name = "amiquip-test-ack-direct-requires-consumer";
+
+    with_chan(|chan| {
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        chan.basic_publish("", Publish::new(b"hello", name))
+            .unwrap();
+
+        let get = queue
+            .get_with_backoff(
+                false,
+                GetBackoff::default(),
+                &crossbeam_channel::after(Duration::from_secs(5)),
+            )
+            .unwrap()
+            .expect("published message not available");
+        match get.delivery.clone().ack_direct() {
+            Err(Error::DeliveryNotFromConsumer) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        get.ack(chan).unwrap();
+    })
+}
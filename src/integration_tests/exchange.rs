@@ -1,5 +1,10 @@
-use super::with_chan;
-use crate::{ExchangeDeclareOptions, ExchangeType, Publish};
+use super::{with_chan, with_conn};
+use crate::{
+    AmqpProperties, AmqpPropertiesExt, AmqpValue, Error, ExchangeDeclareOptions, ExchangeType,
+    FieldTable, GetBackoff, HeadersMatch, Publish, QueueDeclareOptions,
+};
+use std::thread;
+use std::time::Duration;
 
 #[test]
 fn test_publish_empty() {
@@ -11,6 +16,110 @@ fn test_publish_empty() {
     })
 }
 
+#[test]
+fn test_publish_expiration() {
+    with_chan(|chan| {
+        let queue = chan
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        let properties = AmqpProperties::default().with_expiration_ttl(Duration::from_secs(1));
+        chan.basic_publish(
+            "",
+            Publish::with_properties(b"short-lived", queue.name(), properties),
+        )
+        .unwrap();
+        chan.basic_publish("", Publish::new(b"long-lived", queue.name()))
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(1500));
+        let get = queue.get(true).unwrap().unwrap();
+        assert_eq!(get.delivery.body, b"long-lived");
+        assert!(queue.get(true).unwrap().is_none());
+    })
+}
+
+#[test]
+fn test_alternate_exchange() {
+    let name = "amiquip-test-alternate-exchange";
+    let alternate = "amiquip-test-alternate-exchange-ae";
+
+    with_chan(|chan| {
+        let auto_delete = ExchangeDeclareOptions {
+            auto_delete: true,
+            ..ExchangeDeclareOptions::default()
+        };
+        let ae = chan
+            .exchange_declare(ExchangeType::Fanout, alternate, auto_delete.clone())
+            .unwrap();
+        let exchange = chan
+            .exchange_declare(
+                ExchangeType::Direct,
+                name,
+                auto_delete.alternate_exchange(alternate),
+            )
+            .unwrap();
+        let unrouted = chan
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        unrouted.bind(&ae, "", FieldTable::new()).unwrap();
+
+        exchange
+            .publish(Publish::new(b"nobody wants me", "no.such.key"))
+            .unwrap();
+        let get = unrouted
+            .get_with_backoff(
+                true,
+                GetBackoff::default(),
+                &crossbeam_channel::after(Duration::from_secs(5)),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(get.delivery.body, b"nobody wants me");
+        assert_eq!(get.delivery.exchange(), name);
+    })
+}
+
+#[test]
+fn test_publish_flush() {
+    with_chan(|chan| {
+        for _ in 0..100 {
+            chan.basic_publish("", Publish::new(&[0; 1024], "does.not.exist"))
+                .unwrap();
+        }
+        chan.flush().unwrap();
+        // flushing with nothing pending should return immediately
+        chan.flush().unwrap();
+    })
+}
+
+#[test]
+fn test_try_publish() {
+    with_chan(|chan| {
+        let mut sent = 0;
+        for _ in 0..100 {
+            match chan.try_basic_publish("", Publish::new(&[0; 1024], "does.not.exist")) {
+                Ok(()) => sent += 1,
+                Err(Error::OutboundFull { .. }) => (),
+                Err(err) => panic!("unexpected error {}", err),
+            }
+        }
+        assert!(sent > 0);
+        chan.flush().unwrap();
+    })
+}
+
 #[test]
 fn test_declare() {
     let name = "amiquip-test-declare";
@@ -41,3 +150,99 @@ fn test_declare() {
         assert_eq!(ex1.name(), ex3.name());
     })
 }
+
+#[test]
+fn test_bind_headers() {
+    let name = "amiquip-test-bind-headers";
+
+    with_chan(|chan| {
+        let exchange = chan
+            .exchange_declare(
+                ExchangeType::Headers,
+                name,
+                ExchangeDeclareOptions {
+                    auto_delete: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        queue
+            .bind_headers(
+                &exchange,
+                HeadersMatch::all()
+                    .header("format", AmqpValue::LongString("pdf".to_string()))
+                    .header("version", AmqpValue::LongInt(2)),
+                FieldTable::new(),
+            )
+            .unwrap();
+
+        let publish_with_format = |body: &[u8], format: &str| {
+            let mut headers = FieldTable::new();
+            headers.insert(
+                "format".to_string(),
+                AmqpValue::LongString(format.to_string()),
+            );
+            headers.insert("version".to_string(), AmqpValue::LongInt(2));
+            exchange
+                .publish(Publish::with_properties(
+                    body,
+                    "",
+                    AmqpProperties::default().with_headers(headers),
+                ))
+                .unwrap();
+        };
+        publish_with_format(b"skipped", "zip");
+        publish_with_format(b"routed", "pdf");
+
+        let get = queue
+            .get_with_backoff(
+                true,
+                GetBackoff::default(),
+                &crossbeam_channel::after(Duration::from_secs(5)),
+            )
+            .unwrap()
+            .expect("message was not routed by headers");
+        assert_eq!(get.delivery.body, b"routed");
+        assert!(queue.get(true).unwrap().is_none());
+    })
+}
+
+#[test]
+fn test_exchange_declare_checked() {
+    let name = "amiquip-test-exchange-declare-checked";
+
+    with_conn(|conn| {
+        let chan = conn.open_channel(None).unwrap();
+        let options = ExchangeDeclareOptions {
+            auto_delete: true,
+            ..ExchangeDeclareOptions::default()
+        };
+        let _exchange = chan
+            .exchange_declare_checked(ExchangeType::Direct, name, options.clone())
+            .unwrap();
+
+        let chan2 = conn.open_channel(None).unwrap();
+        match chan2.exchange_declare_checked(ExchangeType::Fanout, name, options) {
+            Err(Error::ExchangeTypeMismatch {
+                exchange,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(exchange, name);
+                assert_eq!(expected, "fanout");
+                assert_eq!(actual, "direct");
+            }
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("declare with a different type should fail"),
+        }
+    })
+}
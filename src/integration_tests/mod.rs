@@ -2,7 +2,14 @@ use crate::{Channel, Connection};
 use std::env;
 use std::sync::Once;
 
+mod channel;
+mod connection;
+mod consumer;
+mod delivery;
 mod exchange;
+mod queue;
+mod rpc_client;
+mod topology;
 
 static PRINT_WARNING: Once = Once::new();
 
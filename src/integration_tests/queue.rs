@@ -0,0 +1,119 @@
+use super::with_chan;
+use crate::{GetBackoff, Publish, QueueDeclareOptions};
+use std::time::Duration;
+
+#[test]
+fn test_get_with_backoff() {
+    let name = "amiquip-test-get-with-backoff";
+
+    with_chan(|chan| {
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        let backoff = GetBackoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(10),
+            multiplier: 2,
+        };
+
+        // cancelled while the queue is empty
+        let (cancel_tx, cancel_rx) = crossbeam_channel::bounded(1);
+        cancel_tx.send(()).unwrap();
+        assert!(queue
+            .get_with_backoff(true, backoff, &cancel_rx)
+            .unwrap()
+            .is_none());
+
+        chan.basic_publish("", Publish::new(b"hello", name))
+            .unwrap();
+        let get = queue
+            .get_with_backoff(
+                true,
+                backoff,
+                &crossbeam_channel::after(Duration::from_secs(5)),
+            )
+            .unwrap()
+            .expect("published message was not received");
+        assert_eq!(get.delivery.body, b"hello");
+    })
+}
+
+#[test]
+fn test_queue_declare_passive_counts() {
+    let name = "amiquip-test-queue-declare-passive-counts";
+
+    with_chan(|chan| {
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(queue.declared_message_count(), Some(0));
+
+        let confirms = chan.listen_for_publisher_confirms().unwrap();
+        chan.enable_publisher_confirms().unwrap();
+        chan.basic_publish("", Publish::new(b"hello", name))
+            .unwrap();
+        confirms.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let refreshed = queue.declare_passive().unwrap();
+        assert_eq!(refreshed.name(), name);
+        assert_eq!(refreshed.declared_message_count(), Some(1));
+        assert_eq!(refreshed.declared_consumer_count(), Some(0));
+    })
+}
+
+#[test]
+fn test_get_streaming() {
+    let name = "amiquip-test-get-streaming";
+
+    with_chan(|chan| {
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        assert!(queue.get_streaming(true).unwrap().is_none());
+
+        // large enough to span several body frames
+        let body = (0..1_000_000).map(|i| i as u8).collect::<Vec<_>>();
+        let confirms = chan.listen_for_publisher_confirms().unwrap();
+        chan.enable_publisher_confirms().unwrap();
+        chan.basic_publish("", Publish::new(&body, name)).unwrap();
+        chan.basic_publish("", Publish::new(b"", name)).unwrap();
+        for _ in 0..2 {
+            confirms.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        let get = queue.get_streaming(false).unwrap().unwrap();
+        assert_eq!(get.body_size, body.len() as u64);
+        assert_eq!(get.message_count, 1);
+        assert!(get.delivery.body.is_empty());
+        let chunks = get.receiver().iter().collect::<Vec<_>>();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), body);
+        get.ack(&chan).unwrap();
+
+        let get = queue.get_streaming(true).unwrap().unwrap();
+        assert_eq!(get.body_size, 0);
+        assert_eq!(get.message_count, 0);
+        let get = get.into_get().unwrap();
+        assert!(get.body().is_empty());
+
+        assert!(queue.get_streaming(true).unwrap().is_none());
+    })
+}
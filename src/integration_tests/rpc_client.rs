@@ -0,0 +1,77 @@
+use super::with_chan;
+use crate::{AmqpProperties, Error, Exchange, GetBackoff, Publish, QueueDeclareOptions, RpcClient};
+use std::time::Duration;
+
+#[test]
+fn test_rpc_out_of_order_replies() {
+    let name = "amiquip-test-rpc-out-of-order";
+
+    with_chan(|chan| {
+        let exchange = Exchange::direct(chan);
+        let queue = chan
+            .queue_declare(
+                name,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .unwrap();
+        let client = RpcClient::new(chan, Duration::from_secs(5)).unwrap();
+
+        let id1 = client
+            .send(&exchange, name, b"one", AmqpProperties::default())
+            .unwrap();
+        let id2 = client
+            .send(&exchange, name, b"two", AmqpProperties::default())
+            .unwrap();
+        assert_ne!(id1, id2);
+
+        // act as the server: answer the requests in reverse order
+        let mut requests = Vec::new();
+        for _ in 0..2 {
+            let get = queue
+                .get_with_backoff(
+                    true,
+                    GetBackoff::default(),
+                    &crossbeam_channel::after(Duration::from_secs(5)),
+                )
+                .unwrap()
+                .expect("request was not delivered");
+            requests.push(get.delivery);
+        }
+        for request in requests.into_iter().rev() {
+            let reply_to = request.properties.reply_to().clone().unwrap();
+            assert_eq!(reply_to, client.reply_queue());
+            let correlation_id = request.properties.correlation_id().clone().unwrap();
+            let mut body = b"re: ".to_vec();
+            body.extend_from_slice(&request.body);
+            exchange
+                .publish(Publish::with_properties(
+                    &body,
+                    reply_to,
+                    AmqpProperties::default().with_correlation_id(correlation_id),
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(client.wait(&id1).unwrap().body, b"re: one");
+        assert_eq!(client.wait(&id2).unwrap().body, b"re: two");
+    })
+}
+
+#[test]
+fn test_rpc_timeout() {
+    let name = "amiquip-test-rpc-timeout";
+
+    with_chan(|chan| {
+        let exchange = Exchange::direct(chan);
+        let client = RpcClient::new(chan, Duration::from_millis(100)).unwrap();
+
+        match client.call(&exchange, name, b"hello", AmqpProperties::default()) {
+            Err(Error::RpcTimeout { .. }) => (),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("call with no server should time out"),
+        }
+    })
+}
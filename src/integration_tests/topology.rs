@@ -0,0 +1,60 @@
+use super::with_chan;
+use crate::{
+    ExchangeDeclareOptions, ExchangeType, FieldTable, GetBackoff, Publish, QueueDeclareOptions,
+    Topology,
+};
+use std::time::Duration;
+
+#[test]
+fn test_topology_apply() {
+    let source = "amiquip-test-topology-source";
+    let logs = "amiquip-test-topology-logs";
+    let queue = "amiquip-test-topology-errors";
+
+    with_chan(|chan| {
+        let auto_delete = ExchangeDeclareOptions {
+            auto_delete: true,
+            ..ExchangeDeclareOptions::default()
+        };
+        // Recorded out of dependency order; apply must still declare before binding.
+        let topology = Topology::new()
+            .bind_queue(queue, logs, "*.error", FieldTable::new())
+            .bind_exchange(logs, source, "#", FieldTable::new())
+            .queue(
+                queue,
+                QueueDeclareOptions {
+                    exclusive: true,
+                    ..QueueDeclareOptions::default()
+                },
+            )
+            .exchange(ExchangeType::Topic, logs, auto_delete.clone())
+            .exchange(ExchangeType::Fanout, source, auto_delete);
+
+        let applied = topology.apply(chan).unwrap();
+        assert_eq!(applied.exchanges().len(), 2);
+        assert_eq!(applied.queues().len(), 1);
+
+        // applying again is a no-op
+        topology.apply(chan).unwrap();
+
+        let source = applied.exchange(source).unwrap();
+        source
+            .publish(Publish::new(b"disk full", "db.error"))
+            .unwrap();
+        source
+            .publish(Publish::new(b"all good", "db.info"))
+            .unwrap();
+
+        let queue = applied.queue(queue).unwrap();
+        let get = queue
+            .get_with_backoff(
+                true,
+                GetBackoff::default(),
+                &crossbeam_channel::after(Duration::from_secs(5)),
+            )
+            .unwrap()
+            .expect("message was not routed to the queue");
+        assert_eq!(get.delivery.body, b"disk full");
+        assert!(queue.get(true).unwrap().is_none());
+    })
+}
@@ -1,8 +1,16 @@
+use super::io_loop_handle::ChannelAllocator;
 use super::{
-    ConnectionBlockedNotification, ConsumerMessage, CrossbeamReceiver, IoLoopHandle, IoLoopHandle0,
+    Acker, ConnectionBlockedNotification, ConsumerCanceller, ConsumerMessage, CrossbeamReceiver,
+    IoLoopHandle, IoLoopHandle0,
 };
-use crate::serialize::{IntoAmqpClass, TryFromAmqpClass};
-use crate::{Confirm, Get, Result, Return};
+use crate::channel::ServerCloseCallback;
+use crate::consumer::ConsumerConfig;
+use crate::logging::{debug, trace};
+use crate::serialize::{IntoAmqpClass, TryFromAmqpClass, FRAME_OVERHEAD};
+use crate::spans;
+use crate::{Confirm, Confirmation, Error, Get, Result, Return, StreamingGet};
+#[cfg(feature = "raw-frames")]
+use amq_protocol::protocol::basic::AMQPMethod as AmqpBasic;
 use amq_protocol::protocol::basic::Get as AmqpGet;
 use amq_protocol::protocol::basic::{AMQPProperties, Consume};
 use amq_protocol::protocol::channel::AMQPMethod as AmqpChannel;
@@ -12,14 +20,12 @@ use amq_protocol::protocol::channel::Open as ChannelOpen;
 use amq_protocol::protocol::channel::OpenOk as ChannelOpenOk;
 use amq_protocol::protocol::connection::Close as ConnectionClose;
 use amq_protocol::protocol::constants::REPLY_SUCCESS;
+#[cfg(feature = "raw-frames")]
+use amq_protocol::protocol::AMQPClass as AmqpClass;
 use crossbeam_channel::Sender as CrossbeamSender;
-use log::{debug, trace};
 use std::fmt::Debug;
-
-// Each frame has 8 bytes of overhead (7 byte header, 1 byte frame-end), so when
-// we break our frames up into frame_max pieces, we need to account for this many
-// bytes.
-const FRAME_OVERHEAD: usize = 8;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub(crate) struct Channel0Handle {
@@ -58,24 +64,69 @@ impl Channel0Handle {
     }
 
     pub(crate) fn open_channel(&mut self, channel_id: Option<u16>) -> Result<ChannelHandle> {
-        let mut handle = self.handle.allocate_channel(channel_id)?;
+        let handle = self.handle.allocate_channel(channel_id)?;
+        send_channel_open(handle, self.frame_max)
+    }
 
-        debug!("opening channel {}", handle.channel_id());
-        let out_of_band = String::new();
-        let open = AmqpChannel::Open(ChannelOpen { out_of_band });
+    pub(crate) fn channels(&mut self) -> Result<Vec<u16>> {
+        self.handle.list_channels()
+    }
+
+    pub(crate) fn close_all_channels(&mut self) -> Result<()> {
+        debug!("closing all channels");
+        self.handle.close_all_channels()
+    }
 
-        let open_ok = handle.call::<_, ChannelOpenOk>(open)?;
-        trace!("got open-ok: {:?}", open_ok);
-        Ok(ChannelHandle {
-            handle,
+    pub(crate) fn channel_opener(&self) -> ChannelOpener {
+        ChannelOpener {
+            allocator: self.handle.allocator(),
             frame_max: self.frame_max,
-        })
+        }
+    }
+}
+
+fn send_channel_open(mut handle: IoLoopHandle, frame_max: usize) -> Result<ChannelHandle> {
+    debug!("opening channel {}", handle.channel_id());
+    let out_of_band = String::new();
+    let open = AmqpChannel::Open(ChannelOpen { out_of_band });
+
+    let open_ok = handle.call::<_, ChannelOpenOk>(open)?;
+    trace!("got open-ok: {:?}", open_ok);
+    Ok(ChannelHandle {
+        handle,
+        frame_max,
+        next_publish_tag: None,
+    })
+}
+
+// Cloneable handle for opening new channels without access to the Connection; used by channels
+// that reopen themselves after a server close.
+#[derive(Clone)]
+pub(crate) struct ChannelOpener {
+    allocator: Arc<Mutex<ChannelAllocator>>,
+    frame_max: usize,
+}
+
+impl ChannelOpener {
+    pub(crate) fn open(&self) -> Result<ChannelHandle> {
+        let allocated = self
+            .allocator
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .allocate(None);
+        match allocated {
+            Some(handle) => send_channel_open(handle?, self.frame_max),
+            None => Err(Error::EventLoopDropped),
+        }
     }
 }
 
 pub(crate) struct ChannelHandle {
     handle: IoLoopHandle,
     frame_max: usize,
+    // Delivery tag the server will assign to our next publish, once publisher confirms are
+    // enabled. This mirrors the I/O loop's count, which we can't read synchronously.
+    next_publish_tag: Option<u64>,
 }
 
 impl ChannelHandle {
@@ -92,12 +143,60 @@ impl ChannelHandle {
         Ok(())
     }
 
+    pub(crate) fn close_nowait(&mut self) -> Result<()> {
+        let close = AmqpChannel::Close(ChannelClose {
+            reply_code: 0,
+            reply_text: String::new(),
+            class_id: 0,
+            method_id: 0,
+        });
+        debug!("closing channel {} without waiting", self.channel_id());
+        self.handle.close_detached(close)
+    }
+
     #[inline]
     pub(crate) fn channel_id(&self) -> u16 {
         self.handle.channel_id()
     }
 
     #[inline]
+    pub(crate) fn is_open(&mut self) -> bool {
+        self.handle.is_open()
+    }
+
+    #[inline]
+    pub(crate) fn server_close_reason(&mut self) -> Option<(u16, String)> {
+        self.handle.server_close_reason()
+    }
+
+    #[inline]
+    pub(crate) fn reported_close_code(&self) -> Option<u16> {
+        self.handle.reported_close_code()
+    }
+
+    #[inline]
+    pub(crate) fn is_flow_active(&self) -> bool {
+        self.handle.is_flow_active()
+    }
+
+    #[inline]
+    pub(crate) fn cork(&mut self) -> bool {
+        self.handle.cork()
+    }
+
+    #[inline]
+    pub(crate) fn uncork(&mut self) -> Result<()> {
+        self.handle.uncork()
+    }
+
+    #[inline]
+    pub(crate) fn set_server_close_handler(
+        &mut self,
+        handler: Option<ServerCloseCallback>,
+    ) -> Result<()> {
+        self.handle.set_server_close_handler(handler)
+    }
+
     pub(crate) fn set_return_handler(
         &mut self,
         handler: Option<CrossbeamSender<Return>>,
@@ -113,26 +212,151 @@ impl ChannelHandle {
         self.handle.set_pub_confirm_handler(handler)
     }
 
+    #[inline]
+    pub(crate) fn next_publish_tag(&self) -> Option<u64> {
+        self.next_publish_tag
+    }
+
+    // Number of publishes made since enabling confirms that the server has not yet confirmed,
+    // including any the I/O loop has not yet sent.
+    pub(crate) fn pending_confirms(&self) -> u64 {
+        match self.next_publish_tag {
+            Some(next) => (next - 1).saturating_sub(self.handle.confirmed_publishes()),
+            None => 0,
+        }
+    }
+
+    // Start tracking the outcome of the next message published on this channel, returning its
+    // delivery tag, or None if publisher confirms are not enabled.
+    pub(crate) fn track_next_publish(&mut self) -> Option<u64> {
+        let delivery_tag = self.next_publish_tag?;
+        self.handle.track_confirm(delivery_tag);
+        Some(delivery_tag)
+    }
+
+    #[inline]
+    pub(crate) fn untrack_confirm(&self, delivery_tag: u64) {
+        self.handle.untrack_confirm(delivery_tag)
+    }
+
+    // Like track_next_publish, but also remember where the (mandatory) publish is going so a
+    // basic.return for it can be recognized.
+    pub(crate) fn track_next_mandatory_publish(
+        &mut self,
+        exchange: String,
+        routing_key: String,
+    ) -> Option<u64> {
+        let delivery_tag = self.track_next_publish()?;
+        self.handle
+            .track_mandatory(delivery_tag, exchange, routing_key);
+        Some(delivery_tag)
+    }
+
+    #[inline]
+    pub(crate) fn take_returned(&self, delivery_tag: u64) -> Option<(u16, String)> {
+        self.handle.take_returned(delivery_tag)
+    }
+
+    pub(crate) fn add_confirm_callback<F>(&self, delivery_tag: u64, on_confirm: F) -> Result<()>
+    where
+        F: FnOnce(Result<Confirmation>) + Send + 'static,
+    {
+        self.handle
+            .add_confirm_callback(delivery_tag, Box::new(on_confirm))
+    }
+
+    #[inline]
+    pub(crate) fn remove_confirm_callback(&self, delivery_tag: u64) {
+        self.handle.remove_confirm_callback(delivery_tag)
+    }
+
+    #[inline]
+    pub(crate) fn wait_for_confirm(
+        &self,
+        delivery_tag: u64,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        self.handle.wait_for_confirm(delivery_tag, timeout)
+    }
+
+    #[inline]
+    pub(crate) fn record_timeout(&self) {
+        self.handle.record_timeout()
+    }
+
+    fn record_publish(&mut self) {
+        if let Some(tag) = &mut self.next_publish_tag {
+            *tag += 1;
+        }
+    }
+
+    pub(crate) fn expect_pub_confirms(&mut self) -> Result<()> {
+        // Enabling confirms a second time does not reset the server's tags.
+        if self.next_publish_tag.is_none() {
+            self.next_publish_tag = Some(1);
+        }
+        self.handle.expect_pub_confirms()
+    }
+
+    #[inline]
+    pub(crate) fn cancel_consumer_nowait(&mut self, consumer_tag: String) -> Result<()> {
+        self.handle.cancel_consumer_nowait(consumer_tag)
+    }
+
+    #[inline]
+    pub(crate) fn consumer_canceller(&self, consumer_tag: String) -> ConsumerCanceller {
+        self.handle.consumer_canceller(consumer_tag)
+    }
+
+    #[inline]
+    pub(crate) fn acker(&self) -> Acker {
+        self.handle.acker()
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        trace!("flushing pending writes for channel {}", self.channel_id());
+        self.handle.flush()
+    }
+
     pub(crate) fn get(&mut self, get: AmqpGet) -> Result<Option<Get>> {
         self.handle.get(get)
     }
 
+    pub(crate) fn get_streaming(&mut self, get: AmqpGet) -> Result<Option<StreamingGet>> {
+        self.handle.get_streaming(get)
+    }
+
     pub(crate) fn consume(
         &mut self,
         consume: Consume,
+        config: ConsumerConfig,
     ) -> Result<(String, CrossbeamReceiver<ConsumerMessage>)> {
         trace!(
             "starting consumer on channel {}: {:?}",
             self.channel_id(),
             consume
         );
-        self.handle.consume(consume)
+        self.handle.consume(consume, config)
+    }
+
+    pub(crate) fn consume_nowait(
+        &mut self,
+        consume: Consume,
+        config: ConsumerConfig,
+    ) -> Result<CrossbeamReceiver<ConsumerMessage>> {
+        trace!(
+            "starting nowait consumer on channel {}: {:?}",
+            self.channel_id(),
+            consume
+        );
+        self.handle.consume_nowait(consume, config)
     }
 
     pub(crate) fn call<M: IntoAmqpClass + Debug, T: TryFromAmqpClass>(
         &mut self,
         method: M,
     ) -> Result<T> {
+        let _span = spans::rpc(self.channel_id(), &method);
         trace!(
             "calling rpc method on channel {}: {:?}",
             self.channel_id(),
@@ -142,6 +366,7 @@ impl ChannelHandle {
     }
 
     pub(crate) fn call_nowait<M: IntoAmqpClass + Debug>(&mut self, method: M) -> Result<()> {
+        let _span = spans::rpc(self.channel_id(), &method);
         trace!(
             "calling method on channel {} without expecting a response: {:?}",
             self.channel_id(),
@@ -182,6 +407,62 @@ impl ChannelHandle {
             );
             self.handle.send_content_body(content)?;
         }
+        self.record_publish();
+        Ok(())
+    }
+
+    // The I/O loop assigns a delivery tag to every basic.publish it sends, however it was built,
+    // so keep our count of publishes in step with it.
+    #[cfg(feature = "raw-frames")]
+    pub(crate) fn send_raw_method(&mut self, method: AmqpClass) -> Result<()> {
+        let is_publish = match &method {
+            AmqpClass::Basic(AmqpBasic::Publish(_)) => true,
+            _ => false,
+        };
+        self.call_nowait(method)?;
+        if is_publish {
+            self.record_publish();
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "raw-frames")]
+    pub(crate) fn send_raw_content_header(
+        &mut self,
+        class_id: u16,
+        body_len: usize,
+        properties: &AMQPProperties,
+    ) -> Result<()> {
+        self.handle
+            .send_content_header(class_id, body_len, properties)
+    }
+
+    #[cfg(feature = "raw-frames")]
+    pub(crate) fn send_raw_content_body(&mut self, content: &[u8]) -> Result<()> {
+        self.handle.send_content_body(content)
+    }
+
+    pub(crate) fn try_send_method_with_content<M: IntoAmqpClass + Debug>(
+        &mut self,
+        method: M,
+        content: &[u8],
+        class_id: u16,
+        properties: &AMQPProperties,
+    ) -> Result<()> {
+        trace!(
+            "trying to send method with content on channel {} (len = {}): {:?}",
+            self.channel_id(),
+            content.len(),
+            method
+        );
+        self.handle.try_send_method_with_content(
+            method,
+            class_id,
+            content,
+            self.frame_max,
+            properties,
+        )?;
+        self.record_publish();
         Ok(())
     }
 }
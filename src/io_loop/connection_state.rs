@@ -1,10 +1,17 @@
+use crate::consumer::ConsumerConfig;
 use crate::errors::*;
-use crate::{Confirm, ConfirmPayload, Return};
+use crate::logging::{debug, error, trace, warn};
+use crate::metrics::MetricsCounters;
+use crate::spans;
+use crate::{
+    Confirm, ConfirmPayload, Confirmation, Delivery, Return, ServerClose, StreamingDelivery,
+};
 use amq_protocol::frame::AMQPFrame;
 use amq_protocol::protocol::basic::AMQPMethod as AmqpBasic;
 use amq_protocol::protocol::basic::CancelOk;
 use amq_protocol::protocol::channel::AMQPMethod as AmqpChannel;
 use amq_protocol::protocol::channel::CloseOk as ChannelCloseOk;
+use amq_protocol::protocol::channel::FlowOk;
 use amq_protocol::protocol::confirm::AMQPMethod as AmqpConfirm;
 use amq_protocol::protocol::connection::AMQPMethod as AmqpConnection;
 use amq_protocol::protocol::connection::Close as ConnectionClose;
@@ -13,9 +20,11 @@ use amq_protocol::protocol::exchange::AMQPMethod as AmqpExchange;
 use amq_protocol::protocol::queue::AMQPMethod as AmqpQueue;
 use amq_protocol::protocol::{AMQPClass, AMQPHardError};
 use crossbeam_channel::{Sender, TrySendError};
-use log::{debug, error, trace, warn};
 use snafu::OptionExt;
 use std::collections::hash_map::Entry;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::Ordering;
 
 use super::content_collector::CollectorResult;
 use super::{
@@ -34,10 +43,13 @@ pub(super) enum ConnectionState {
 }
 
 fn slot_remove(inner: &mut Inner, channel_id: u16) -> Result<ChannelSlot> {
-    inner
+    let slot = inner
         .chan_slots
         .remove(channel_id)
-        .context(ReceivedFrameWithBogusChannelId { channel_id })
+        .context(ReceivedFrameWithBogusChannelId { channel_id })?;
+    // Messages still awaiting confirmation on a closed channel will never be confirmed.
+    MetricsCounters::sub(&inner.metrics.unconfirmed, slot.unconfirmed.len() as u64);
+    Ok(slot)
 }
 
 fn slot_get(inner: &mut Inner, channel_id: u16) -> Result<&ChannelSlot> {
@@ -54,7 +66,7 @@ fn slot_get_mut(inner: &mut Inner, channel_id: u16) -> Result<&mut ChannelSlot>
         .context(ReceivedFrameWithBogusChannelId { channel_id })
 }
 
-fn send<T: Send + Sync + 'static>(tx: &Sender<T>, item: T) -> Result<()> {
+pub(super) fn send<T: Send + Sync + 'static>(tx: &Sender<T>, item: T) -> Result<()> {
     // See comment in ChannelSlot::new() about the bound size of the control
     // channel. If we're sending to a consumer channel, they are not bounded
     // and will not return Full.
@@ -105,6 +117,126 @@ fn try_send_confirm(slot: &mut ChannelSlot, confirm: Confirm) {
     warn!("discarding returned data {:?}", confirm);
 }
 
+// Give a delivery to one of this channel's consumers the means to ack itself.
+fn attach_acker(slot: &ChannelSlot, consumer_tag: &str, delivery: Delivery) -> Delivery {
+    let no_ack = slot
+        .consumer_configs
+        .get(consumer_tag)
+        .map_or(false, |config| config.no_ack);
+    delivery.with_acker(slot.acker.clone(), no_ack)
+}
+
+// Hand off content that the collector has finished (or, for streaming consumers,
+// partially) collecting to whoever is waiting for it.
+fn dispatch_collected(
+    slot: &mut ChannelSlot,
+    channel_id: u16,
+    collected: CollectorResult,
+) -> Result<()> {
+    match collected {
+        CollectorResult::Delivery((consumer_tag, delivery)) => {
+            let _span = spans::delivery(channel_id, &consumer_tag, delivery.delivery_tag());
+            let delivery = attach_acker(slot, &consumer_tag, delivery);
+            let tx = slot
+                .consumers
+                .get(&consumer_tag)
+                .context(UnknownConsumerTag {
+                    channel_id,
+                    consumer_tag,
+                })?;
+            send(tx, ConsumerMessage::Delivery(delivery))?;
+        }
+        CollectorResult::Streaming {
+            consumer_tag,
+            part,
+            complete,
+        } => {
+            let part = match part {
+                StreamingDelivery::Start {
+                    delivery,
+                    body_size,
+                } => StreamingDelivery::Start {
+                    delivery: attach_acker(slot, &consumer_tag, delivery),
+                    body_size,
+                },
+                other => other,
+            };
+            let tx = slot
+                .consumers
+                .get(&consumer_tag)
+                .context(UnknownConsumerTag {
+                    channel_id,
+                    consumer_tag,
+                })?;
+            send(tx, ConsumerMessage::StreamingDelivery(part))?;
+            if complete {
+                send(
+                    tx,
+                    ConsumerMessage::StreamingDelivery(StreamingDelivery::Complete),
+                )?;
+            }
+        }
+        CollectorResult::Oversized {
+            consumer_tag,
+            delivery,
+            body_size,
+        } => {
+            let delivery = attach_acker(slot, &consumer_tag, delivery);
+            let tx = slot
+                .consumers
+                .get(&consumer_tag)
+                .context(UnknownConsumerTag {
+                    channel_id,
+                    consumer_tag,
+                })?;
+            send(
+                tx,
+                ConsumerMessage::OversizedDelivery {
+                    delivery,
+                    body_size,
+                },
+            )?;
+        }
+        CollectorResult::Return(return_) => {
+            slot.shared.record_return(&return_);
+            try_send_return(slot, return_);
+        }
+        CollectorResult::Get(get) => {
+            send(&slot.tx, Ok(ChannelMessage::GetOk(Box::new(Some(get)))))?;
+        }
+        CollectorResult::StreamingGetStart { get, body_size } => {
+            if body_size == 0 {
+                // Dropping the body sender tells the client there is nothing more to read.
+                slot.streaming_get = None;
+            }
+            send(
+                &slot.tx,
+                Ok(ChannelMessage::StreamingGetOk(Box::new(get), body_size)),
+            )?;
+        }
+        CollectorResult::StreamingGetBody { body, complete } => {
+            // The client may have dropped its StreamingGet without reading the body; that's
+            // not an error, we just have nowhere to send the rest of it.
+            if let Some(tx) = &slot.streaming_get {
+                let _ = tx.send(body);
+            }
+            if complete {
+                slot.streaming_get = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A confirm on a channel that never enabled publisher confirms is a protocol
+// violation; we can't pass it along as an RPC reply, so the connection is closed.
+fn unexpected_confirm_text<T: fmt::Debug>(channel_id: u16, confirm: &T) -> String {
+    format!(
+        "received publisher confirm on channel {} without confirms enabled: {:?}",
+        channel_id, confirm
+    )
+}
+
 // When we set up a blocked connection listener, it's just a crossbeam channel. If it gets
 // dropped, we don't want to error; just start discarding blocked notifications.
 fn try_send_blocked(slot: &mut Channel0Slot, note: ConnectionBlockedNotification) {
@@ -171,6 +303,8 @@ impl ConnectionState {
                 *self = ConnectionState::ServerClosing(close);
 
                 for (_, mut slot) in inner.chan_slots.drain() {
+                    let unconfirmed = slot.unconfirmed.len() as u64;
+                    MetricsCounters::sub(&inner.metrics.unconfirmed, unconfirmed);
                     send(&slot.tx, Err(make_err()))?;
                     for (_, tx) in slot.consumers.drain() {
                         send(&tx, ConsumerMessage::ServerClosedConnection(make_err()))?;
@@ -189,6 +323,8 @@ impl ConnectionState {
                 *self = ConnectionState::ClientClosed;
 
                 for (_, mut slot) in inner.chan_slots.drain() {
+                    let unconfirmed = slot.unconfirmed.len() as u64;
+                    MetricsCounters::sub(&inner.metrics.unconfirmed, unconfirmed);
                     send(&slot.tx, Err(Error::ClientClosedConnection))?;
                     for (_, tx) in slot.consumers.drain() {
                         send(&tx, ConsumerMessage::ClientClosedConnection)?;
@@ -230,6 +366,15 @@ impl ConnectionState {
                 for (_, tx) in slot.consumers.drain() {
                     send(&tx, ConsumerMessage::ServerClosedChannel(make_err()))?;
                 }
+                if let Some(handler) = &slot.server_close_handler {
+                    handler(ServerClose {
+                        channel_id: n,
+                        reply_code: close.reply_code,
+                        reply_text: close.reply_text.clone(),
+                        class_id: close.class_id,
+                        method_id: close.method_id,
+                    });
+                }
                 inner.push_method(n, AmqpChannel::CloseOk(ChannelCloseOk {}));
             }
             // Server ack for client-initiated channel close.
@@ -240,17 +385,38 @@ impl ConnectionState {
                 // an error to get a CloseOk for a nonexistent slot, since the server is
                 // confirming that a channel is gone (and we don't have it anymore anyway).
                 if let Ok(mut slot) = slot_remove(inner, n) {
-                    send(
-                        &slot.tx,
+                    // If the connection closed this channel, its handle isn't waiting for
+                    // the close-ok; tell it the channel is gone instead.
+                    let message = if slot.close_notify.is_some() {
+                        Err(Error::ClientClosedChannel)
+                    } else {
                         Ok(ChannelMessage::Method(AMQPClass::Channel(
                             AmqpChannel::CloseOk(close_ok),
-                        ))),
-                    )?;
+                        )))
+                    };
+                    send(&slot.tx, message)?;
                     for (_, tx) in slot.consumers.drain() {
                         send(&tx, ConsumerMessage::ClientClosedChannel)?;
                     }
                 }
             }
+            // Server pausing or resuming content on a channel.
+            AMQPFrame::Method(n, AMQPClass::Channel(AmqpChannel::Flow(flow))) => {
+                let slot = slot_get(inner, n)?;
+                slot.shared.flow_active.store(flow.active, Ordering::SeqCst);
+                inner.push_method(
+                    n,
+                    AmqpChannel::FlowOk(FlowOk {
+                        active: flow.active,
+                    }),
+                );
+                if flow.active {
+                    debug!("server resumed channel {}", n);
+                    inner.release_held_publishes(n);
+                } else {
+                    warn!("server paused channel {}", n);
+                }
+            }
             // Server ack for consume request.
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::ConsumeOk(consume_ok))) => {
                 let consumer_tag = consume_ok.consumer_tag;
@@ -264,6 +430,9 @@ impl ConnectionState {
                         .fail();
                     }
                     Entry::Vacant(entry) => {
+                        let config =
+                            mem::replace(&mut slot.next_consumer_config, ConsumerConfig::default());
+                        slot.consumer_configs.insert(consumer_tag.clone(), config);
                         let (tx, rx) = crossbeam_channel::unbounded();
                         entry.insert(tx);
                         send(&slot.tx, Ok(ChannelMessage::ConsumeOk(consumer_tag, rx)))?;
@@ -274,6 +443,7 @@ impl ConnectionState {
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::Cancel(cancel))) => {
                 let consumer_tag = cancel.consumer_tag;
                 let slot = slot_get_mut(inner, n)?;
+                slot.consumer_configs.remove(&consumer_tag);
                 if let Some(tx) = slot.consumers.remove(&consumer_tag) {
                     send(&tx, ConsumerMessage::ServerCancelled)?;
                 }
@@ -285,12 +455,17 @@ impl ConnectionState {
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::CancelOk(cancel_ok))) => {
                 let slot = slot_get_mut(inner, n)?;
                 let consumer = slot.consumers.remove(&cancel_ok.consumer_tag);
-                send(
-                    &slot.tx,
-                    Ok(ChannelMessage::Method(AMQPClass::Basic(
-                        AmqpBasic::CancelOk(cancel_ok),
-                    ))),
-                )?;
+                slot.consumer_configs.remove(&cancel_ok.consumer_tag);
+                // Cancels sent on behalf of a ConsumerCanceller have no channel RPC
+                // waiting on them.
+                if !slot.async_cancels.remove(&cancel_ok.consumer_tag) {
+                    send(
+                        &slot.tx,
+                        Ok(ChannelMessage::Method(AMQPClass::Basic(
+                            AmqpBasic::CancelOk(cancel_ok),
+                        ))),
+                    )?;
+                }
                 if let Some(tx) = consumer {
                     send(&tx, ConsumerMessage::ClientCancelled)?;
                 }
@@ -298,7 +473,12 @@ impl ConnectionState {
             // Server beginning delivery of content to a consumer.
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::Deliver(deliver))) => {
                 let slot = slot_get_mut(inner, n)?;
-                slot.collector.collect_deliver(deliver)?;
+                let config = slot
+                    .consumer_configs
+                    .get(&deliver.consumer_tag)
+                    .cloned()
+                    .unwrap_or_default();
+                slot.collector.collect_deliver(deliver, config)?;
             }
             // Server beginning return of undeliverable content.
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::Return(return_))) => {
@@ -308,30 +488,48 @@ impl ConnectionState {
             // Server ack for get (message incoming).
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::GetOk(get_ok))) => {
                 let slot = slot_get_mut(inner, n)?;
-                slot.collector.collect_get(get_ok)?;
+                let streaming = slot.streaming_get.is_some();
+                slot.collector.collect_get(get_ok, streaming)?;
             }
             // Server ack for get (no message).
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::GetEmpty(_))) => {
-                let slot = slot_get(inner, n)?;
+                let slot = slot_get_mut(inner, n)?;
+                slot.streaming_get = None;
                 send(&slot.tx, Ok(ChannelMessage::GetOk(Box::new(None))))?;
             }
             // Server ack for publish (publisher confirmation)
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::Ack(ack))) => {
                 let slot = slot_get_mut(inner, n)?;
-                let confirm = ConfirmPayload {
-                    delivery_tag: ack.delivery_tag,
-                    multiple: ack.multiple,
-                };
-                try_send_confirm(slot, Confirm::Ack(confirm));
+                if slot.pub_confirms_enabled {
+                    let confirmed =
+                        slot.record_confirm(ack.delivery_tag, ack.multiple, Confirmation::Ack);
+                    let confirm = ConfirmPayload {
+                        delivery_tag: ack.delivery_tag,
+                        multiple: ack.multiple,
+                    };
+                    try_send_confirm(slot, Confirm::Ack(confirm));
+                    MetricsCounters::sub(&inner.metrics.unconfirmed, confirmed);
+                } else {
+                    let text = unexpected_confirm_text(n, &ack);
+                    self.client_exception(inner, AMQPHardError::UNEXPECTEDFRAME, text)?;
+                }
             }
             // Server nack for publish (publisher confirmation)
             AMQPFrame::Method(n, AMQPClass::Basic(AmqpBasic::Nack(nack))) => {
                 let slot = slot_get_mut(inner, n)?;
-                let confirm = ConfirmPayload {
-                    delivery_tag: nack.delivery_tag,
-                    multiple: nack.multiple,
-                };
-                try_send_confirm(slot, Confirm::Nack(confirm));
+                if slot.pub_confirms_enabled {
+                    let confirmed =
+                        slot.record_confirm(nack.delivery_tag, nack.multiple, Confirmation::Nack);
+                    let confirm = ConfirmPayload {
+                        delivery_tag: nack.delivery_tag,
+                        multiple: nack.multiple,
+                    };
+                    try_send_confirm(slot, Confirm::Nack(confirm));
+                    MetricsCounters::sub(&inner.metrics.unconfirmed, confirmed);
+                } else {
+                    let text = unexpected_confirm_text(n, &nack);
+                    self.client_exception(inner, AMQPHardError::UNEXPECTEDFRAME, text)?;
+                }
             }
             // Generic ack messages we send back to the caller.
             AMQPFrame::Method(n, method @ AMQPClass::Basic(AmqpBasic::QosOk(_)))
@@ -357,7 +555,6 @@ impl ConnectionState {
             }
             // Methods we do not handle
             AMQPFrame::Method(n, method @ AMQPClass::Access(_))
-            | AMQPFrame::Method(n, method @ AMQPClass::Channel(AmqpChannel::Flow(_)))
             | AMQPFrame::Method(n, method @ AMQPClass::Channel(AmqpChannel::FlowOk(_)))
             | AMQPFrame::Method(n, method @ AMQPClass::Tx(_)) => {
                 let text = format!(
@@ -393,23 +590,10 @@ impl ConnectionState {
             AMQPFrame::Header(n, _, header) => {
                 let slot = slot_get_mut(inner, n)?;
                 if let Some(collected) = slot.collector.collect_header(*header)? {
-                    match collected {
-                        CollectorResult::Delivery((consumer_tag, delivery)) => {
-                            let tx =
-                                slot.consumers
-                                    .get(&consumer_tag)
-                                    .context(UnknownConsumerTag {
-                                        channel_id: n,
-                                        consumer_tag,
-                                    })?;
-                            send(tx, ConsumerMessage::Delivery(delivery))?;
-                        }
-                        CollectorResult::Return(return_) => {
-                            try_send_return(slot, return_);
-                        }
-                        CollectorResult::Get(get) => {
-                            send(&slot.tx, Ok(ChannelMessage::GetOk(Box::new(Some(get)))))?;
-                        }
+                    let is_delivery = collected.is_delivery();
+                    dispatch_collected(slot, n, collected)?;
+                    if is_delivery {
+                        MetricsCounters::add(&inner.metrics.deliveries_received, 1);
                     }
                 }
             }
@@ -417,23 +601,10 @@ impl ConnectionState {
             AMQPFrame::Body(n, body) => {
                 let slot = slot_get_mut(inner, n)?;
                 if let Some(collected) = slot.collector.collect_body(body)? {
-                    match collected {
-                        CollectorResult::Delivery((consumer_tag, delivery)) => {
-                            let tx =
-                                slot.consumers
-                                    .get(&consumer_tag)
-                                    .context(UnknownConsumerTag {
-                                        channel_id: n,
-                                        consumer_tag,
-                                    })?;
-                            send(tx, ConsumerMessage::Delivery(delivery))?;
-                        }
-                        CollectorResult::Return(return_) => {
-                            try_send_return(slot, return_);
-                        }
-                        CollectorResult::Get(get) => {
-                            send(&slot.tx, Ok(ChannelMessage::GetOk(Box::new(Some(get)))))?;
-                        }
+                    let is_delivery = collected.is_delivery();
+                    dispatch_collected(slot, n, collected)?;
+                    if is_delivery {
+                        MetricsCounters::add(&inner.metrics.deliveries_received, 1);
                     }
                 }
             }
@@ -441,3 +612,312 @@ impl ConnectionState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{HeartbeatTimers, IoLoopHandle, IoLoopHandle0, IoLoopMessage, Watchdog};
+    use super::*;
+    use crate::serialize::OutputBuffer;
+    use amq_protocol::protocol::basic::{AMQPProperties, Ack, Nack, Publish};
+    use amq_protocol::protocol::channel::Flow;
+    use std::sync::Arc;
+
+    fn steady_with_channel(
+        channel_id: u16,
+    ) -> (ConnectionState, Inner, IoLoopHandle0, IoLoopHandle) {
+        steady_with_watchdog(channel_id, Watchdog::new(None).0)
+    }
+
+    fn steady_with_watchdog(
+        channel_id: u16,
+        watchdog: Watchdog,
+    ) -> (ConnectionState, Inner, IoLoopHandle0, IoLoopHandle) {
+        let mut inner = Inner::new(HeartbeatTimers::default(), 16, watchdog);
+        let (ch0_slot, ch0_handle) = Channel0Slot::new(16, &inner.metrics, &inner.watchdog);
+        let metrics = Arc::clone(&inner.metrics);
+        let watchdog = Arc::clone(&inner.watchdog);
+        let handle = inner
+            .chan_slots
+            .insert(Some(channel_id), |channel_id| {
+                Ok(ChannelSlot::new(16, channel_id, &metrics, &watchdog))
+            })
+            .unwrap();
+        (ConnectionState::Steady(ch0_slot), inner, ch0_handle, handle)
+    }
+
+    fn ack(delivery_tag: u64) -> AMQPFrame {
+        AMQPFrame::Method(
+            1,
+            AMQPClass::Basic(AmqpBasic::Ack(Ack {
+                delivery_tag,
+                multiple: false,
+            })),
+        )
+    }
+
+    #[test]
+    fn confirm_on_non_confirming_channel() {
+        let (mut state, mut inner, _ch0_handle, _handle) = steady_with_channel(1);
+
+        state.process(&mut inner, ack(1)).unwrap();
+        match state {
+            ConnectionState::ClientException => (),
+            _ => panic!("expected client exception"),
+        }
+        assert!(inner.are_writes_sealed());
+
+        // the connection is closing; further confirms are ignored
+        let nack = AMQPFrame::Method(
+            1,
+            AMQPClass::Basic(AmqpBasic::Nack(Nack {
+                delivery_tag: 2,
+                multiple: false,
+                requeue: false,
+            })),
+        );
+        state.process(&mut inner, nack).unwrap();
+    }
+
+    #[test]
+    fn confirm_on_confirming_channel() {
+        let (mut state, mut inner, _ch0_handle, _handle) = steady_with_channel(1);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        {
+            let slot = inner.chan_slots.get_mut(1).unwrap();
+            slot.pub_confirms_enabled = true;
+            slot.pub_confirm_handler = Some(tx);
+        }
+
+        state.process(&mut inner, ack(1)).unwrap();
+        match state {
+            ConnectionState::Steady(_) => (),
+            _ => panic!("expected connection to remain open"),
+        }
+        match rx.try_recv().unwrap() {
+            Confirm::Ack(payload) => assert_eq!(payload.delivery_tag, 1),
+            other => panic!("unexpected confirm {:?}", other),
+        }
+    }
+
+    #[test]
+    fn confirms_are_shared_with_handle() {
+        let (mut state, mut inner, _ch0_handle, handle) = steady_with_channel(1);
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        {
+            let slot = inner.chan_slots.get_mut(1).unwrap();
+            slot.pub_confirms_enabled = true;
+            slot.pub_confirm_handler = Some(tx);
+            slot.unconfirmed.extend(1..=3);
+        }
+        assert_eq!(handle.confirmed_publishes(), 0);
+
+        state.process(&mut inner, ack(2)).unwrap();
+        assert_eq!(handle.confirmed_publishes(), 1);
+
+        // an ack for a tag we are not waiting on does not change the count
+        state.process(&mut inner, ack(2)).unwrap();
+        assert_eq!(handle.confirmed_publishes(), 1);
+
+        let multiple = AMQPFrame::Method(
+            1,
+            AMQPClass::Basic(AmqpBasic::Ack(Ack {
+                delivery_tag: 3,
+                multiple: true,
+            })),
+        );
+        state.process(&mut inner, multiple).unwrap();
+        assert_eq!(handle.confirmed_publishes(), 3);
+    }
+
+    #[test]
+    fn wait_for_tracked_confirms() {
+        use std::time::Duration;
+
+        let (mut state, mut inner, _ch0_handle, handle) = steady_with_channel(1);
+        {
+            let slot = inner.chan_slots.get_mut(1).unwrap();
+            slot.pub_confirms_enabled = true;
+            slot.unconfirmed.extend(1..=4);
+        }
+        for tag in 1..=4 {
+            handle.track_confirm(tag);
+        }
+
+        let nack = AMQPFrame::Method(
+            1,
+            AMQPClass::Basic(AmqpBasic::Nack(Nack {
+                delivery_tag: 2,
+                multiple: true,
+                requeue: false,
+            })),
+        );
+        state.process(&mut inner, nack).unwrap();
+        state.process(&mut inner, ack(4)).unwrap();
+
+        let wait = |tag| handle.wait_for_confirm(tag, Duration::from_millis(0));
+        assert_eq!(wait(4).unwrap(), Confirmation::Ack);
+        assert_eq!(wait(1).unwrap(), Confirmation::Nack);
+        assert_eq!(wait(2).unwrap(), Confirmation::Nack);
+        match wait(2) {
+            Err(Error::UntrackedDeliveryTag { delivery_tag: 2 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match wait(3) {
+            Err(Error::ConfirmTimeout { delivery_tag: 3 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        drop(inner);
+        match wait(3) {
+            Err(Error::EventLoopDropped) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn confirm_timeouts_count_toward_watchdog() {
+        use std::time::Duration;
+
+        let (watchdog, _registration) = Watchdog::new(Some(10));
+        let (_state, mut inner, _ch0_handle, handle) = steady_with_watchdog(1, watchdog);
+        {
+            let slot = inner.chan_slots.get_mut(1).unwrap();
+            slot.pub_confirms_enabled = true;
+            slot.unconfirmed.insert(1);
+        }
+        handle.track_confirm(1);
+        let wait = |millis| handle.wait_for_confirm(1, Duration::from_millis(millis));
+
+        assert!(wait(1).is_err());
+        assert_eq!(inner.watchdog.consecutive_timeouts(), 1);
+
+        // polling doesn't count as a timeout
+        assert!(wait(0).is_err());
+        assert_eq!(inner.watchdog.consecutive_timeouts(), 1);
+    }
+
+    #[test]
+    fn confirm_callbacks_fire_in_tag_order() {
+        let (mut state, mut inner, _ch0_handle, handle) = steady_with_channel(1);
+        {
+            let slot = inner.chan_slots.get_mut(1).unwrap();
+            slot.pub_confirms_enabled = true;
+            slot.unconfirmed.extend(1..=3);
+        }
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for tag in 1..=3 {
+            let tx = tx.clone();
+            handle
+                .add_confirm_callback(
+                    tag,
+                    Box::new(move |outcome: Result<Confirmation>| {
+                        tx.send((tag, outcome.ok())).unwrap()
+                    }),
+                )
+                .unwrap();
+        }
+
+        // 2 is confirmed first, but its callback waits for 1's
+        state.process(&mut inner, ack(2)).unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let nack = AMQPFrame::Method(
+            1,
+            AMQPClass::Basic(AmqpBasic::Nack(Nack {
+                delivery_tag: 1,
+                multiple: false,
+                requeue: false,
+            })),
+        );
+        state.process(&mut inner, nack).unwrap();
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![(1, Some(Confirmation::Nack)), (2, Some(Confirmation::Ack))]
+        );
+
+        // callbacks still pending when the channel goes away are failed
+        drop(inner);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![(3, None)]);
+        assert!(handle
+            .add_confirm_callback(4, Box::new(|_: Result<Confirmation>| ()))
+            .is_err());
+    }
+
+    #[test]
+    fn flow_off_and_on() {
+        let (mut state, mut inner, _ch0_handle, handle) = steady_with_channel(1);
+        let flow =
+            |active| AMQPFrame::Method(1, AMQPClass::Channel(AmqpChannel::Flow(Flow { active })));
+        let publish = |body: &[u8]| {
+            let mut buf = OutputBuffer::empty();
+            buf.push_method(
+                1,
+                AmqpBasic::Publish(Publish {
+                    ticket: 0,
+                    exchange: String::new(),
+                    routing_key: "q".to_string(),
+                    mandatory: false,
+                    immediate: false,
+                }),
+            );
+            buf.push_content_header(1, 60, body.len(), &AMQPProperties::default());
+            buf
+        };
+        let body = |body: &[u8]| {
+            let mut buf = OutputBuffer::empty();
+            buf.push_content_body(1, body);
+            buf
+        };
+        let send = |inner: &mut Inner, buf| {
+            inner
+                .process_channel_message(1, IoLoopMessage::Send(buf))
+                .unwrap()
+        };
+        // discard the protocol header so we only see our reply below
+        inner.outbuf.clear();
+
+        state.process(&mut inner, flow(false)).unwrap();
+        assert!(!handle.is_flow_active());
+        assert!(!inner.chan_slots.get(1).unwrap().is_flow_active());
+        assert!(inner.has_data_to_write());
+        inner.outbuf.clear();
+
+        // a repeated pause is acknowledged but otherwise a no-op
+        state.process(&mut inner, flow(false)).unwrap();
+        assert!(!handle.is_flow_active());
+        assert!(inner.has_data_to_write());
+        inner.outbuf.clear();
+
+        // publishes and their content are held back, but everything else still goes out
+        send(&mut inner, publish(b"hello"));
+        send(&mut inner, body(b"hello"));
+        assert!(!inner.has_data_to_write());
+        let mut ack = OutputBuffer::empty();
+        ack.push_method(
+            1,
+            AmqpBasic::Ack(Ack {
+                delivery_tag: 1,
+                multiple: false,
+            }),
+        );
+        let ack_len = ack.len();
+        send(&mut inner, ack);
+        assert_eq!(inner.outbuf.len(), ack_len);
+        inner.outbuf.clear();
+
+        // the start of a publish that began while paused is released with the rest
+        send(&mut inner, publish(b"world"));
+        let held_len = inner.chan_slots.get(1).unwrap().held_publishes.len();
+        state.process(&mut inner, flow(true)).unwrap();
+        assert!(handle.is_flow_active());
+        assert!(inner.chan_slots.get(1).unwrap().held_publishes.is_empty());
+        assert!(inner.outbuf.len() > held_len);
+        let released_len = inner.outbuf.len();
+        send(&mut inner, body(b"world"));
+        assert!(inner.outbuf.len() > released_len);
+        match state {
+            ConnectionState::Steady(_) => (),
+            _ => panic!("expected connection to remain open"),
+        }
+    }
+}
@@ -1,19 +1,64 @@
+use crate::consumer::ConsumerConfig;
 use crate::errors::*;
-use crate::{AmqpProperties, Delivery, Get, Return};
+use crate::{AmqpProperties, Delivery, Get, Return, StreamingDelivery};
 use amq_protocol::frame::AMQPContentHeader;
 use amq_protocol::protocol::basic::Deliver;
 use amq_protocol::protocol::basic::GetOk as AmqpGetOk;
 use amq_protocol::protocol::basic::Return as AmqpReturn;
 
+// Upper bound on how much we'll preallocate for a message body based on the size
+// claimed by the server in a content header; beyond this, the buffer grows as body
+// frames actually arrive.
+const MAX_BODY_PREALLOCATION: usize = 1 << 20;
+
 pub(super) struct ContentCollector {
     channel_id: u16,
     kind: Option<Kind>,
+    max_body_size: Option<u64>,
 }
 
 pub(super) enum CollectorResult {
     Delivery((String, Delivery)),
     Return(Return),
     Get(Get),
+    Streaming {
+        consumer_tag: String,
+        part: StreamingDelivery,
+        complete: bool,
+    },
+    Oversized {
+        consumer_tag: String,
+        delivery: Delivery,
+        body_size: u64,
+    },
+    // The start of a streamed get; `get` has an empty body. If body_size is 0, no
+    // StreamingGetBody will follow.
+    StreamingGetStart {
+        get: Get,
+        body_size: u64,
+    },
+    StreamingGetBody {
+        body: Vec<u8>,
+        complete: bool,
+    },
+}
+
+impl CollectorResult {
+    // True if this result represents the arrival of a new message from the server (as
+    // opposed to a return, or a later piece of a streamed message).
+    pub(super) fn is_delivery(&self) -> bool {
+        match self {
+            CollectorResult::Delivery(_)
+            | CollectorResult::Get(_)
+            | CollectorResult::Oversized { .. }
+            | CollectorResult::StreamingGetStart { .. } => true,
+            CollectorResult::Streaming { part, .. } => match part {
+                StreamingDelivery::Start { .. } => true,
+                StreamingDelivery::Body(_) | StreamingDelivery::Complete => false,
+            },
+            CollectorResult::Return(_) | CollectorResult::StreamingGetBody { .. } => false,
+        }
+    }
 }
 
 impl ContentCollector {
@@ -21,13 +66,23 @@ impl ContentCollector {
         ContentCollector {
             channel_id,
             kind: None,
+            max_body_size: None,
         }
     }
 
-    pub(super) fn collect_deliver(&mut self, deliver: Deliver) -> Result<()> {
+    pub(super) fn collect_deliver(
+        &mut self,
+        deliver: Deliver,
+        config: ConsumerConfig,
+    ) -> Result<()> {
         match self.kind.take() {
             None => {
-                self.kind = Some(Kind::Delivery(State::Start(deliver)));
+                self.kind = Some(if config.streaming {
+                    Kind::StreamingDelivery(StreamingState::Start(deliver))
+                } else {
+                    Kind::Delivery(State::Start(deliver))
+                });
+                self.max_body_size = config.max_body_size;
                 Ok(())
             }
             Some(_) => FrameUnexpected.fail(),
@@ -44,10 +99,14 @@ impl ContentCollector {
         }
     }
 
-    pub(super) fn collect_get(&mut self, get_ok: AmqpGetOk) -> Result<()> {
+    pub(super) fn collect_get(&mut self, get_ok: AmqpGetOk, streaming: bool) -> Result<()> {
         match self.kind.take() {
             None => {
-                self.kind = Some(Kind::Get(State::Start(get_ok)));
+                self.kind = Some(if streaming {
+                    Kind::StreamingGet(StreamingGetState::Start(get_ok))
+                } else {
+                    Kind::Get(State::Start(get_ok))
+                });
                 Ok(())
             }
             Some(_) => FrameUnexpected.fail(),
@@ -58,6 +117,11 @@ impl ContentCollector {
         &mut self,
         header: AMQPContentHeader,
     ) -> Result<Option<CollectorResult>> {
+        if let Some(max_body_size) = self.max_body_size.take() {
+            if header.body_size > max_body_size {
+                return self.collect_oversized_header(header);
+            }
+        }
         match self.kind.take() {
             Some(Kind::Delivery(state)) => match state.collect_header(self.channel_id, header)? {
                 Content::Done((tag, delivery)) => {
@@ -89,10 +153,41 @@ impl ContentCollector {
                     Ok(None)
                 }
             },
-            None => FrameUnexpected.fail(),
+            Some(Kind::StreamingDelivery(state)) => {
+                let (state, result) = state.collect_header(self.channel_id, header)?;
+                self.kind = state.map(Kind::StreamingDelivery);
+                Ok(Some(result))
+            }
+            Some(Kind::StreamingGet(state)) => {
+                let (state, result) = state.collect_header(self.channel_id, header)?;
+                self.kind = state.map(Kind::StreamingGet);
+                Ok(Some(result))
+            }
+            Some(Kind::Discard(_)) | None => FrameUnexpected.fail(),
         }
     }
 
+    fn collect_oversized_header(
+        &mut self,
+        header: AMQPContentHeader,
+    ) -> Result<Option<CollectorResult>> {
+        let deliver = match self.kind.take() {
+            Some(Kind::Delivery(State::Start(deliver)))
+            | Some(Kind::StreamingDelivery(StreamingState::Start(deliver))) => deliver,
+            _ => return FrameUnexpected.fail(),
+        };
+        let body_size = header.body_size;
+        let (consumer_tag, delivery) =
+            Delivery::new(self.channel_id, deliver, Vec::new(), header.properties);
+        // We only get here if body_size exceeds the max, so it's at least 1.
+        self.kind = Some(Kind::Discard(body_size));
+        Ok(Some(CollectorResult::Oversized {
+            consumer_tag,
+            delivery,
+            body_size,
+        }))
+    }
+
     pub(super) fn collect_body(&mut self, body: Vec<u8>) -> Result<Option<CollectorResult>> {
         match self.kind.take() {
             Some(Kind::Delivery(state)) => match state.collect_body(self.channel_id, body)? {
@@ -125,6 +220,26 @@ impl ContentCollector {
                     Ok(None)
                 }
             },
+            Some(Kind::StreamingDelivery(state)) => {
+                let (state, result) = state.collect_body(body)?;
+                self.kind = state.map(Kind::StreamingDelivery);
+                Ok(Some(result))
+            }
+            Some(Kind::StreamingGet(state)) => {
+                let (state, result) = state.collect_body(body)?;
+                self.kind = state.map(Kind::StreamingGet);
+                Ok(Some(result))
+            }
+            Some(Kind::Discard(remaining)) => {
+                let len = body.len() as u64;
+                if len > remaining {
+                    return FrameUnexpected.fail();
+                }
+                if len < remaining {
+                    self.kind = Some(Kind::Discard(remaining - len));
+                }
+                Ok(None)
+            }
             None => FrameUnexpected.fail(),
         }
     }
@@ -134,6 +249,10 @@ enum Kind {
     Delivery(State<Delivery>),
     Return(State<Return>),
     Get(State<Get>),
+    StreamingDelivery(StreamingState),
+    StreamingGet(StreamingGetState),
+    // Remaining body bytes of an oversized delivery that we're throwing away.
+    Discard(u64),
 }
 
 trait ContentType {
@@ -220,7 +339,8 @@ impl<T: ContentType> State<T> {
                         header.properties,
                     )))
                 } else {
-                    let buf = Vec::with_capacity(header.body_size as usize);
+                    let capacity = (header.body_size as usize).min(MAX_BODY_PREALLOCATION);
+                    let buf = Vec::with_capacity(capacity);
                     Ok(Content::NeedMore(State::Body(start, header, buf)))
                 }
             }
@@ -250,3 +370,128 @@ impl<T: ContentType> State<T> {
         }
     }
 }
+
+// Unlike State, which buffers the entire body, StreamingState hands each body frame
+// back to the caller as soon as it arrives and only remembers how much is left.
+enum StreamingState {
+    Start(Deliver),
+    Body {
+        consumer_tag: String,
+        remaining: usize,
+    },
+}
+
+impl StreamingState {
+    fn collect_header(
+        self,
+        channel_id: u16,
+        header: AMQPContentHeader,
+    ) -> Result<(Option<StreamingState>, CollectorResult)> {
+        match self {
+            StreamingState::Start(deliver) => {
+                let body_size = header.body_size;
+                let (consumer_tag, delivery) =
+                    Delivery::new(channel_id, deliver, Vec::new(), header.properties);
+                let part = StreamingDelivery::Start {
+                    delivery,
+                    body_size,
+                };
+                let (state, complete) = if body_size == 0 {
+                    (None, true)
+                } else {
+                    let state = StreamingState::Body {
+                        consumer_tag: consumer_tag.clone(),
+                        remaining: body_size as usize,
+                    };
+                    (Some(state), false)
+                };
+                let result = CollectorResult::Streaming {
+                    consumer_tag,
+                    part,
+                    complete,
+                };
+                Ok((state, result))
+            }
+            StreamingState::Body { .. } => FrameUnexpected.fail(),
+        }
+    }
+
+    fn collect_body(self, body: Vec<u8>) -> Result<(Option<StreamingState>, CollectorResult)> {
+        match self {
+            StreamingState::Body {
+                consumer_tag,
+                remaining,
+            } => {
+                if body.len() > remaining {
+                    return FrameUnexpected.fail();
+                }
+                let remaining = remaining - body.len();
+                let complete = remaining == 0;
+                let state = if complete {
+                    None
+                } else {
+                    Some(StreamingState::Body {
+                        consumer_tag: consumer_tag.clone(),
+                        remaining,
+                    })
+                };
+                let result = CollectorResult::Streaming {
+                    consumer_tag,
+                    part: StreamingDelivery::Body(body),
+                    complete,
+                };
+                Ok((state, result))
+            }
+            StreamingState::Start(_) => FrameUnexpected.fail(),
+        }
+    }
+}
+
+// The get-ok counterpart of StreamingState.
+enum StreamingGetState {
+    Start(AmqpGetOk),
+    Body { remaining: usize },
+}
+
+impl StreamingGetState {
+    fn collect_header(
+        self,
+        channel_id: u16,
+        header: AMQPContentHeader,
+    ) -> Result<(Option<StreamingGetState>, CollectorResult)> {
+        match self {
+            StreamingGetState::Start(get_ok) => {
+                let body_size = header.body_size;
+                let get = Get::new(channel_id, get_ok, Vec::new(), header.properties);
+                let state = if body_size == 0 {
+                    None
+                } else {
+                    Some(StreamingGetState::Body {
+                        remaining: body_size as usize,
+                    })
+                };
+                Ok((state, CollectorResult::StreamingGetStart { get, body_size }))
+            }
+            StreamingGetState::Body { .. } => FrameUnexpected.fail(),
+        }
+    }
+
+    fn collect_body(self, body: Vec<u8>) -> Result<(Option<StreamingGetState>, CollectorResult)> {
+        match self {
+            StreamingGetState::Body { remaining } => {
+                if body.len() > remaining {
+                    return FrameUnexpected.fail();
+                }
+                let remaining = remaining - body.len();
+                let complete = remaining == 0;
+                let state = if complete {
+                    None
+                } else {
+                    Some(StreamingGetState::Body { remaining })
+                };
+                Ok((state, CollectorResult::StreamingGetBody { body, complete }))
+            }
+            StreamingGetState::Start(_) => FrameUnexpected.fail(),
+        }
+    }
+}
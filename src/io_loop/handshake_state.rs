@@ -1,12 +1,12 @@
 use super::Inner;
 use crate::connection_options::ConnectionOptions;
 use crate::errors::*;
+use crate::logging::{debug, error};
 use crate::serialize::TryFromAmqpFrame;
 use crate::{FieldTable, Sasl};
 use amq_protocol::frame::AMQPFrame;
 use amq_protocol::protocol::connection::AMQPMethod as AmqpConnection;
 use amq_protocol::protocol::connection::{Close, CloseOk, OpenOk, Secure, Start, Tune, TuneOk};
-use log::{debug, error};
 
 #[derive(Debug)]
 pub(super) enum HandshakeState<Auth: Sasl> {
@@ -1,5 +1,5 @@
 use crate::heartbeats::Heartbeat;
-use log::trace;
+use crate::logging::trace;
 use mio_extras::timer::Timer;
 use std::time::Duration;
 
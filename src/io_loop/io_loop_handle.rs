@@ -1,26 +1,55 @@
-use super::{ChannelMessage, ConnectionBlockedNotification, ConsumerMessage, IoLoopMessage};
+use super::request_queue::RequestSender;
+use super::{
+    ChannelMessage, ChannelShared, ConfirmCallback, ConnectionBlockedNotification, ConsumerMessage,
+    IoLoopMessage,
+};
+use crate::channel::ServerCloseCallback;
+use crate::consumer::ConsumerConfig;
 use crate::errors::*;
-use crate::serialize::{IntoAmqpClass, OutputBuffer, TryFromAmqpClass};
-use crate::{AmqpProperties, Confirm, Error, Get, Return};
+use crate::logging::error;
+use crate::serialize::{IntoAmqpClass, OutputBuffer, TryFromAmqpClass, FRAME_OVERHEAD};
+use crate::spans;
+use crate::{AmqpProperties, Confirm, Confirmation, Error, Get, Return, StreamingGet};
 use amq_protocol::protocol::basic::AMQPMethod as AmqpBasic;
 use amq_protocol::protocol::basic::Consume;
 use amq_protocol::protocol::basic::Get as AmqpGet;
+use amq_protocol::protocol::basic::{Ack, Nack, Reject};
 use amq_protocol::protocol::connection::AMQPMethod as AmqpConnection;
 use amq_protocol::protocol::connection::Close as ConnectionClose;
 use amq_protocol::protocol::connection::CloseOk as ConnectionCloseOk;
 use crossbeam_channel::Receiver as CrossbeamReceiver;
 use crossbeam_channel::Sender as CrossbeamSender;
-use log::error;
+use crossbeam_channel::TryRecvError;
 use mio_extras::channel::SyncSender as MioSyncSender;
+use mio_extras::channel::TrySendError as MioTrySendError;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 pub(super) struct IoLoopHandle {
     channel_id: u16,
     buf: OutputBuffer,
-    tx: MioSyncSender<IoLoopMessage>,
+    tx: RequestSender,
     rx: CrossbeamReceiver<Result<ChannelMessage>>,
+
+    // Error pulled off rx by is_open() that has not yet been returned to a caller.
+    stashed_error: Option<Error>,
+
+    // Reply code and text if the server has closed our channel or connection.
+    server_close_reason: Option<(u16, String)>,
+
+    // Whether server_close_reason came from a connection close (as opposed to a channel close).
+    server_closed_connection: bool,
+
+    // Flow control and confirm state maintained by the I/O loop.
+    shared: Arc<ChannelShared>,
+
+    // While corked, frames from nowait methods and publishes accumulate here instead of being
+    // handed to the I/O loop one message at a time.
+    corked: Option<OutputBuffer>,
 }
 
 impl fmt::Debug for IoLoopHandle {
@@ -32,14 +61,36 @@ impl fmt::Debug for IoLoopHandle {
 impl IoLoopHandle {
     pub(super) fn new(
         channel_id: u16,
-        tx: MioSyncSender<IoLoopMessage>,
+        tx: RequestSender,
         rx: CrossbeamReceiver<Result<ChannelMessage>>,
+        shared: Arc<ChannelShared>,
     ) -> IoLoopHandle {
         IoLoopHandle {
             channel_id,
             buf: OutputBuffer::empty(),
             tx,
             rx,
+            stashed_error: None,
+            server_close_reason: None,
+            server_closed_connection: false,
+            shared,
+            corked: None,
+        }
+    }
+
+    // Start corking; returns false if we were already corked.
+    pub(super) fn cork(&mut self) -> bool {
+        if self.corked.is_some() {
+            return false;
+        }
+        self.corked = Some(OutputBuffer::empty());
+        true
+    }
+
+    pub(super) fn uncork(&mut self) -> Result<()> {
+        match self.corked.take() {
+            Some(buf) if !buf.is_empty() => self.send_now(IoLoopMessage::Send(buf)),
+            _ => Ok(()),
         }
     }
 
@@ -48,6 +99,169 @@ impl IoLoopHandle {
         self.channel_id
     }
 
+    pub(super) fn is_open(&mut self) -> bool {
+        if self.stashed_error.is_some() || self.server_close_reason.is_some() {
+            return false;
+        }
+        // Outside of an RPC call, the only thing the I/O loop will send us is an error
+        // telling us we've been closed; the I/O loop will drop its sending side at the
+        // same time.
+        match self.rx.try_recv() {
+            Ok(Ok(_)) => {
+                error!("internal error - received unexpected frame outside of an rpc call");
+                self.stashed_error = Some(Error::FrameUnexpected);
+                false
+            }
+            Ok(Err(err)) => {
+                self.record_server_close_reason(&err);
+                self.stashed_error = Some(err);
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => false,
+        }
+    }
+
+    #[inline]
+    pub(super) fn is_flow_active(&self) -> bool {
+        self.shared.flow_active.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub(super) fn confirmed_publishes(&self) -> u64 {
+        self.shared.confirmed.load(Ordering::SeqCst)
+    }
+
+    // Count a wait on the server (e.g., for an RPC reply) that timed out; see Watchdog.
+    pub(super) fn record_timeout(&self) {
+        self.shared.watchdog.record_timeout();
+    }
+
+    pub(super) fn track_confirm(&self, delivery_tag: u64) {
+        self.shared
+            .lock_tracked()
+            .outcomes
+            .insert(delivery_tag, None);
+    }
+
+    pub(super) fn untrack_confirm(&self, delivery_tag: u64) {
+        let mut tracked = self.shared.lock_tracked();
+        tracked.outcomes.remove(&delivery_tag);
+        tracked.mandatory.remove(&delivery_tag);
+        tracked.returned.remove(&delivery_tag);
+    }
+
+    pub(super) fn track_mandatory(&self, delivery_tag: u64, exchange: String, routing_key: String) {
+        self.shared
+            .lock_tracked()
+            .mandatory
+            .insert(delivery_tag, (exchange, routing_key));
+    }
+
+    pub(super) fn take_returned(&self, delivery_tag: u64) -> Option<(u16, String)> {
+        self.shared.lock_tracked().returned.remove(&delivery_tag)
+    }
+
+    pub(super) fn add_confirm_callback(
+        &self,
+        delivery_tag: u64,
+        callback: ConfirmCallback,
+    ) -> Result<()> {
+        let mut tracked = self.shared.lock_tracked();
+        if tracked.closed {
+            return EventLoopDropped.fail();
+        }
+        tracked.callbacks.insert(delivery_tag, (None, callback));
+        Ok(())
+    }
+
+    pub(super) fn remove_confirm_callback(&self, delivery_tag: u64) {
+        self.shared.lock_tracked().callbacks.remove(&delivery_tag);
+    }
+
+    pub(super) fn wait_for_confirm(
+        &self,
+        delivery_tag: u64,
+        timeout: Duration,
+    ) -> Result<Confirmation> {
+        let deadline = Instant::now() + timeout;
+        let mut tracked = self.shared.lock_tracked();
+        loop {
+            match tracked.outcomes.get(&delivery_tag).cloned() {
+                Some(Some(outcome)) => {
+                    tracked.outcomes.remove(&delivery_tag);
+                    return Ok(outcome);
+                }
+                Some(None) => (),
+                None => return UntrackedDeliveryTag { delivery_tag }.fail(),
+            }
+            if tracked.closed {
+                return EventLoopDropped.fail();
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                // A zero timeout is a poll, not a wait on the server.
+                if timeout > Duration::from_secs(0) {
+                    self.record_timeout();
+                }
+                return ConfirmTimeout { delivery_tag }.fail();
+            }
+            tracked = self
+                .shared
+                .tracked_changed
+                .wait_timeout(tracked, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    pub(super) fn server_close_reason(&mut self) -> Option<(u16, String)> {
+        self.is_open();
+        self.server_close_reason.clone()
+    }
+
+    // Reply code of a server close that has already been returned to a caller (as opposed to one
+    // that is_open() has stashed but no operation has reported yet).
+    pub(super) fn reported_close_code(&self) -> Option<u16> {
+        if self.stashed_error.is_some() {
+            return None;
+        }
+        self.server_close_reason.as_ref().map(|(code, _)| *code)
+    }
+
+    fn record_server_close_reason(&mut self, err: &Error) {
+        match err {
+            Error::ServerClosedChannel { code, message, .. } => {
+                self.server_close_reason = Some((*code, message.clone()));
+            }
+            Error::ServerClosedConnection { code, message } => {
+                self.server_close_reason = Some((*code, message.clone()));
+                self.server_closed_connection = true;
+            }
+            _ => (),
+        }
+    }
+
+    // The error to return once the I/O loop has dropped its sending side. If the server closed
+    // our channel or connection, keep reporting that instead of EventLoopDropped, so callers can
+    // tell a closed channel (which can be reopened) from an I/O loop that is gone.
+    fn disconnected_error(&self) -> Error {
+        match &self.server_close_reason {
+            Some((code, message)) if self.server_closed_connection => {
+                Error::ServerClosedConnection {
+                    code: *code,
+                    message: message.clone(),
+                }
+            }
+            Some((code, message)) => Error::ServerClosedChannel {
+                channel_id: self.channel_id,
+                code: *code,
+                message: message.clone(),
+            },
+            None => Error::EventLoopDropped,
+        }
+    }
+
     fn make_buf<M: IntoAmqpClass>(&mut self, method: M) -> OutputBuffer {
         debug_assert!(self.buf.is_empty());
         self.buf.push_method(self.channel_id, method);
@@ -68,24 +282,108 @@ impl IoLoopHandle {
         self.send(IoLoopMessage::SetPubConfirmHandler(handler))
     }
 
+    pub(super) fn set_server_close_handler(
+        &mut self,
+        handler: Option<ServerCloseCallback>,
+    ) -> Result<()> {
+        self.send(IoLoopMessage::SetServerCloseHandler(handler))
+    }
+
+    pub(super) fn expect_pub_confirms(&mut self) -> Result<()> {
+        self.send(IoLoopMessage::ExpectPubConfirms)
+    }
+
+    pub(super) fn consumer_canceller(&self, consumer_tag: String) -> ConsumerCanceller {
+        ConsumerCanceller {
+            channel_id: self.channel_id,
+            consumer_tag,
+            tx: self.tx.clone(),
+        }
+    }
+
+    pub(super) fn acker(&self) -> Acker {
+        Acker::new(self.channel_id, self.tx.clone())
+    }
+
     pub(super) fn get(&mut self, get: AmqpGet) -> Result<Option<Get>> {
         let buf = self.make_buf(AmqpBasic::Get(get));
         self.send(IoLoopMessage::Send(buf))?;
         match self.recv()? {
             ChannelMessage::GetOk(get) => Ok(*get),
-            ChannelMessage::Method(_) | ChannelMessage::ConsumeOk(_, _) => FrameUnexpected.fail(),
+            ChannelMessage::Method(_)
+            | ChannelMessage::ConsumeOk(_, _)
+            | ChannelMessage::StreamingGetOk(_, _)
+            | ChannelMessage::Flushed => FrameUnexpected.fail(),
+        }
+    }
+
+    pub(super) fn get_streaming(&mut self, get: AmqpGet) -> Result<Option<StreamingGet>> {
+        // As with consume_nowait, the I/O loop sees the body channel before the get
+        // request that will use it.
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.send(IoLoopMessage::StreamNextGet(tx))?;
+        let buf = self.make_buf(AmqpBasic::Get(get));
+        self.send(IoLoopMessage::Send(buf))?;
+        match self.recv()? {
+            ChannelMessage::StreamingGetOk(get, body_size) => {
+                Ok(Some(StreamingGet::new(*get, body_size, rx)))
+            }
+            ChannelMessage::GetOk(get) => match *get {
+                None => Ok(None),
+                Some(_) => FrameUnexpected.fail(),
+            },
+            ChannelMessage::Method(_)
+            | ChannelMessage::ConsumeOk(_, _)
+            | ChannelMessage::Flushed => FrameUnexpected.fail(),
         }
     }
 
     pub(super) fn consume(
         &mut self,
         consume: Consume,
+        config: ConsumerConfig,
     ) -> Result<(String, CrossbeamReceiver<ConsumerMessage>)> {
+        // We don't know the consumer tag until the server assigns it, so tell the I/O
+        // loop to apply our config to whichever consumer the next consume-ok is for.
+        self.send(IoLoopMessage::ConfigureNextConsumer(config))?;
         let buf = self.make_buf(AmqpBasic::Consume(consume));
         self.send(IoLoopMessage::Send(buf))?;
         match self.recv()? {
             ChannelMessage::ConsumeOk(tag, rx) => Ok((tag, rx)),
-            ChannelMessage::Method(_) | ChannelMessage::GetOk(_) => FrameUnexpected.fail(),
+            ChannelMessage::Method(_)
+            | ChannelMessage::GetOk(_)
+            | ChannelMessage::StreamingGetOk(_, _)
+            | ChannelMessage::Flushed => FrameUnexpected.fail(),
+        }
+    }
+
+    pub(super) fn consume_nowait(
+        &mut self,
+        consume: Consume,
+        config: ConsumerConfig,
+    ) -> Result<CrossbeamReceiver<ConsumerMessage>> {
+        // The server will not send consume-ok, so register the consumer with the
+        // I/O loop ourselves before sending the consume request. Both go through the
+        // same channel, so the I/O loop is guaranteed to see the registration first.
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.send(IoLoopMessage::RegisterConsumer(
+            consume.consumer_tag.clone(),
+            tx,
+            config,
+        ))?;
+        let buf = self.make_buf(AmqpBasic::Consume(consume));
+        self.send(IoLoopMessage::Send(buf))?;
+        Ok(rx)
+    }
+
+    pub(super) fn flush(&mut self) -> Result<()> {
+        self.send(IoLoopMessage::Flush)?;
+        match self.recv()? {
+            ChannelMessage::Flushed => Ok(()),
+            ChannelMessage::Method(_)
+            | ChannelMessage::ConsumeOk(_, _)
+            | ChannelMessage::GetOk(_)
+            | ChannelMessage::StreamingGetOk(_, _) => FrameUnexpected.fail(),
         }
     }
 
@@ -97,6 +395,24 @@ impl IoLoopHandle {
         self.call_message(IoLoopMessage::ConnectionClose(buf))
     }
 
+    // Ask the I/O loop to cancel a consumer; it swallows the cancel-ok itself, so we must not
+    // wait for one. This is the same request a ConsumerCanceller sends, but ordered after
+    // anything we have corked.
+    pub(super) fn cancel_consumer_nowait(&mut self, consumer_tag: String) -> Result<()> {
+        self.send_corked()?;
+        if self.consumer_canceller(consumer_tag).cancel() {
+            Ok(())
+        } else {
+            Err(self.check_recv_for_error())
+        }
+    }
+
+    // Send `close` and tell the I/O loop this handle will not wait for the close-ok.
+    pub(super) fn close_detached<M: IntoAmqpClass>(&mut self, close: M) -> Result<()> {
+        let buf = self.make_buf(close);
+        self.send(IoLoopMessage::CloseDetached(buf))
+    }
+
     pub(super) fn call<M: IntoAmqpClass, T: TryFromAmqpClass>(&mut self, method: M) -> Result<T> {
         let buf = self.make_buf(method);
         self.call_message(IoLoopMessage::Send(buf))
@@ -106,13 +422,16 @@ impl IoLoopHandle {
         self.send(message)?;
         match self.recv()? {
             ChannelMessage::Method(method) => T::try_from(method),
-            ChannelMessage::ConsumeOk(_, _) | ChannelMessage::GetOk(_) => FrameUnexpected.fail(),
+            ChannelMessage::ConsumeOk(_, _)
+            | ChannelMessage::GetOk(_)
+            | ChannelMessage::StreamingGetOk(_, _)
+            | ChannelMessage::Flushed => FrameUnexpected.fail(),
         }
     }
 
     pub(super) fn call_nowait<M: IntoAmqpClass>(&mut self, method: M) -> Result<()> {
         let buf = self.make_buf(method);
-        self.send(IoLoopMessage::Send(buf))
+        self.send_or_cork(buf)
     }
 
     pub(super) fn send_content_header(
@@ -125,24 +444,108 @@ impl IoLoopHandle {
         self.buf
             .push_content_header(self.channel_id, class_id, len, properties);
         let buf = self.buf.drain_into_new_buf();
-        self.send(IoLoopMessage::Send(buf))
+        self.send_or_cork(buf)
     }
 
     pub(super) fn send_content_body(&mut self, content: &[u8]) -> Result<()> {
+        // Frame the content directly into the buffer handed to the I/O loop instead of
+        // staging it in self.buf; content frames can be large, and staging would copy
+        // every byte an extra time.
+        let mut buf = OutputBuffer::with_capacity(content.len() + FRAME_OVERHEAD);
+        buf.push_content_body(self.channel_id, content);
+        self.send_or_cork(buf)
+    }
+
+    pub(super) fn try_send_method_with_content<M: IntoAmqpClass>(
+        &mut self,
+        method: M,
+        class_id: u16,
+        content: &[u8],
+        frame_max: usize,
+        properties: &AmqpProperties,
+    ) -> Result<()> {
+        // The I/O loop would only hold onto this until the server resumes us.
+        if !self.is_flow_active() {
+            return OutboundFull {
+                channel_id: self.channel_id,
+            }
+            .fail();
+        }
+        // Serialize the method and all its content frames into a single message so we
+        // either hand the entire thing to the I/O loop or none of it; interleaving a
+        // partial publish with other frames on this channel would be a protocol error.
+        // The method and header are small, so stage them in self.buf to learn their size;
+        // the content is then framed directly into a buffer that fits everything, so it is
+        // neither copied an extra time nor moved by a reallocation.
         debug_assert!(self.buf.is_empty());
-        self.buf.push_content_body(self.channel_id, content);
-        let buf = self.buf.drain_into_new_buf();
-        self.send(IoLoopMessage::Send(buf))
+        self.buf.push_method(self.channel_id, method);
+        self.buf
+            .push_content_header(self.channel_id, class_id, content.len(), properties);
+        let mut buf = OutputBuffer::with_capacity(
+            self.buf.len() + OutputBuffer::content_body_len(content.len(), frame_max),
+        );
+        buf.append(self.buf.drain_into_new_buf());
+        for chunk in content.chunks(frame_max) {
+            buf.push_content_body(self.channel_id, chunk);
+        }
+        if let Some(corked) = &mut self.corked {
+            corked.append(buf);
+            return Ok(());
+        }
+        match self.tx.try_send(IoLoopMessage::Send(buf)) {
+            Ok(()) => Ok(()),
+            Err(MioTrySendError::Full(_)) => OutboundFull {
+                channel_id: self.channel_id,
+            }
+            .fail(),
+            Err(MioTrySendError::Disconnected(_)) | Err(MioTrySendError::Io(_)) => {
+                Err(self.check_recv_for_error())
+            }
+        }
+    }
+
+    fn send_or_cork(&mut self, buf: OutputBuffer) -> Result<()> {
+        match &mut self.corked {
+            Some(corked) => {
+                corked.append(buf);
+                Ok(())
+            }
+            None => self.send_now(IoLoopMessage::Send(buf)),
+        }
     }
 
     fn send(&mut self, message: IoLoopMessage) -> Result<()> {
+        self.send_corked()?;
+        self.send_now(message)
+    }
+
+    // Anything that isn't corked (e.g., a synchronous RPC) must not overtake frames that are,
+    // so hand those to the I/O loop first; we stay corked afterwards.
+    fn send_corked(&mut self) -> Result<()> {
+        if let Some(corked) = &mut self.corked {
+            if !corked.is_empty() {
+                let buf = corked.drain_into_new_buf();
+                self.send_now(IoLoopMessage::Send(buf))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_now(&mut self, message: IoLoopMessage) -> Result<()> {
         self.tx
             .send(message)
             .map_err(|_| self.check_recv_for_error())
     }
 
     fn recv(&mut self) -> Result<ChannelMessage> {
-        self.rx.recv().map_err(|_| Error::EventLoopDropped)?
+        let result = match self.stashed_error.take() {
+            Some(err) => Err(err),
+            None => self.rx.recv().map_err(|_| self.disconnected_error())?,
+        };
+        if let Err(err) = &result {
+            self.record_server_close_reason(err);
+        }
+        result
     }
 
     fn check_recv_for_error(&mut self) -> Error {
@@ -163,11 +566,148 @@ impl IoLoopHandle {
     }
 }
 
+/// A cloneable handle for acknowledging, nacking, or rejecting deliveries on a single channel
+/// without going through the [`Channel`](struct.Channel.html) that received them.
+///
+/// Unlike a `Channel`, an `Acker` is `Send` and can be cloned freely, so deliveries (or just
+/// their [tags](struct.Delivery.html#method.delivery_tag)) can be handed to worker threads that
+/// acknowledge them when they finish. Get one from
+/// [`Channel::acker`](struct.Channel.html#method.acker) or
+/// [`Delivery::acker`](struct.Delivery.html#method.acker).
+///
+/// All methods are asynchronous: they hand the request to the connection's I/O thread and
+/// return without waiting for it to be sent. Delivery tags are channel-specific; passing a tag
+/// that was not delivered on this acker's channel causes the server to close the channel. Once
+/// the channel is closed, every method returns
+/// [`Error::AckOnClosedChannel`](enum.Error.html#variant.AckOnClosedChannel).
+#[derive(Clone)]
+pub struct Acker {
+    channel_id: u16,
+    tx: RequestSender,
+}
+
+impl fmt::Debug for Acker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> StdResult<(), fmt::Error> {
+        write!(f, "Acker {{ channel_id: {}, .. }}", self.channel_id)
+    }
+}
+
+impl Acker {
+    pub(super) fn new(channel_id: u16, tx: RequestSender) -> Acker {
+        Acker { channel_id, tx }
+    }
+
+    /// The ID of the channel whose deliveries this acker acknowledges.
+    #[inline]
+    pub fn channel_id(&self) -> u16 {
+        self.channel_id
+    }
+
+    /// Acknowledge the delivery with the given tag. If `multiple` is true, also acknowledges all
+    /// other unacknowledged deliveries on this channel with smaller tags.
+    pub fn ack(&self, delivery_tag: u64, multiple: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id, "ack", delivery_tag);
+        self.send(AmqpBasic::Ack(Ack {
+            delivery_tag,
+            multiple,
+        }))
+    }
+
+    /// Reject the delivery with the given tag. If `multiple` is true, also rejects all other
+    /// unacknowledged deliveries on this channel with smaller tags. If `requeue` is true,
+    /// instructs the server to attempt to requeue the messages.
+    pub fn nack(&self, delivery_tag: u64, multiple: bool, requeue: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id, "nack", delivery_tag);
+        self.send(AmqpBasic::Nack(Nack {
+            delivery_tag,
+            multiple,
+            requeue,
+        }))
+    }
+
+    /// Reject the delivery with the given tag. If `requeue` is true, instructs the server to
+    /// attempt to requeue the message.
+    pub fn reject(&self, delivery_tag: u64, requeue: bool) -> Result<()> {
+        let _span = spans::ack(self.channel_id, "reject", delivery_tag);
+        self.send(AmqpBasic::Reject(Reject {
+            delivery_tag,
+            requeue,
+        }))
+    }
+
+    fn send(&self, method: AmqpBasic) -> Result<()> {
+        let mut buf = OutputBuffer::empty();
+        buf.push_method(self.channel_id, method);
+        // Unlike IoLoopHandle, we have no reply channel to inspect for the reason the
+        // send failed; the I/O loop only drops its receiver once the channel is closed
+        // (by either side) or the loop itself has exited.
+        self.tx
+            .send(IoLoopMessage::Send(buf))
+            .map_err(|_| Error::AckOnClosedChannel {
+                channel_id: self.channel_id,
+            })
+    }
+}
+
+// Cloneable handle for cancelling a single consumer from any thread. The cancel is issued by the
+// I/O loop itself, which swallows the resulting cancel-ok instead of handing it to the channel's
+// RPC reply channel.
+#[derive(Clone)]
+pub(crate) struct ConsumerCanceller {
+    channel_id: u16,
+    consumer_tag: String,
+    tx: RequestSender,
+}
+
+impl fmt::Debug for ConsumerCanceller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> StdResult<(), fmt::Error> {
+        write!(
+            f,
+            "ConsumerCanceller {{ channel_id: {}, consumer_tag: {:?}, .. }}",
+            self.channel_id, self.consumer_tag
+        )
+    }
+}
+
+impl ConsumerCanceller {
+    // Returns false if the channel is already closed, in which case the consumer has been (or
+    // will be) told about the close.
+    pub(crate) fn cancel(&self) -> bool {
+        self.tx
+            .send(IoLoopMessage::CancelConsumer(self.consumer_tag.clone()))
+            .is_ok()
+    }
+}
+
+// Requests new channels from the I/O loop. Replies are not tagged with the request they answer,
+// so the connection and any channels that reopen themselves share one allocator behind a mutex.
+pub(super) struct ChannelAllocator {
+    req_tx: MioSyncSender<Option<u16>>,
+    rep_rx: CrossbeamReceiver<Result<IoLoopHandle>>,
+}
+
+impl ChannelAllocator {
+    pub(super) fn new(
+        req_tx: MioSyncSender<Option<u16>>,
+        rep_rx: CrossbeamReceiver<Result<IoLoopHandle>>,
+    ) -> ChannelAllocator {
+        ChannelAllocator { req_tx, rep_rx }
+    }
+
+    // Returns None if the request could not be sent because the I/O loop has exited.
+    pub(super) fn allocate(&self, channel_id: Option<u16>) -> Option<Result<IoLoopHandle>> {
+        self.req_tx.send(channel_id).ok()?;
+        Some(match self.rep_rx.recv() {
+            Ok(result) => result,
+            Err(_) => Err(Error::EventLoopDropped),
+        })
+    }
+}
+
 pub(super) struct IoLoopHandle0 {
     common: IoLoopHandle,
     set_blocked_tx: MioSyncSender<CrossbeamSender<ConnectionBlockedNotification>>,
-    alloc_chan_req_tx: MioSyncSender<Option<u16>>,
-    alloc_chan_rep_rx: CrossbeamReceiver<Result<IoLoopHandle>>,
+    allocator: Arc<Mutex<ChannelAllocator>>,
 }
 
 impl fmt::Debug for IoLoopHandle0 {
@@ -180,24 +720,46 @@ impl IoLoopHandle0 {
     pub(super) fn new(
         common: IoLoopHandle,
         set_blocked_tx: MioSyncSender<CrossbeamSender<ConnectionBlockedNotification>>,
-        alloc_chan_req_tx: MioSyncSender<Option<u16>>,
-        alloc_chan_rep_rx: CrossbeamReceiver<Result<IoLoopHandle>>,
+        allocator: ChannelAllocator,
     ) -> IoLoopHandle0 {
         IoLoopHandle0 {
             common,
             set_blocked_tx,
-            alloc_chan_req_tx,
-            alloc_chan_rep_rx,
+            allocator: Arc::new(Mutex::new(allocator)),
         }
     }
 
     pub(super) fn allocate_channel(&mut self, channel_id: Option<u16>) -> Result<IoLoopHandle> {
-        self.alloc_chan_req_tx
-            .send(channel_id)
-            .map_err(|_| self.common.check_recv_for_error())?;
-        self.alloc_chan_rep_rx
-            .recv()
-            .map_err(|_| Error::EventLoopDropped)?
+        let allocated = self
+            .allocator
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .allocate(channel_id);
+        match allocated {
+            Some(result) => result,
+            None => Err(self.common.check_recv_for_error()),
+        }
+    }
+
+    pub(super) fn list_channels(&mut self) -> Result<Vec<u16>> {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        self.common.send(IoLoopMessage::ListChannels(tx))?;
+        rx.recv().map_err(|_| self.common.check_recv_for_error())
+    }
+
+    pub(super) fn close_all_channels(&mut self) -> Result<()> {
+        let (tx, rx) = crossbeam_channel::bounded::<()>(0);
+        self.common.send(IoLoopMessage::CloseAllChannels(tx))?;
+        // The I/O loop hands a clone of tx to every channel it closes and drops the original;
+        // nothing is ever sent, so recv returns once all of those channels are gone (or the
+        // I/O loop has exited).
+        let _ = rx.recv();
+        Ok(())
+    }
+
+    #[inline]
+    pub(super) fn allocator(&self) -> Arc<Mutex<ChannelAllocator>> {
+        Arc::clone(&self.allocator)
     }
 
     pub(super) fn set_blocked_tx(
@@ -1,26 +1,40 @@
+use crate::channel::ServerCloseCallback;
 use crate::connection_options::ConnectionOptions;
+use crate::consumer::ConsumerConfig;
 use crate::errors::*;
 use crate::frame_buffer::FrameBuffer;
+use crate::frame_observer::{FrameDirection, FrameObserver};
+use crate::logging::{debug, error, trace, warn};
+use crate::metrics::MetricsCounters;
 use crate::serialize::{IntoAmqpClass, OutputBuffer, SealableOutputBuffer};
 use crate::{
-    Confirm, ConnectionBlockedNotification, ConnectionTuning, ConsumerMessage, FieldTable, Get,
-    IoStream, Return, Sasl,
+    Confirm, Confirmation, ConnectionBlockedNotification, ConnectionTuning, ConsumerMessage,
+    FieldTable, Get, IoStream, Return, Sasl,
 };
 use amq_protocol::frame::AMQPFrame;
+use amq_protocol::protocol::basic::AMQPMethod as AmqpBasic;
+use amq_protocol::protocol::basic::Cancel;
+use amq_protocol::protocol::channel::AMQPMethod as AmqpChannel;
+use amq_protocol::protocol::channel::Close as ChannelClose;
+use amq_protocol::protocol::connection::AMQPMethod as AmqpConnection;
+use amq_protocol::protocol::connection::Close as ConnectionClose;
 use amq_protocol::protocol::connection::TuneOk;
-use amq_protocol::protocol::AMQPClass;
+use amq_protocol::protocol::{AMQPClass, AMQPHardError, AMQPSoftError};
 use crossbeam_channel::Receiver as CrossbeamReceiver;
 use crossbeam_channel::SendError;
 use crossbeam_channel::Sender as CrossbeamSender;
-use log::{debug, error, trace, warn};
 use mio::{Event, Evented, Events, Poll, PollOpt, Ready, Token};
 use mio_extras::channel::sync_channel as mio_sync_channel;
 use mio_extras::channel::Receiver as MioReceiver;
 use snafu::ResultExt;
-use std::collections::hash_map::HashMap;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::TryRecvError;
-use std::thread::{Builder, JoinHandle};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 #[cfg(feature = "native-tls")]
@@ -33,45 +47,199 @@ mod content_collector;
 mod handshake_state;
 mod heartbeat_timers;
 mod io_loop_handle;
+mod request_queue;
+mod watchdog;
 
-pub(crate) use channel_handle::{Channel0Handle, ChannelHandle};
+pub(crate) use channel_handle::{Channel0Handle, ChannelHandle, ChannelOpener};
 use channel_slots::ChannelSlots;
-use connection_state::ConnectionState;
+use connection_state::{send, ConnectionState};
 use content_collector::ContentCollector;
 use handshake_state::HandshakeState;
 use heartbeat_timers::{HeartbeatKind, HeartbeatState, HeartbeatTimers};
-use io_loop_handle::{IoLoopHandle, IoLoopHandle0};
+pub use io_loop_handle::Acker;
+pub(crate) use io_loop_handle::ConsumerCanceller;
+use io_loop_handle::{ChannelAllocator, IoLoopHandle, IoLoopHandle0};
+use request_queue::{request_queue, RequestReceiver, RequestSender};
+use watchdog::Watchdog;
 
 const STREAM: Token = Token(u16::max_value() as usize + 1);
 const HEARTBEAT: Token = Token(u16::max_value() as usize + 2);
 const ALLOC_CHANNEL: Token = Token(u16::max_value() as usize + 3);
 const SET_BLOCKED_TX: Token = Token(u16::max_value() as usize + 4);
+const WATCHDOG: Token = Token(u16::max_value() as usize + 5);
 
 enum IoLoopMessage {
     Send(OutputBuffer),
     ConnectionClose(OutputBuffer),
+    CloseDetached(OutputBuffer),
     SetReturnHandler(Option<CrossbeamSender<Return>>),
     SetPubConfirmHandler(Option<CrossbeamSender<Confirm>>),
+    SetServerCloseHandler(Option<ServerCloseCallback>),
+    RegisterConsumer(String, CrossbeamSender<ConsumerMessage>, ConsumerConfig),
+    ConfigureNextConsumer(ConsumerConfig),
+    StreamNextGet(CrossbeamSender<Vec<u8>>),
+    Flush,
+    ExpectPubConfirms,
+    CancelConsumer(String),
+    ListChannels(CrossbeamSender<Vec<u16>>),
+    CloseAllChannels(CrossbeamSender<()>),
 }
 
 enum ChannelMessage {
     Method(AMQPClass),
     ConsumeOk(String, CrossbeamReceiver<ConsumerMessage>),
     GetOk(Box<Option<Get>>),
+    StreamingGetOk(Box<Get>, u64),
+    Flushed,
 }
 
 struct ChannelSlot {
-    rx: MioReceiver<IoLoopMessage>,
+    rx: RequestReceiver,
     tx: CrossbeamSender<Result<ChannelMessage>>,
     collector: ContentCollector,
     consumers: HashMap<String, CrossbeamSender<ConsumerMessage>>,
+    consumer_configs: HashMap<String, ConsumerConfig>,
+    next_consumer_config: ConsumerConfig,
+    // Where to send body chunks of the streamed get that is outstanding or in progress, if any.
+    streaming_get: Option<CrossbeamSender<Vec<u8>>>,
     return_handler: Option<CrossbeamSender<Return>>,
     pub_confirm_handler: Option<CrossbeamSender<Confirm>>,
+    server_close_handler: Option<ServerCloseCallback>,
+    pub_confirms_enabled: bool,
+    next_publish_tag: u64,
+    unconfirmed: BTreeSet<u64>,
+    acker: Acker,
+    // Consumers cancelled via a ConsumerCanceller whose cancel-ok has not yet arrived; that
+    // cancel-ok must not be forwarded to the channel's RPC reply channel.
+    async_cancels: HashSet<String>,
+    shared: Arc<ChannelShared>,
+    // Set if the connection has asked us to close this channel; never sent on, but dropped
+    // along with the slot so the connection can tell when every channel it closed is gone.
+    close_notify: Option<CrossbeamSender<()>>,
+    // Set if the channel's handle sent a close and went away without waiting for the close-ok;
+    // replies meant for the handle are sent here instead until the slot is removed.
+    detached_rx: Option<CrossbeamReceiver<Result<ChannelMessage>>>,
+    // Publishes (and their content frames) sent while the server has paused this channel via
+    // channel.flow; written once it resumes us.
+    held_publishes: OutputBuffer,
+    // True if the publish most recently sent on this channel went into held_publishes, so the
+    // rest of its content frames must follow it there.
+    holding_publish: bool,
+}
+
+// State the I/O loop maintains for a channel that the channel's IoLoopHandle reads directly.
+struct ChannelShared {
+    // False while the server has paused this channel via channel.flow.
+    flow_active: AtomicBool,
+    // Number of publishes the server has acked or nacked since confirms were enabled.
+    confirmed: AtomicU64,
+    // Publishes a client intends to wait on individually; notified whenever one is confirmed.
+    tracked: Mutex<TrackedConfirms>,
+    tracked_changed: Condvar,
+    // Shared by every channel on the connection.
+    watchdog: Arc<Watchdog>,
+}
+
+pub(crate) type ConfirmCallback = Box<dyn FnOnce(Result<Confirmation>) + Send>;
+
+#[derive(Default)]
+struct TrackedConfirms {
+    // Keyed by delivery tag; the outcome is None until the server acks or nacks that tag.
+    outcomes: HashMap<u64, Option<Confirmation>>,
+    // Callbacks registered via basic_publish_with_callback, with the outcome of their tag once
+    // known. A callback only runs once every callback with a lower tag has, so they fire in
+    // tag order even if the server confirms out of order.
+    callbacks: BTreeMap<u64, (Option<Confirmation>, ConfirmCallback)>,
+    // Exchange and routing key of tracked mandatory publishes that have been neither confirmed
+    // nor returned. basic.return carries no delivery tag, but the server always sends it before
+    // the ack for the same message, so a return belongs to the lowest tag listed here with the
+    // same exchange and routing key.
+    mandatory: BTreeMap<u64, (String, String)>,
+    // Reply code and text of tracked mandatory publishes the server returned.
+    returned: HashMap<u64, (u16, String)>,
+    // Set once the I/O loop has dropped this channel; no more outcomes will be recorded.
+    closed: bool,
+}
+
+impl ChannelShared {
+    fn new(watchdog: &Arc<Watchdog>) -> ChannelShared {
+        ChannelShared {
+            flow_active: AtomicBool::new(true),
+            confirmed: AtomicU64::new(0),
+            tracked: Mutex::default(),
+            tracked_changed: Condvar::new(),
+            watchdog: Arc::clone(watchdog),
+        }
+    }
+
+    fn lock_tracked(&self) -> MutexGuard<TrackedConfirms> {
+        self.tracked.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record_return(&self, return_: &Return) {
+        let mut tracked = self.lock_tracked();
+        let delivery_tag = tracked
+            .mandatory
+            .iter()
+            .find(|(_, (exchange, routing_key))| {
+                *exchange == return_.exchange && *routing_key == return_.routing_key
+            })
+            .map(|(&delivery_tag, _)| delivery_tag);
+        if let Some(delivery_tag) = delivery_tag {
+            tracked.mandatory.remove(&delivery_tag);
+            tracked.returned.insert(
+                delivery_tag,
+                (return_.reply_code, return_.reply_text.clone()),
+            );
+        }
+    }
+
+    fn resolve_tracked(&self, delivery_tags: &BTreeSet<u64>, outcome: Confirmation) {
+        let mut tracked = self.lock_tracked();
+        for tag in delivery_tags {
+            tracked.mandatory.remove(tag);
+        }
+        let mut resolved = false;
+        for (tag, tag_outcome) in tracked.outcomes.iter_mut() {
+            if tag_outcome.is_none() && delivery_tags.contains(tag) {
+                *tag_outcome = Some(outcome);
+                resolved = true;
+            }
+        }
+        for tag in delivery_tags {
+            if let Some((tag_outcome, _)) = tracked.callbacks.get_mut(tag) {
+                *tag_outcome = Some(outcome);
+            }
+        }
+        let mut ready = Vec::new();
+        loop {
+            let tag = match tracked.callbacks.iter().next() {
+                Some((&tag, (Some(_), _))) => tag,
+                _ => break,
+            };
+            // unwraps are safe; we just matched this entry with a known outcome
+            let (outcome, callback) = tracked.callbacks.remove(&tag).unwrap();
+            ready.push((callback, outcome.unwrap()));
+        }
+        if resolved {
+            self.tracked_changed.notify_all();
+        }
+        // Run callbacks without holding the lock, in case they publish on this channel.
+        drop(tracked);
+        for (callback, outcome) in ready {
+            callback(Ok(outcome));
+        }
+    }
 }
 
 impl ChannelSlot {
-    fn new(mio_channel_bound: usize, channel_id: u16) -> (ChannelSlot, IoLoopHandle) {
-        let (mio_tx, mio_rx) = mio_sync_channel(mio_channel_bound);
+    fn new(
+        mio_channel_bound: usize,
+        channel_id: u16,
+        metrics: &Arc<MetricsCounters>,
+        watchdog: &Arc<Watchdog>,
+    ) -> (ChannelSlot, IoLoopHandle) {
+        let (mio_tx, mio_rx) = request_queue(mio_channel_bound, metrics);
 
         // Bound of 2 is intentional here. The normal case for this channel is that it
         // will have at most 1 message in it (the response to a synchronous RPC call).
@@ -84,19 +252,86 @@ impl ChannelSlot {
         // Either way, the connection is in a bad state - bail out.
         let (tx, rx) = crossbeam_channel::bounded(2);
 
+        // Deliveries handed to consumers carry a clone of the sending side of our mio
+        // channel so they can be acked without access to the Channel. This means we
+        // never see this receiver disconnect while the slot is alive; that's fine, as
+        // channels are always removed from the slot map on close.
+        let acker = Acker::new(channel_id, mio_tx.clone());
+        let shared = Arc::new(ChannelShared::new(watchdog));
+
         let channel_slot = ChannelSlot {
             rx: mio_rx,
             tx,
             collector: ContentCollector::new(channel_id),
             consumers: HashMap::new(),
+            consumer_configs: HashMap::new(),
+            next_consumer_config: ConsumerConfig::default(),
+            streaming_get: None,
             return_handler: None,
             pub_confirm_handler: None,
+            server_close_handler: None,
+            pub_confirms_enabled: false,
+            next_publish_tag: 0,
+            unconfirmed: BTreeSet::new(),
+            acker,
+            async_cancels: HashSet::new(),
+            shared: Arc::clone(&shared),
+            close_notify: None,
+            detached_rx: None,
+            held_publishes: OutputBuffer::empty(),
+            holding_publish: false,
         };
 
-        let loop_handle = IoLoopHandle::new(channel_id, mio_tx, rx);
+        let loop_handle = IoLoopHandle::new(channel_id, mio_tx, rx, shared);
 
         (channel_slot, loop_handle)
     }
+
+    // Record a server ack or nack of published messages; returns the number of messages
+    // that are no longer unconfirmed.
+    fn record_confirm(&mut self, delivery_tag: u64, multiple: bool, outcome: Confirmation) -> u64 {
+        let confirmed = if !multiple {
+            let mut confirmed = BTreeSet::new();
+            if self.unconfirmed.remove(&delivery_tag) {
+                confirmed.insert(delivery_tag);
+            }
+            confirmed
+        } else {
+            // Per the spec, a multiple ack with a delivery tag of 0 acks all outstanding
+            // messages.
+            let still_unconfirmed = if delivery_tag == 0 {
+                BTreeSet::new()
+            } else {
+                self.unconfirmed.split_off(&(delivery_tag + 1))
+            };
+            mem::replace(&mut self.unconfirmed, still_unconfirmed)
+        };
+        let count = confirmed.len() as u64;
+        self.shared.confirmed.fetch_add(count, Ordering::SeqCst);
+        self.shared.resolve_tracked(&confirmed, outcome);
+        count
+    }
+
+    #[inline]
+    fn is_flow_active(&self) -> bool {
+        self.shared.flow_active.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ChannelSlot {
+    fn drop(&mut self) {
+        // Wake anyone waiting on a confirm that will now never arrive, and fail any
+        // callbacks still pending (in tag order).
+        let callbacks = {
+            let mut tracked = self.shared.lock_tracked();
+            tracked.closed = true;
+            mem::replace(&mut tracked.callbacks, BTreeMap::new())
+        };
+        self.shared.tracked_changed.notify_all();
+        for (_, (outcome, callback)) in callbacks {
+            callback(outcome.ok_or(Error::EventLoopDropped));
+        }
+    }
 }
 
 struct Channel0Slot {
@@ -108,8 +343,13 @@ struct Channel0Slot {
 }
 
 impl Channel0Slot {
-    fn new(mio_channel_bound: usize) -> (Channel0Slot, IoLoopHandle0) {
-        let (common_slot, common_handle) = ChannelSlot::new(mio_channel_bound, 0);
+    fn new(
+        mio_channel_bound: usize,
+        metrics: &Arc<MetricsCounters>,
+        watchdog: &Arc<Watchdog>,
+    ) -> (Channel0Slot, IoLoopHandle0) {
+        let (common_slot, common_handle) =
+            ChannelSlot::new(mio_channel_bound, 0, metrics, watchdog);
         let (alloc_chan_req_tx, alloc_chan_req_rx) = mio_sync_channel(1);
         let (set_blocked_tx, set_blocked_rx) = mio_sync_channel(1);
         let (alloc_chan_rep_tx, alloc_chan_rep_rx) = crossbeam_channel::bounded(1);
@@ -124,8 +364,7 @@ impl Channel0Slot {
         let handle = IoLoopHandle0::new(
             common_handle,
             set_blocked_tx,
-            alloc_chan_req_tx,
-            alloc_chan_rep_rx,
+            ChannelAllocator::new(alloc_chan_req_tx, alloc_chan_rep_rx),
         );
 
         (slot, handle)
@@ -134,7 +373,8 @@ impl Channel0Slot {
 
 pub(crate) struct IoLoop {
     poll: Poll,
-    connection_timeout: Option<Duration>,
+    // Deadline for completing the handshake; None once the connection is established.
+    connection_deadline: Option<Instant>,
     frame_buffer: FrameBuffer,
     inner: Inner,
 
@@ -142,6 +382,37 @@ pub(crate) struct IoLoop {
     // we will stop polling non-0 channels' requests for us to send more data.
     buffered_writes_high_water: usize,
     buffered_writes_low_water: usize,
+
+    // Once the handshake is done, writes of less than write_buffer_threshold bytes are held
+    // back for up to write_buffer_max_delay so that frames enqueued in the meantime go out in
+    // the same write. write_hold_deadline is when the data currently held must be written.
+    write_buffer_threshold: usize,
+    write_buffer_max_delay: Duration,
+    write_hold_deadline: Option<Instant>,
+    coalesce_writes: bool,
+
+    // Set when the I/O thread exits, and if it panics; see ExitFlags.
+    exited: Arc<AtomicBool>,
+    panicked: Arc<AtomicBool>,
+
+    // Registered under WATCHDOG; becomes readable once the watchdog trips.
+    _watchdog_registration: mio::Registration,
+}
+
+// Held by the I/O thread for its entire life; records whether the thread is exiting because
+// of a panic so the Connection can report it without joining the thread.
+struct ExitFlags {
+    exited: Arc<AtomicBool>,
+    panicked: Arc<AtomicBool>,
+}
+
+impl Drop for ExitFlags {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.panicked.store(true, Ordering::SeqCst);
+        }
+        self.exited.store(true, Ordering::SeqCst);
+    }
 }
 
 impl IoLoop {
@@ -157,32 +428,82 @@ impl IoLoop {
         )
         .context(RegisterWithPollHandle)?;
 
+        let (watchdog, watchdog_registration) = Watchdog::new(tuning.max_consecutive_timeouts);
+        poll.register(
+            &watchdog_registration,
+            WATCHDOG,
+            Ready::readable(),
+            PollOpt::edge(),
+        )
+        .context(RegisterWithPollHandle)?;
+
         Ok(IoLoop {
             poll,
             frame_buffer: FrameBuffer::new(),
-            inner: Inner::new(heartbeats, tuning.mem_channel_bound),
+            inner: Inner::new(heartbeats, tuning.mem_channel_bound, watchdog),
             buffered_writes_high_water: tuning.buffered_writes_high_water,
             buffered_writes_low_water: tuning.buffered_writes_low_water,
-            connection_timeout: None,
+            write_buffer_threshold: tuning.write_buffer_threshold,
+            write_buffer_max_delay: tuning.write_buffer_max_delay,
+            write_hold_deadline: None,
+            coalesce_writes: false,
+            connection_deadline: None,
+            exited: Arc::new(AtomicBool::new(false)),
+            panicked: Arc::new(AtomicBool::new(false)),
+            _watchdog_registration: watchdog_registration,
         })
     }
 
+    pub(crate) fn metrics(&self) -> Arc<MetricsCounters> {
+        Arc::clone(&self.inner.metrics)
+    }
+
+    pub(crate) fn frame_observer(&self) -> Arc<FrameObserver> {
+        Arc::clone(&self.inner.frame_observer)
+    }
+
+    pub(crate) fn exited(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.exited)
+    }
+
+    pub(crate) fn panicked(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.panicked)
+    }
+
+    fn exit_flags(&self) -> ExitFlags {
+        ExitFlags {
+            exited: self.exited(),
+            panicked: self.panicked(),
+        }
+    }
+
     pub(crate) fn start<Auth: Sasl, S: IoStream>(
         mut self,
         stream: S,
         mut options: ConnectionOptions<Auth>,
-    ) -> Result<(JoinHandle<Result<()>>, FieldTable, Channel0Handle)> {
+    ) -> Result<(JoinHandle<Result<()>>, TuneOk, FieldTable, Channel0Handle)> {
         self.poll
             .register(&stream, STREAM, Ready::writable(), PollOpt::edge())
             .context(RegisterWithPollHandle)?;
 
-        self.connection_timeout = options.connection_timeout.take();
+        self.connection_deadline = options
+            .connection_timeout
+            .take()
+            .map(|timeout| Instant::now() + timeout);
         let (handshake_done_tx, handshake_done_rx) = crossbeam_channel::bounded(1);
-        let (ch0_slot, ch0_handle) = Channel0Slot::new(self.inner.mio_channel_bound);
+        let (ch0_slot, ch0_handle) = Channel0Slot::new(
+            self.inner.mio_channel_bound,
+            &self.inner.metrics,
+            &self.inner.watchdog,
+        );
 
-        let join_handle = Builder::new()
-            .name("amiquip-io".to_string())
-            .spawn(move || self.thread_main(stream, options, handshake_done_tx, ch0_slot, false))
+        let exit_flags = self.exit_flags();
+        let join_handle = options
+            .io_thread_builder()
+            .spawn(move || {
+                let _exit_flags = exit_flags;
+                self.thread_main(stream, options, handshake_done_tx, ch0_slot, false)
+            })
             .context(ForkFailed)?;
 
         IoLoop::wait_for_amqp_handshake(ch0_handle, join_handle, handshake_done_rx)
@@ -193,7 +514,7 @@ impl IoLoop {
         mut self,
         stream: S,
         mut options: ConnectionOptions<Auth>,
-    ) -> Result<(JoinHandle<Result<()>>, FieldTable, Channel0Handle)> {
+    ) -> Result<(JoinHandle<Result<()>>, TuneOk, FieldTable, Channel0Handle)> {
         self.poll
             .register(
                 &stream,
@@ -203,13 +524,24 @@ impl IoLoop {
             )
             .context(RegisterWithPollHandle)?;
 
-        self.connection_timeout = options.connection_timeout.take();
+        self.connection_deadline = options
+            .connection_timeout
+            .take()
+            .map(|timeout| Instant::now() + timeout);
         let (handshake_done_tx, handshake_done_rx) = crossbeam_channel::bounded(1);
-        let (ch0_slot, ch0_handle) = Channel0Slot::new(self.inner.mio_channel_bound);
+        let (ch0_slot, ch0_handle) = Channel0Slot::new(
+            self.inner.mio_channel_bound,
+            &self.inner.metrics,
+            &self.inner.watchdog,
+        );
 
-        let join_handle = Builder::new()
-            .name("amiquip-io".to_string())
-            .spawn(move || self.thread_main_tls(stream, options, handshake_done_tx, ch0_slot))
+        let exit_flags = self.exit_flags();
+        let join_handle = options
+            .io_thread_builder()
+            .spawn(move || {
+                let _exit_flags = exit_flags;
+                self.thread_main_tls(stream, options, handshake_done_tx, ch0_slot)
+            })
             .context(ForkFailed)?;
 
         IoLoop::wait_for_amqp_handshake(ch0_handle, join_handle, handshake_done_rx)
@@ -218,14 +550,18 @@ impl IoLoop {
     fn wait_for_amqp_handshake(
         ch0_handle: IoLoopHandle0,
         join_handle: JoinHandle<Result<()>>,
-        handshake_done_rx: CrossbeamReceiver<(usize, FieldTable)>,
-    ) -> Result<(JoinHandle<Result<()>>, FieldTable, Channel0Handle)> {
+        handshake_done_rx: CrossbeamReceiver<(TuneOk, FieldTable)>,
+    ) -> Result<(JoinHandle<Result<()>>, TuneOk, FieldTable, Channel0Handle)> {
         match handshake_done_rx.recv() {
-            Ok((frame_max, server_properties)) => Ok((
-                join_handle,
-                server_properties,
-                Channel0Handle::new(ch0_handle, frame_max),
-            )),
+            Ok((tune_ok, server_properties)) => {
+                let frame_max = tune_ok.frame_max as usize;
+                Ok((
+                    join_handle,
+                    tune_ok,
+                    server_properties,
+                    Channel0Handle::new(ch0_handle, frame_max),
+                ))
+            }
 
             // If sender was dropped without sending, the I/O thread has failed; peel out
             // its final error.
@@ -244,7 +580,7 @@ impl IoLoop {
         mut self,
         stream: S,
         options: ConnectionOptions<Auth>,
-        handshake_done_tx: crossbeam_channel::Sender<(usize, FieldTable)>,
+        handshake_done_tx: crossbeam_channel::Sender<(TuneOk, FieldTable)>,
         ch0_slot: Channel0Slot,
     ) -> Result<()> {
         trace!("starting TLS handshake");
@@ -275,7 +611,7 @@ impl IoLoop {
         mut self,
         mut stream: S,
         options: ConnectionOptions<Auth>,
-        handshake_done_tx: crossbeam_channel::Sender<(usize, FieldTable)>,
+        handshake_done_tx: crossbeam_channel::Sender<(TuneOk, FieldTable)>,
         ch0_slot: Channel0Slot,
         have_written_to_socket: bool,
     ) -> Result<()> {
@@ -306,7 +642,8 @@ impl IoLoop {
         let (tune_ok, server_properties) =
             self.run_amqp_handshake(&mut stream, options, have_written_to_socket)?;
         let channel_max = tune_ok.channel_max;
-        match handshake_done_tx.send((tune_ok.frame_max as usize, server_properties)) {
+        self.frame_buffer.set_frame_max(tune_ok.frame_max as usize);
+        match handshake_done_tx.send((tune_ok, server_properties)) {
             Ok(_) => (),
             Err(_) => return Ok(()),
         }
@@ -340,13 +677,26 @@ impl IoLoop {
                 };
             }
         }
-        self.connection_timeout = None;
+        self.connection_deadline = None;
         match state {
             HandshakeState::Start(_)
             | HandshakeState::Secure(_, _)
             | HandshakeState::Tune(_, _)
             | HandshakeState::Open(_, _) => unreachable!(),
             HandshakeState::Done(tune_ok, server_properties) => Ok((tune_ok, server_properties)),
+            // The server can only close the connection during the handshake in response to
+            // our open request; RabbitMQ responds with NOT_ALLOWED if the vhost does not exist
+            // and ACCESS_REFUSED if the user lacks permission to access it.
+            HandshakeState::ServerClosing(ref close)
+                if close.reply_code == AMQPSoftError::ACCESSREFUSED.get_id()
+                    || close.reply_code == AMQPHardError::NOTALLOWED.get_id() =>
+            {
+                AccessRefused {
+                    code: close.reply_code,
+                    message: close.reply_text.clone(),
+                }
+                .fail()
+            }
             HandshakeState::ServerClosing(close) => ServerClosedConnection {
                 code: close.reply_code,
                 message: close.reply_text,
@@ -405,6 +755,7 @@ impl IoLoop {
         ch0_slot: Channel0Slot,
     ) -> Result<()> {
         let mut state = ConnectionState::Steady(ch0_slot);
+        self.coalesce_writes = self.write_buffer_threshold > 0;
         self.run_io_loop(
             stream,
             &mut state,
@@ -432,8 +783,14 @@ impl IoLoop {
     ) -> Result<()> {
         match event.token() {
             STREAM => {
-                if event.readiness().is_writable() {
+                // We're registered for writable whenever we have data to write, so writable
+                // events usually accompany reads too; don't let those cut short a hold.
+                if event.readiness().is_writable() && self.should_write() {
                     self.inner.write_to_stream(stream)?;
+                    // Anything enqueued after this write starts a new hold.
+                    if !self.inner.has_data_to_write() {
+                        self.write_hold_deadline = None;
+                    }
                 }
                 if event.readiness().is_readable() {
                     self.inner.read_from_stream(
@@ -444,6 +801,12 @@ impl IoLoop {
                 }
             }
             HEARTBEAT => self.inner.process_heartbeat_timers()?,
+            WATCHDOG => {
+                return ConsecutiveTimeouts {
+                    count: self.inner.watchdog.consecutive_timeouts(),
+                }
+                .fail()
+            }
             SET_BLOCKED_TX => match state {
                 ConnectionState::Steady(ch0_slot) => self.handle_set_blocked_tx(ch0_slot)?,
                 ConnectionState::ServerClosing(_)
@@ -477,7 +840,7 @@ impl IoLoop {
             }
             _ => unreachable!(),
         }
-        Ok(())
+        self.inner.notify_pending_flushes()
     }
 
     fn handle_set_blocked_tx(&self, ch0_slot: &mut Channel0Slot) -> Result<()> {
@@ -547,20 +910,30 @@ impl IoLoop {
         let mut events = Events::with_capacity(128);
         let mut listening_to_channels = true;
         loop {
-            let start_poll = Instant::now();
-            self.poll
-                .poll(&mut events, self.connection_timeout)
-                .context(FailedToPoll)?;
-            if events.is_empty() {
-                if let Some(timeout) = &self.connection_timeout {
-                    if start_poll.elapsed() > *timeout {
+            let now = Instant::now();
+            let mut timeout = match self.connection_deadline {
+                Some(deadline) => {
+                    if now >= deadline {
                         return ConnectionTimeout.fail();
                     }
+                    Some(deadline - now)
                 }
+                None => None,
+            };
+            // Wake up in time to write any data we're holding back; once the hold has expired,
+            // we're registered for writable and don't need a timeout.
+            if let Some(deadline) = self.write_hold_deadline {
+                if deadline > now {
+                    let hold = deadline - now;
+                    timeout = Some(timeout.map_or(hold, |timeout| timeout.min(hold)));
+                }
+            }
+            self.poll.poll(&mut events, timeout).context(FailedToPoll)?;
+            if events.is_empty() && !self.write_hold_expired() {
                 continue;
             }
 
-            let had_data_to_write = self.inner.has_data_to_write();
+            let had_data_to_write = self.should_write();
 
             for event in events.iter() {
                 handle_event(self, stream, state, event)?;
@@ -595,7 +968,10 @@ impl IoLoop {
             // If we don't have data to write, only reregister for readable (without
             // writable) if we had data to write after the last poll; otherwise we know
             // we were already registered as readable only and don't need to rereg.
-            if self.inner.has_data_to_write() && have_written_to_socket {
+            //
+            // Data we're holding back to coalesce with later frames doesn't count until its
+            // hold expires.
+            if self.should_write() && have_written_to_socket {
                 trace!("reregistering socket for readable or writable");
                 self.poll
                     .reregister(
@@ -614,6 +990,35 @@ impl IoLoop {
             }
         }
     }
+
+    // Returns true if we have data to write and shouldn't hold it back any longer. Data is
+    // only held back when coalescing is enabled and nothing is waiting on it: a channel
+    // waiting for a flush, or a connection close (which seals writes).
+    fn should_write(&mut self) -> bool {
+        if !self.inner.has_data_to_write() {
+            self.write_hold_deadline = None;
+            return false;
+        }
+        if !self.coalesce_writes
+            || self.inner.outbuf.len() >= self.write_buffer_threshold
+            || self.inner.are_writes_sealed()
+            || self.inner.has_pending_flushes()
+        {
+            self.write_hold_deadline = None;
+            return true;
+        }
+        let max_delay = self.write_buffer_max_delay;
+        let deadline = *self
+            .write_hold_deadline
+            .get_or_insert_with(|| Instant::now() + max_delay);
+        Instant::now() >= deadline
+    }
+
+    #[inline]
+    fn write_hold_expired(&self) -> bool {
+        self.write_hold_deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
 }
 
 struct Inner {
@@ -634,16 +1039,35 @@ struct Inner {
 
     // If true, non-0 channels are registered with mio. (Channel 0 is always registered.)
     channels_are_registered: bool,
+
+    // Channels waiting for us to finish writing everything currently in outbuf.
+    pending_flushes: Vec<u16>,
+
+    // Counters exposed via Connection::metrics().
+    metrics: Arc<MetricsCounters>,
+
+    // Tracks consecutive timeouts reported by channel handles; see ConnectionTuning.
+    watchdog: Arc<Watchdog>,
+
+    // Callback installed via Connection::set_frame_observer(), and how many bytes at the
+    // front of outbuf it has already been shown.
+    frame_observer: Arc<FrameObserver>,
+    observed_outgoing: usize,
 }
 
 impl Inner {
-    fn new(heartbeats: HeartbeatTimers, mio_channel_bound: usize) -> Self {
+    fn new(heartbeats: HeartbeatTimers, mio_channel_bound: usize, watchdog: Watchdog) -> Self {
         Inner {
             outbuf: SealableOutputBuffer::new(OutputBuffer::with_protocol_header()),
             heartbeats,
             chan_slots: ChannelSlots::new(),
             mio_channel_bound,
             channels_are_registered: true,
+            pending_flushes: Vec::new(),
+            metrics: Arc::default(),
+            watchdog: Arc::new(watchdog),
+            frame_observer: Arc::default(),
+            observed_outgoing: 0,
         }
     }
 
@@ -660,9 +1084,38 @@ impl Inner {
 
     #[inline]
     fn push_method<M: IntoAmqpClass>(&mut self, channel_id: u16, method: M) {
+        if !self.are_writes_sealed() {
+            MetricsCounters::add(&self.metrics.frames_sent, 1);
+        }
         self.outbuf.push_method(channel_id, method)
     }
 
+    // Update metrics for a buffer sent to us by a channel that we're about to append to
+    // outbuf.
+    fn record_outgoing(&mut self, channel_id: u16, buf: &OutputBuffer) {
+        if self.are_writes_sealed() {
+            return;
+        }
+        let stats = buf.stats();
+        MetricsCounters::add(&self.metrics.frames_sent, stats.frames);
+        MetricsCounters::add(&self.metrics.messages_published, stats.publishes);
+        MetricsCounters::add(&self.metrics.acks_sent, stats.acks);
+        MetricsCounters::add(&self.metrics.nacks_sent, stats.nacks);
+
+        if stats.publishes > 0 {
+            // unwrap is safe here, because we can only be called if we just
+            // received a message from this slot.
+            let slot = self.chan_slots.get_mut(channel_id).unwrap();
+            if slot.pub_confirms_enabled {
+                for _ in 0..stats.publishes {
+                    slot.next_publish_tag += 1;
+                    slot.unconfirmed.insert(slot.next_publish_tag);
+                }
+                MetricsCounters::add(&self.metrics.unconfirmed, stats.publishes);
+            }
+        }
+    }
+
     #[inline]
     fn start_heartbeats(&mut self, interval: u16) {
         if interval > 0 {
@@ -677,6 +1130,11 @@ impl Inner {
         !self.outbuf.is_empty()
     }
 
+    #[inline]
+    fn has_pending_flushes(&self) -> bool {
+        !self.pending_flushes.is_empty()
+    }
+
     fn deregister_nonzero_channels(&mut self, poll: &Poll) -> Result<()> {
         for (_, slot) in self.chan_slots.iter() {
             poll.deregister(&slot.rx)
@@ -721,6 +1179,7 @@ impl Inner {
                         // enqueuing up a heartbeat frame
                         if self.outbuf.is_empty() {
                             debug!("sending heartbeat");
+                            MetricsCounters::add(&self.metrics.frames_sent, 1);
                             self.outbuf.push_heartbeat();
                         } else {
                             warn!("tx heartbeat fired, but already have queued data to write - possible socket problem");
@@ -760,6 +1219,8 @@ impl Inner {
             match slot.rx.try_recv() {
                 Ok(message) => self.process_channel_message(channel_id, message)?,
                 Err(TryRecvError::Empty) => return Ok(()),
+                // A detached handle is expected to go away before its close-ok arrives.
+                Err(TryRecvError::Disconnected) if slot.detached_rx.is_some() => return Ok(()),
                 Err(TryRecvError::Disconnected) => return EventLoopClientDropped.fail(),
             }
         }
@@ -768,11 +1229,26 @@ impl Inner {
     fn process_channel_message(&mut self, channel_id: u16, message: IoLoopMessage) -> Result<()> {
         match message {
             IoLoopMessage::ConnectionClose(buf) => {
+                self.record_outgoing(channel_id, &buf);
                 self.outbuf.append(buf);
                 self.seal_writes();
             }
             IoLoopMessage::Send(buf) => {
+                if let Some(buf) = self.hold_publish_if_paused(channel_id, buf) {
+                    self.record_outgoing(channel_id, &buf);
+                    self.outbuf.append(buf);
+                }
+            }
+            IoLoopMessage::CloseDetached(buf) => {
+                assert!(channel_id != 0, "channel 0 cannot be detached");
+                self.record_outgoing(channel_id, &buf);
                 self.outbuf.append(buf);
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                let (tx, rx) = crossbeam_channel::unbounded();
+                slot.tx = tx;
+                slot.detached_rx = Some(rx);
             }
             IoLoopMessage::SetReturnHandler(handler) => {
                 assert!(channel_id != 0, "channel 0 cannot have a return handler");
@@ -788,6 +1264,182 @@ impl Inner {
                 let slot = self.chan_slots.get_mut(channel_id).unwrap();
                 slot.pub_confirm_handler = handler;
             }
+            IoLoopMessage::SetServerCloseHandler(handler) => {
+                assert!(
+                    channel_id != 0,
+                    "channel 0 cannot have a server close handler"
+                );
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                slot.server_close_handler = handler;
+            }
+            IoLoopMessage::RegisterConsumer(consumer_tag, tx, config) => {
+                assert!(channel_id != 0, "channel 0 cannot have consumers");
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                match slot.consumers.entry(consumer_tag) {
+                    Entry::Occupied(entry) => {
+                        // The server will close the channel when it sees the duplicate
+                        // tag; drop the new consumer's sender and leave the existing
+                        // consumer in place so it is notified of the close.
+                        warn!(
+                            "channel {} already has a consumer with tag {}",
+                            channel_id,
+                            entry.key()
+                        );
+                    }
+                    Entry::Vacant(entry) => {
+                        slot.consumer_configs.insert(entry.key().clone(), config);
+                        entry.insert(tx);
+                    }
+                }
+            }
+            IoLoopMessage::ConfigureNextConsumer(config) => {
+                assert!(channel_id != 0, "channel 0 cannot have consumers");
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                slot.next_consumer_config = config;
+            }
+            IoLoopMessage::StreamNextGet(tx) => {
+                assert!(channel_id != 0, "channel 0 cannot get messages");
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                slot.streaming_get = Some(tx);
+            }
+            IoLoopMessage::Flush => {
+                assert!(channel_id != 0, "channel 0 cannot request a flush");
+                self.pending_flushes.push(channel_id);
+            }
+            IoLoopMessage::ExpectPubConfirms => {
+                assert!(
+                    channel_id != 0,
+                    "channel 0 cannot enable publisher confirms"
+                );
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                slot.pub_confirms_enabled = true;
+            }
+            IoLoopMessage::CancelConsumer(consumer_tag) => {
+                assert!(channel_id != 0, "channel 0 cannot have consumers");
+                // unwrap is safe here, because we can only be called if we just
+                // received a message from this slot.
+                let slot = self.chan_slots.get_mut(channel_id).unwrap();
+                // Skip consumers that are already gone or already being cancelled; we
+                // only swallow one cancel-ok per tag.
+                if slot.consumers.contains_key(&consumer_tag)
+                    && slot.async_cancels.insert(consumer_tag.clone())
+                {
+                    self.push_method(
+                        channel_id,
+                        AmqpBasic::Cancel(Cancel {
+                            consumer_tag,
+                            nowait: false,
+                        }),
+                    );
+                }
+            }
+            IoLoopMessage::ListChannels(tx) => {
+                assert!(channel_id == 0, "only channel 0 can list channels");
+                let mut channel_ids = self.open_channel_ids();
+                channel_ids.sort();
+                // The connection is blocked waiting for this reply, so the send can only fail
+                // if it has since been dropped.
+                let _ = tx.send(channel_ids);
+            }
+            IoLoopMessage::CloseAllChannels(tx) => {
+                assert!(channel_id == 0, "only channel 0 can close all channels");
+                for id in self.open_channel_ids() {
+                    debug!("closing channel {} at the connection's request", id);
+                    // unwrap is safe here, because we just got id from chan_slots.
+                    let slot = self.chan_slots.get_mut(id).unwrap();
+                    slot.close_notify = Some(tx.clone());
+                    self.push_method(
+                        id,
+                        AmqpChannel::Close(ChannelClose {
+                            reply_code: 0,
+                            reply_text: String::new(),
+                            class_id: 0,
+                            method_id: 0,
+                        }),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // While the server has paused a channel via channel.flow, we hold back its publishes (and
+    // the content frames that follow them) until it resumes us, but keep sending everything
+    // else so acks, cancels, closes, etc. are not stuck behind them. Returns `buf` if it should
+    // be sent now.
+    fn hold_publish_if_paused(
+        &mut self,
+        channel_id: u16,
+        buf: OutputBuffer,
+    ) -> Option<OutputBuffer> {
+        let slot = match self.chan_slots.get_mut(channel_id) {
+            Some(slot) => slot,
+            None => return Some(buf),
+        };
+        let stats = buf.stats();
+        if stats.publishes > 0 {
+            // Publishes must not overtake ones we're already holding.
+            slot.holding_publish = !slot.is_flow_active() || !slot.held_publishes.is_empty();
+        } else if stats.content_frames == 0 {
+            return Some(buf);
+        }
+        if slot.holding_publish {
+            trace!(
+                "channel {} is paused by the server; holding publish",
+                channel_id
+            );
+            slot.held_publishes.append(buf);
+            None
+        } else {
+            Some(buf)
+        }
+    }
+
+    fn release_held_publishes(&mut self, channel_id: u16) {
+        let buf = match self.chan_slots.get_mut(channel_id) {
+            Some(slot) => {
+                slot.holding_publish = false;
+                mem::replace(&mut slot.held_publishes, OutputBuffer::empty())
+            }
+            None => return,
+        };
+        if !buf.is_empty() {
+            debug!(
+                "sending {} publishes held while channel {} was paused",
+                buf.stats().publishes,
+                channel_id
+            );
+            self.record_outgoing(channel_id, &buf);
+            self.outbuf.append(buf);
+        }
+    }
+
+    fn open_channel_ids(&self) -> Vec<u16> {
+        self.chan_slots.iter().map(|(id, _)| *id).collect()
+    }
+
+    fn notify_pending_flushes(&mut self) -> Result<()> {
+        // Frames from every channel share outbuf, so a flush is only complete once
+        // outbuf is entirely empty (even if other channels have since added data).
+        if self.pending_flushes.is_empty() || self.has_data_to_write() {
+            return Ok(());
+        }
+        for channel_id in self.pending_flushes.drain(..) {
+            // The channel may have been closed by the server since requesting the flush;
+            // if so, its handle will already have been told about the close.
+            if let Some(slot) = self.chan_slots.get(channel_id) {
+                send(&slot.tx, Ok(ChannelMessage::Flushed))?;
+            }
         }
         Ok(())
     }
@@ -802,8 +1454,11 @@ impl Inner {
 
             let mio_channel_bound = self.mio_channel_bound;
             let channels_are_registered = self.channels_are_registered;
+            let metrics = &self.metrics;
+            let watchdog = &self.watchdog;
             let result = self.chan_slots.insert(new_channel_id, |new_channel_id| {
-                let (slot, handle) = ChannelSlot::new(mio_channel_bound, new_channel_id);
+                let (slot, handle) =
+                    ChannelSlot::new(mio_channel_bound, new_channel_id, metrics, watchdog);
                 poll.register(
                     &slot.rx,
                     Token(new_channel_id as usize),
@@ -847,11 +1502,40 @@ impl Inner {
         S: IoStream,
         F: FnMut(&mut Inner, AMQPFrame) -> Result<()>,
     {
-        let n = frame_buffer.read_from(stream, |frame| {
+        let result = frame_buffer.read_from(stream, |frame| {
             trace!("read frame {:?}", frame);
+            MetricsCounters::add(&self.metrics.frames_received, 1);
+            // Any frame from the server shows the connection is not half-open.
+            self.watchdog.record_success();
+            if self.frame_observer.is_installed() {
+                self.frame_observer.observe(FrameDirection::Inbound, &frame);
+            }
             handler(self, frame)
-        })?;
+        });
+        let n = match result {
+            Ok(n) => n,
+            Err(err @ Error::FrameDecode { .. }) | Err(err @ Error::FrameTooLarge { .. }) => {
+                // We can't reliably parse anything else the server sends us, so don't
+                // wait for a close-ok; make a best-effort attempt to tell the server why
+                // we're going away, then bail out.
+                error!("{} - closing connection", err);
+                self.push_method(
+                    0,
+                    AmqpConnection::Close(ConnectionClose {
+                        reply_code: AMQPHardError::FRAMEERROR.get_id(),
+                        reply_text: err.to_string(),
+                        class_id: 0,
+                        method_id: 0,
+                    }),
+                );
+                self.seal_writes();
+                let _ = self.write_to_stream(stream);
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
         if n > 0 {
+            MetricsCounters::add(&self.metrics.bytes_received, n as u64);
             self.heartbeats.record_rx_activity();
         }
         Ok(())
@@ -861,18 +1545,32 @@ impl Inner {
         let len = self.outbuf.len();
         let mut pos = 0;
 
+        // Frames are only ever appended whole, so everything past what the observer has seen
+        // starts on a frame boundary. With no observer installed, mark everything as seen so
+        // that one installed later starts with new data instead of a partially-written frame.
+        if self.frame_observer.is_installed() {
+            let start = usize::min(self.observed_outgoing, len);
+            self.observed_outgoing =
+                start + self.frame_observer.observe_outgoing(&self.outbuf[start..]);
+        } else {
+            self.observed_outgoing = len;
+        }
+
         // Keep writing until we've written all len bytes or we hit WouldBlock.
         while pos < len {
             trace!("trying to write {} bytes", len - pos);
             let n = match stream.write(&self.outbuf[pos..]) {
                 Ok(n) => {
                     trace!("wrote {} bytes", n);
+                    MetricsCounters::add(&self.metrics.bytes_sent, n as u64);
+                    MetricsCounters::add(&self.metrics.socket_writes, 1);
                     self.heartbeats.record_tx_activity();
                     n
                 }
                 Err(err) => match err.kind() {
                     io::ErrorKind::WouldBlock => {
                         self.outbuf.drain_written(pos);
+                        self.observed_outgoing = self.observed_outgoing.saturating_sub(pos);
                         return Ok(());
                     }
                     _ => return Err(err).context(IoErrorWritingSocket),
@@ -883,6 +1581,7 @@ impl Inner {
 
         // Wrote everything we have - use clear instead of .drain_written().
         self.outbuf.clear();
+        self.observed_outgoing = 0;
         Ok(())
     }
 }
@@ -0,0 +1,136 @@
+use super::IoLoopMessage;
+use crate::metrics::MetricsCounters;
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use mio_extras::channel::sync_channel as mio_sync_channel;
+use mio_extras::channel::Receiver as MioReceiver;
+use mio_extras::channel::SendError as MioSendError;
+use mio_extras::channel::SyncSender as MioSyncSender;
+use mio_extras::channel::TrySendError as MioTrySendError;
+use std::io;
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+
+// Create the queue a channel uses to send requests to the I/O loop. Both sides keep the
+// connection's `outbound_pending` counter up to date with the number of requests that have been
+// queued but not yet taken off the queue by the I/O loop.
+pub(super) fn request_queue(
+    bound: usize,
+    metrics: &Arc<MetricsCounters>,
+) -> (RequestSender, RequestReceiver) {
+    let (tx, rx) = mio_sync_channel(bound);
+    (
+        RequestSender {
+            tx,
+            metrics: Arc::clone(metrics),
+        },
+        RequestReceiver {
+            rx,
+            metrics: Arc::clone(metrics),
+        },
+    )
+}
+
+#[derive(Clone)]
+pub(super) struct RequestSender {
+    tx: MioSyncSender<IoLoopMessage>,
+    metrics: Arc<MetricsCounters>,
+}
+
+impl RequestSender {
+    // Count the request before sending it, so the I/O loop can never take it off the queue
+    // (and decrement the counter) first.
+    pub(super) fn send(&self, message: IoLoopMessage) -> Result<(), MioSendError<IoLoopMessage>> {
+        MetricsCounters::add(&self.metrics.outbound_pending, 1);
+        let result = self.tx.send(message);
+        if result.is_err() {
+            MetricsCounters::sub(&self.metrics.outbound_pending, 1);
+        }
+        result
+    }
+
+    pub(super) fn try_send(
+        &self,
+        message: IoLoopMessage,
+    ) -> Result<(), MioTrySendError<IoLoopMessage>> {
+        MetricsCounters::add(&self.metrics.outbound_pending, 1);
+        let result = self.tx.try_send(message);
+        if result.is_err() {
+            MetricsCounters::sub(&self.metrics.outbound_pending, 1);
+        }
+        result
+    }
+}
+
+pub(super) struct RequestReceiver {
+    rx: MioReceiver<IoLoopMessage>,
+    metrics: Arc<MetricsCounters>,
+}
+
+impl RequestReceiver {
+    pub(super) fn try_recv(&self) -> Result<IoLoopMessage, TryRecvError> {
+        let message = self.rx.try_recv()?;
+        MetricsCounters::sub(&self.metrics.outbound_pending, 1);
+        Ok(message)
+    }
+}
+
+// Requests still queued when a channel goes away will never be processed; stop counting them.
+impl Drop for RequestReceiver {
+    fn drop(&mut self) {
+        while self.try_recv().is_ok() {}
+    }
+}
+
+impl Evented for RequestReceiver {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.rx.register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.rx.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        self.rx.deregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    fn pending(metrics: &MetricsCounters) -> u64 {
+        metrics.outbound_pending.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn counts_queued_requests() {
+        let metrics = Arc::new(MetricsCounters::default());
+        let (tx, rx) = request_queue(2, &metrics);
+        tx.send(IoLoopMessage::Flush).unwrap();
+        tx.clone().try_send(IoLoopMessage::Flush).unwrap();
+        assert!(tx.try_send(IoLoopMessage::Flush).is_err());
+        assert_eq!(pending(&metrics), 2);
+
+        rx.try_recv().unwrap();
+        assert_eq!(pending(&metrics), 1);
+
+        drop(rx);
+        assert_eq!(pending(&metrics), 0);
+        assert!(tx.send(IoLoopMessage::Flush).is_err());
+        assert_eq!(pending(&metrics), 0);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::logging::error;
+use mio::{Ready, Registration, SetReadiness};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Counts consecutive timeouts waiting on the server across all of a connection's channels. A
+// half-open connection (socket still up, server no longer responding) can keep every channel
+// waiting forever; once `limit` waits in a row have timed out, the watchdog marks its
+// registration readable so the I/O loop can give up on the connection.
+pub(super) struct Watchdog {
+    limit: Option<u32>,
+    consecutive: AtomicU32,
+    set_readiness: SetReadiness,
+}
+
+impl Watchdog {
+    // The returned registration must be registered with the I/O loop's poll handle for the
+    // watchdog to have any effect.
+    pub(super) fn new(limit: Option<u32>) -> (Watchdog, Registration) {
+        let (registration, set_readiness) = Registration::new2();
+        let watchdog = Watchdog {
+            limit,
+            consecutive: AtomicU32::new(0),
+            set_readiness,
+        };
+        (watchdog, registration)
+    }
+
+    pub(super) fn record_timeout(&self) {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let consecutive = self.consecutive.fetch_add(1, Ordering::SeqCst) + 1;
+        if consecutive >= limit {
+            error!(
+                "{} consecutive timeouts waiting on the server - closing connection",
+                consecutive
+            );
+            // This can only fail if the I/O loop (and its registration) is already gone.
+            let _ = self.set_readiness.set_readiness(Ready::readable());
+        }
+    }
+
+    #[inline]
+    pub(super) fn record_success(&self) {
+        self.consecutive.store(0, Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub(super) fn consecutive_timeouts(&self) -> u32 {
+        self.consecutive.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::{Events, Poll, PollOpt, Token};
+    use std::time::Duration;
+
+    fn is_tripped(poll: &Poll) -> bool {
+        let mut events = Events::with_capacity(1);
+        poll.poll(&mut events, Some(Duration::from_millis(0)))
+            .unwrap();
+        events.iter().any(|event| event.token() == Token(0))
+    }
+
+    #[test]
+    fn trips_after_consecutive_timeouts() {
+        let poll = Poll::new().unwrap();
+        let (watchdog, registration) = Watchdog::new(Some(2));
+        poll.register(&registration, Token(0), Ready::readable(), PollOpt::edge())
+            .unwrap();
+
+        watchdog.record_timeout();
+        watchdog.record_success();
+        watchdog.record_timeout();
+        assert_eq!(watchdog.consecutive_timeouts(), 1);
+        assert!(!is_tripped(&poll));
+
+        watchdog.record_timeout();
+        assert_eq!(watchdog.consecutive_timeouts(), 2);
+        assert!(is_tripped(&poll));
+    }
+
+    #[test]
+    fn disabled_without_limit() {
+        let (watchdog, _registration) = Watchdog::new(None);
+        for _ in 0..10 {
+            watchdog.record_timeout();
+        }
+        assert_eq!(watchdog.consecutive_timeouts(), 0);
+    }
+}
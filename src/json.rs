@@ -0,0 +1,80 @@
+use crate::errors::*;
+use crate::{AmqpProperties, Channel, Delivery, Get, Publish};
+
+const APPLICATION_JSON: &str = "application/json";
+
+impl Channel {
+    /// Serialize `value` as JSON and publish it to `exchange` with the given routing key and a
+    /// `content_type` of `application/json`. Requires the `serde` feature.
+    ///
+    /// If serialization fails, nothing is published and this method returns
+    /// [`Error::Serialize`](enum.Error.html#variant.Serialize). Otherwise, this behaves like
+    /// [`basic_publish`](#method.basic_publish).
+    pub fn publish_json<S0, S1, T>(&self, exchange: S0, routing_key: S1, value: &T) -> Result<()>
+    where
+        S0: Into<String>,
+        S1: Into<String>,
+        T: serde::Serialize + ?Sized,
+    {
+        let body = serde_json::to_vec(value).map_err(|err| Error::Serialize {
+            source: Box::new(err),
+        })?;
+        let properties = AmqpProperties::default().with_content_type(APPLICATION_JSON.to_string());
+        self.basic_publish(
+            exchange,
+            Publish::with_properties(&body, routing_key, properties),
+        )
+    }
+}
+
+impl Delivery {
+    /// Deserialize the body of this message from JSON. Requires the `serde` feature.
+    ///
+    /// The message's `content_type` is not checked. If the body is not valid JSON for `T`,
+    /// returns [`Error::Deserialize`](enum.Error.html#variant.Deserialize); the delivery must
+    /// still be acknowledged or rejected.
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).map_err(|err| Error::Deserialize {
+            source: Box::new(err),
+        })
+    }
+}
+
+impl Get {
+    /// Calls [`Delivery::json`](struct.Delivery.html#method.json) on `self.delivery`. Requires
+    /// the `serde` feature.
+    #[inline]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        self.delivery.json()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amq_protocol::protocol::basic::Deliver;
+    use std::collections::BTreeMap;
+
+    fn delivery(body: &[u8]) -> Delivery {
+        let deliver = Deliver {
+            consumer_tag: "tag".to_string(),
+            delivery_tag: 1,
+            redelivered: false,
+            exchange: String::new(),
+            routing_key: "json".to_string(),
+        };
+        Delivery::new(1, deliver, body.to_vec(), AmqpProperties::default()).1
+    }
+
+    #[test]
+    fn delivery_json() {
+        let value: BTreeMap<String, u32> = delivery(br#"{"a": 1, "b": 2}"#).json().unwrap();
+        assert_eq!(value.get("a"), Some(&1));
+        assert_eq!(value.get("b"), Some(&2));
+
+        match delivery(b"not json").json::<BTreeMap<String, u32>>() {
+            Err(Error::Deserialize { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -20,6 +20,52 @@
 //! `Connection::insecure_open_stream` will still be available, as these methods support
 //! unencrypted connections.
 //!
+//! The optional `mock-broker` feature provides [`MockBroker`](struct.MockBroker.html), an
+//! in-process stand-in for a RabbitMQ server that allows code using amiquip to be tested without
+//! a running broker:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! amiquip = { version = "0.3", features = ["mock-broker"] }
+//! ```
+//!
+//! The optional `serde` feature adds helpers for JSON message bodies:
+//! [`Channel::publish_json`](struct.Channel.html#method.publish_json) and
+//! [`Delivery::json`](struct.Delivery.html#method.json).
+//!
+//! ```toml
+//! [dependencies]
+//! amiquip = { version = "0.3", features = ["serde"] }
+//! ```
+//!
+//! The optional `gzip` feature adds transparent compression of message bodies:
+//! [`Channel::publish_gzip`](struct.Channel.html#method.publish_gzip) compresses a body and sets
+//! its `content_encoding` to `gzip`, and
+//! [`Delivery::decoded_body`](struct.Delivery.html#method.decoded_body) undoes it.
+//!
+//! ```toml
+//! [dependencies]
+//! amiquip = { version = "0.3", features = ["gzip"] }
+//! ```
+//!
+//! The optional `raw-frames` feature allows sending arbitrary methods and content frames on a
+//! channel (see [`Channel::send_raw_method`](struct.Channel.html#method.send_raw_method)), for
+//! testing how servers and proxies handle protocol violations. It should not be enabled outside
+//! of such tests.
+//!
+//! amiquip logs via the [`log`](https://docs.rs/log) crate. The optional `tracing` feature
+//! switches its log messages to [`tracing`](https://docs.rs/tracing) events (which are still
+//! forwarded to `log` if no `tracing` subscriber is installed), and records spans (at the `DEBUG`
+//! level) around RPC calls (`amqp.rpc`), publishes (`amqp.publish`), acks, nacks, and rejects
+//! (`amqp.ack`), and the I/O thread's hand-off of deliveries to consumers (`amqp.delivery`).
+//! Spans carry the channel ID and, where applicable, the method name and delivery tag; log
+//! messages emitted during an operation appear within its span.
+//!
+//! ```toml
+//! [dependencies]
+//! amiquip = { version = "0.3", features = ["tracing"] }
+//! ```
+//!
 //! # Examples
 //!
 //! A "hello world" publisher:
@@ -156,55 +202,94 @@
 //! * Connection recovery. If something goes wrong with a connection, it will be torn down, and
 //! errors will be returned from calls on the connection and any other handles (channels,
 //! consumers, etc.). A connection recovery strategy could be implemented on top of amiquip.
-//! * Channel-level flow control. RabbitMQ, as of version 3.7.14 in March 2019, [does not
-//! support](https://www.rabbitmq.com/specification.html#rules) clients requesting channel flow
-//! control, and it does not send channel flow control messages to clients (using TCP backpressure
-//! instead).
-//! * Setting up a [`Consumer`](struct.Consumer.html) with a user-provided consumer tag. If this is
-//! something you need, please [file an issue](https://github.com/jgallagher/amiquip/issues).
-//! * `nowait` variants of [`Queue::consume`](struct.Queue.html#method.consume) and
-//! [`Consumer::cancel`](struct.Consumer.html#method.cancel). It is unlikely support for these will
-//! be added, as the synchronous versions are used to set up internal channels for consumer
-//! messages.
 //! * `nowait` variant of [`Channel::recover`](struct.Channel.html#method.recover). The
 //! asynchronous version of `recover` is marked as deprecated in RabbitMQ's AMQP reference.
 
+mod ack_on_drop;
 mod auth;
+mod backoff;
 mod channel;
 mod confirm;
 mod connection;
 mod connection_options;
 mod consumer;
+mod consumer_arguments;
 mod delivery;
 mod errors;
 mod exchange;
+mod field_table;
 mod frame_buffer;
+mod frame_observer;
 mod get;
+#[cfg(feature = "gzip")]
+mod gzip;
+mod headers_match;
 mod heartbeats;
 mod io_loop;
+#[cfg(feature = "serde")]
+mod json;
+mod logging;
+mod metrics;
+#[cfg(feature = "mock-broker")]
+mod mock_broker;
+mod properties;
 mod queue;
 mod return_;
+mod routing_key;
+mod rpc_client;
 mod serialize;
+mod server_capabilities;
+mod spans;
 mod stream;
+mod stream_consumer;
+mod timestamp;
+mod topology;
 
+pub use ack_on_drop::AckOnDrop;
 pub use auth::{Auth, Sasl};
-pub use channel::Channel;
-pub use confirm::{Confirm, ConfirmPayload, ConfirmSmoother};
+pub use backoff::BackoffStrategy;
+pub use channel::{Channel, ChannelConfig, ServerClose};
+pub use confirm::{
+    BatchConfirm, Confirm, ConfirmPayload, ConfirmSmoother, Confirmation, PublishBatch,
+};
 pub use connection::{Connection, ConnectionBlockedNotification, ConnectionTuning};
 pub use connection_options::ConnectionOptions;
-pub use consumer::{Consumer, ConsumerMessage, ConsumerOptions};
+pub use consumer::{
+    Consumer, ConsumerMessage, ConsumerOptions, ConsumerSet, ConsumerShutdownHandle,
+    StreamingDelivery,
+};
+pub use consumer_arguments::{ConsumerArguments, StreamOffset};
 pub use delivery::Delivery;
 pub use errors::{Error, Result};
 pub use exchange::{Exchange, ExchangeDeclareOptions, ExchangeType, Publish};
-pub use get::Get;
-pub use queue::{Queue, QueueDeclareOptions, QueueDeleteOptions};
+pub use field_table::FieldTableBuilder;
+pub use frame_observer::FrameDirection;
+pub use get::{Get, GetBackoff, StreamingGet};
+pub use headers_match::HeadersMatch;
+pub use io_loop::Acker;
+pub use metrics::ConnectionMetrics;
+pub use properties::AmqpPropertiesExt;
+pub use queue::{
+    Queue, QueueBinding, QueueDeclareOptions, QueueDeleteOptions, QueueStatus, QueueType,
+};
 pub use return_::Return;
+pub use routing_key::RoutingKey;
+pub use rpc_client::RpcClient;
+pub use server_capabilities::ServerCapabilities;
 pub use stream::IoStream;
+pub use stream_consumer::StreamConsumer;
+pub use topology::{AppliedTopology, Topology};
 
 #[cfg(feature = "native-tls")]
 pub use stream::TlsConnector;
 
+#[cfg(feature = "mock-broker")]
+pub use mock_broker::{MockBroker, MockStream};
+
+pub use amq_protocol::frame::AMQPFrame as AmqpFrame;
 pub use amq_protocol::protocol::basic::AMQPProperties as AmqpProperties;
+#[cfg(feature = "raw-frames")]
+pub use amq_protocol::protocol::AMQPClass as AmqpClass;
 pub use amq_protocol::types::AMQPValue as AmqpValue;
 pub use amq_protocol::types::FieldTable;
 
@@ -0,0 +1,9 @@
+// amiquip's logging macros. With the `tracing` feature, messages are emitted as `tracing` events
+// so they nest within the spans in `spans`; tracing's `log` feature still forwards them to `log`
+// when no `tracing` subscriber is installed. Without the feature they are plain `log` records.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, trace, warn};
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, error, trace, warn};
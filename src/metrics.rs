@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of counters maintained by a connection's I/O thread.
+///
+/// Counters start at zero when the connection is opened and only increase, with the exception of
+/// [`unconfirmed`](#structfield.unconfirmed). Each counter is read individually, so a snapshot
+/// taken while the connection is busy may not be perfectly consistent across fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionMetrics {
+    /// Number of frames (including heartbeats) queued to be written to the server.
+    pub frames_sent: u64,
+
+    /// Number of frames (including heartbeats) read from the server.
+    pub frames_received: u64,
+
+    /// Number of bytes written to the socket.
+    pub bytes_sent: u64,
+
+    /// Number of successful writes to the connection's stream. For unencrypted connections, each
+    /// is a single write system call on the socket.
+    pub socket_writes: u64,
+
+    /// Number of bytes read from the socket.
+    pub bytes_received: u64,
+
+    /// Number of messages published on all channels.
+    pub messages_published: u64,
+
+    /// Number of messages received from the server, either by consumers or via
+    /// [`Channel::basic_get`](struct.Channel.html#method.basic_get).
+    pub deliveries_received: u64,
+
+    /// Number of `basic.ack` frames sent on all channels. A single ack with `multiple` set counts
+    /// once.
+    pub acks_sent: u64,
+
+    /// Number of `basic.nack` and `basic.reject` frames sent on all channels.
+    pub nacks_sent: u64,
+
+    /// Number of messages published on channels with
+    /// [publisher confirms](struct.Channel.html#method.enable_publisher_confirms) enabled that
+    /// the server has not yet acked or nacked. Messages on channels that have since been closed
+    /// are not included.
+    pub unconfirmed: u64,
+}
+
+impl ConnectionMetrics {
+    /// Average number of frames sent per write to the connection's stream, or `None` if nothing
+    /// has been written yet. Higher values mean small frames are being coalesced into fewer
+    /// writes; see
+    /// [`ConnectionTuning::write_buffer_threshold`](struct.ConnectionTuning.html#structfield.write_buffer_threshold).
+    ///
+    /// [`frames_sent`](#structfield.frames_sent) counts frames as they are queued, so this can
+    /// overstate the average slightly while frames are still waiting to be written. Take the
+    /// difference between two snapshots to measure a particular workload.
+    pub fn frames_per_write(&self) -> Option<f64> {
+        if self.socket_writes == 0 {
+            return None;
+        }
+        Some(self.frames_sent as f64 / self.socket_writes as f64)
+    }
+}
+
+// Counters shared between the I/O thread (which updates them) and the Connection (which
+// reads them). Nothing synchronizes on these values, so relaxed ordering is sufficient.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsCounters {
+    pub(crate) frames_sent: AtomicU64,
+    pub(crate) frames_received: AtomicU64,
+    pub(crate) bytes_sent: AtomicU64,
+    pub(crate) socket_writes: AtomicU64,
+    pub(crate) bytes_received: AtomicU64,
+    pub(crate) messages_published: AtomicU64,
+    pub(crate) deliveries_received: AtomicU64,
+    pub(crate) acks_sent: AtomicU64,
+    pub(crate) nacks_sent: AtomicU64,
+    pub(crate) unconfirmed: AtomicU64,
+    // Requests queued by channels that the I/O thread has not yet picked up; not part of
+    // ConnectionMetrics since it is a gauge, not a counter.
+    pub(crate) outbound_pending: AtomicU64,
+}
+
+impl MetricsCounters {
+    #[inline]
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn sub(counter: &AtomicU64, n: u64) {
+        counter.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionMetrics {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ConnectionMetrics {
+            frames_sent: get(&self.frames_sent),
+            frames_received: get(&self.frames_received),
+            bytes_sent: get(&self.bytes_sent),
+            socket_writes: get(&self.socket_writes),
+            bytes_received: get(&self.bytes_received),
+            messages_published: get(&self.messages_published),
+            deliveries_received: get(&self.deliveries_received),
+            acks_sent: get(&self.acks_sent),
+            nacks_sent: get(&self.nacks_sent),
+            unconfirmed: get(&self.unconfirmed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let counters = MetricsCounters::default();
+        assert_eq!(counters.snapshot(), ConnectionMetrics::default());
+
+        MetricsCounters::add(&counters.bytes_sent, 10);
+        MetricsCounters::add(&counters.unconfirmed, 3);
+        MetricsCounters::sub(&counters.unconfirmed, 2);
+        let metrics = counters.snapshot();
+        assert_eq!(metrics.bytes_sent, 10);
+        assert_eq!(metrics.unconfirmed, 1);
+        assert_eq!(metrics.frames_sent, 0);
+    }
+
+    #[test]
+    fn frames_per_write() {
+        let counters = MetricsCounters::default();
+        assert_eq!(counters.snapshot().frames_per_write(), None);
+
+        MetricsCounters::add(&counters.frames_sent, 30);
+        MetricsCounters::add(&counters.socket_writes, 4);
+        assert_eq!(counters.snapshot().frames_per_write(), Some(7.5));
+    }
+}
@@ -0,0 +1,1502 @@
+use crate::errors::*;
+use crate::logging::{debug, warn};
+use crate::serialize::{IntoAmqpClass, OutputBuffer};
+use crate::{Auth, Connection, ConnectionOptions, ConnectionTuning, FieldTable, IoStream};
+use amq_protocol::frame::{parse_frame, AMQPFrame};
+use amq_protocol::protocol::basic::AMQPMethod as AmqpBasic;
+use amq_protocol::protocol::basic::{
+    AMQPProperties, Ack, Cancel, CancelOk, ConsumeOk, Deliver, GetEmpty, GetOk, Publish, QosOk,
+    RecoverOk, Return,
+};
+use amq_protocol::protocol::channel::AMQPMethod as AmqpChannel;
+use amq_protocol::protocol::channel::OpenOk as ChannelOpenOk;
+use amq_protocol::protocol::channel::{Close as ChannelClose, CloseOk as ChannelCloseOk};
+use amq_protocol::protocol::confirm::AMQPMethod as AmqpConfirm;
+use amq_protocol::protocol::confirm::SelectOk;
+use amq_protocol::protocol::connection::AMQPMethod as AmqpConnection;
+use amq_protocol::protocol::connection::{
+    Close as ConnectionClose, CloseOk as ConnectionCloseOk, OpenOk, Start, Tune,
+};
+use amq_protocol::protocol::exchange::AMQPMethod as AmqpExchange;
+use amq_protocol::protocol::exchange::{
+    DeclareOk as ExchangeDeclareOk, DeleteOk as ExchangeDeleteOk,
+};
+use amq_protocol::protocol::queue::AMQPMethod as AmqpQueue;
+use amq_protocol::protocol::queue::{
+    BindOk, DeclareOk as QueueDeclareOk, DeleteOk as QueueDeleteOk, PurgeOk, UnbindOk,
+};
+use amq_protocol::protocol::{AMQPClass, AMQPHardError, AMQPSoftError};
+use amq_protocol::types::AMQPValue;
+use crossbeam_channel::{select, Receiver, Sender};
+use mio::{Evented, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use snafu::ResultExt;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::Builder;
+
+// Largest frame the mock broker will negotiate; also used to split delivered bodies.
+pub(crate) const FRAME_MAX: u32 = 131_072;
+
+// Frame header (7 bytes) plus frame-end marker (1 byte).
+const FRAME_OVERHEAD: usize = 8;
+
+const PROTOCOL_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+
+/// An in-process stand-in for an AMQP server, for testing code that uses amiquip without a
+/// running RabbitMQ.
+///
+/// `MockBroker` speaks AMQP 0-9-1 over an in-memory [`MockStream`](struct.MockStream.html), so
+/// connections to it go through the same I/O thread and frame handling as connections to a real
+/// server. All connections made from the same `MockBroker` (or clones of it) share its queues and
+/// exchanges.
+///
+/// The mock implements a deliberately small subset of RabbitMQ's behavior:
+///
+/// * Queues can be declared (including server-named, exclusive, and auto-delete queues), bound,
+///   unbound, purged, and deleted.
+/// * Only direct exchanges are supported; declaring an exchange of any other type closes the
+///   channel. The default exchange and `amq.direct` always exist.
+/// * Published messages are routed to queues and delivered to consumers round-robin, or fetched
+///   with `basic.get`. Acks, nacks, rejects, and `basic.recover` are honored, and unacked
+///   messages are requeued when their channel or connection closes.
+/// * Mandatory messages that cannot be routed are returned, and publisher confirms are supported
+///   (every message is acked as soon as it is routed).
+/// * Prefetch limits set with `basic.qos` are accepted but not enforced, and no message
+///   properties (TTLs, priorities, dead-lettering, etc.) have any effect.
+///
+/// Any other request closes the connection with a `NOT_IMPLEMENTED` error.
+///
+/// This type is only available when the `mock-broker` feature is enabled.
+///
+/// # Example
+///
+/// ```rust
+/// use amiquip::{ConsumerMessage, ConsumerOptions, MockBroker, Publish, QueueDeclareOptions};
+///
+/// # fn main() -> amiquip::Result<()> {
+/// let broker = MockBroker::new();
+/// let mut connection = broker.connect()?;
+/// let channel = connection.open_channel(None)?;
+///
+/// let queue = channel.queue_declare("hello", QueueDeclareOptions::default())?;
+/// channel.basic_publish("", Publish::new(b"hello world", "hello"))?;
+///
+/// let consumer = queue.consume(ConsumerOptions::default())?;
+/// match consumer.receiver().recv().unwrap() {
+///     ConsumerMessage::Delivery(delivery) => {
+///         assert_eq!(delivery.body, b"hello world");
+///         consumer.ack(delivery)?;
+///     }
+///     other => panic!("unexpected message {:?}", other),
+/// }
+///
+/// connection.close()
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct MockBroker {
+    state: Arc<Mutex<BrokerState>>,
+}
+
+impl MockBroker {
+    /// Create a new broker with no queues and only the default and `amq.direct` exchanges.
+    pub fn new() -> MockBroker {
+        MockBroker::default()
+    }
+
+    /// Open a connection to this broker with default options.
+    pub fn connect(&self) -> Result<Connection> {
+        Connection::insecure_open_stream(
+            self.stream()?,
+            ConnectionOptions::<Auth>::default(),
+            ConnectionTuning::default(),
+        )
+    }
+
+    /// Create a new in-memory stream connected to this broker, suitable for passing to
+    /// [`Connection::insecure_open_stream`](struct.Connection.html#method.insecure_open_stream)
+    /// with custom options. The broker accepts any credentials.
+    pub fn stream(&self) -> Result<MockStream> {
+        let (registration, set_readiness) = Registration::new2();
+        // We never block writes, so the stream is always writable.
+        set_readiness
+            .set_readiness(Ready::writable())
+            .context(RegisterWithPollHandle)?;
+
+        let (data_tx, data_rx) = crossbeam_channel::unbounded();
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        let outbound = Outbound {
+            pipe: Arc::new(Mutex::new(Pipe::default())),
+            set_readiness,
+        };
+
+        let session = {
+            let mut state = self.state.lock().unwrap();
+            state.next_session_id += 1;
+            Session {
+                id: state.next_session_id,
+                broker: Arc::clone(&self.state),
+                events: events_tx,
+                outbound: outbound.clone(),
+                frame_max: FRAME_MAX,
+                channels: HashMap::new(),
+                input: Vec::new(),
+                got_protocol_header: false,
+                cancel_notify: false,
+                closing: false,
+            }
+        };
+        Builder::new()
+            .name("amiquip-mock-broker".to_string())
+            .spawn(move || session.run(data_rx, events_rx))
+            .context(ForkFailed)?;
+
+        Ok(MockStream {
+            data: data_tx,
+            pipe: outbound.pipe,
+            set_readiness: outbound.set_readiness,
+            registration,
+        })
+    }
+}
+
+/// Client side of an in-memory connection to a [`MockBroker`](struct.MockBroker.html).
+///
+/// This type is only available when the `mock-broker` feature is enabled.
+pub struct MockStream {
+    data: Sender<Vec<u8>>,
+    pipe: Arc<Mutex<Pipe>>,
+    set_readiness: SetReadiness,
+    registration: Registration,
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.pipe.lock().unwrap();
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Ok(0);
+            }
+            // Readiness is only updated while holding the pipe lock, so this can't race with
+            // the broker pushing more data.
+            self.set_readiness.set_readiness(Ready::writable())?;
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = usize::min(buf.len(), pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Evented for MockStream {
+    fn register(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        Evented::register(&self.registration, poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        Evented::reregister(&self.registration, poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        Evented::deregister(&self.registration, poll)
+    }
+}
+
+impl IoStream for MockStream {}
+
+// Data written by the broker, waiting to be read by the client.
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+}
+
+#[derive(Clone)]
+struct Outbound {
+    pipe: Arc<Mutex<Pipe>>,
+    set_readiness: SetReadiness,
+}
+
+impl Outbound {
+    fn send(&self, buf: OutputBuffer) {
+        let mut pipe = self.pipe.lock().unwrap();
+        pipe.buf.extend(&buf[0..]);
+        let _ = self
+            .set_readiness
+            .set_readiness(Ready::readable() | Ready::writable());
+    }
+
+    fn close(&self) {
+        let mut pipe = self.pipe.lock().unwrap();
+        pipe.closed = true;
+        let _ = self
+            .set_readiness
+            .set_readiness(Ready::readable() | Ready::writable());
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Message {
+    exchange: String,
+    routing_key: String,
+    properties: AMQPProperties,
+    body: Vec<u8>,
+    redelivered: bool,
+}
+
+// Sent from the shared broker state to the session owning a consumer.
+enum SessionEvent {
+    Deliver {
+        channel_id: u16,
+        consumer_tag: String,
+        queue: String,
+        message: Message,
+    },
+    Cancelled {
+        channel_id: u16,
+        consumer_tag: String,
+    },
+}
+
+struct ConsumerRef {
+    session_id: u64,
+    channel_id: u16,
+    consumer_tag: String,
+    exclusive: bool,
+    events: Sender<SessionEvent>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    owner: Option<u64>,
+    auto_delete: bool,
+    arguments: FieldTable,
+    messages: VecDeque<Message>,
+    consumers: Vec<ConsumerRef>,
+    next_consumer: usize,
+}
+
+struct BrokerState {
+    next_session_id: u64,
+    next_queue_id: u64,
+    queues: HashMap<String, QueueState>,
+    // Direct exchanges, mapping each to its (routing key, queue) bindings. The default exchange
+    // is handled specially and does not appear here.
+    exchanges: HashMap<String, HashSet<(String, String)>>,
+}
+
+impl Default for BrokerState {
+    fn default() -> BrokerState {
+        let mut exchanges = HashMap::new();
+        exchanges.insert("amq.direct".to_string(), HashSet::new());
+        BrokerState {
+            next_session_id: 0,
+            next_queue_id: 0,
+            queues: HashMap::new(),
+            exchanges,
+        }
+    }
+}
+
+impl BrokerState {
+    fn route(&self, exchange: &str, routing_key: &str) -> Option<Vec<String>> {
+        if exchange == "" {
+            let queues = if self.queues.contains_key(routing_key) {
+                vec![routing_key.to_string()]
+            } else {
+                Vec::new()
+            };
+            return Some(queues);
+        }
+        let bindings = self.exchanges.get(exchange)?;
+        let mut queues = bindings
+            .iter()
+            .filter(|(key, _)| key == routing_key)
+            .map(|(_, queue)| queue.clone())
+            .collect::<Vec<_>>();
+        queues.sort();
+        queues.dedup();
+        Some(queues)
+    }
+
+    fn enqueue(&mut self, queue: &str, message: Message, front: bool) {
+        if let Some(state) = self.queues.get_mut(queue) {
+            if front {
+                state.messages.push_front(message);
+            } else {
+                state.messages.push_back(message);
+            }
+            self.dispatch(queue);
+        }
+    }
+
+    // Hand queued messages to consumers, round-robin.
+    fn dispatch(&mut self, queue: &str) {
+        let state = match self.queues.get_mut(queue) {
+            Some(state) => state,
+            None => return,
+        };
+        while !state.consumers.is_empty() {
+            let message = match state.messages.pop_front() {
+                Some(message) => message,
+                None => return,
+            };
+            let index = state.next_consumer % state.consumers.len();
+            let consumer = &state.consumers[index];
+            let event = SessionEvent::Deliver {
+                channel_id: consumer.channel_id,
+                consumer_tag: consumer.consumer_tag.clone(),
+                queue: queue.to_string(),
+                message,
+            };
+            match consumer.events.send(event) {
+                Ok(()) => state.next_consumer = index + 1,
+                Err(err) => {
+                    // The consumer's session is gone; requeue and forget the consumer.
+                    if let SessionEvent::Deliver { message, .. } = err.into_inner() {
+                        state.messages.push_front(message);
+                    }
+                    state.consumers.remove(index);
+                }
+            }
+        }
+    }
+
+    fn remove_consumer(&mut self, session_id: u64, channel_id: u16, consumer_tag: &str) {
+        let mut emptied = None;
+        for (name, queue) in self.queues.iter_mut() {
+            let before = queue.consumers.len();
+            queue.consumers.retain(|c| {
+                !(c.session_id == session_id
+                    && c.channel_id == channel_id
+                    && c.consumer_tag == consumer_tag)
+            });
+            if queue.consumers.len() < before && queue.consumers.is_empty() && queue.auto_delete {
+                emptied = Some(name.clone());
+            }
+        }
+        if let Some(name) = emptied {
+            self.delete_queue(&name);
+        }
+    }
+
+    fn delete_queue(&mut self, name: &str) -> Option<u32> {
+        let queue = self.queues.remove(name)?;
+        for bindings in self.exchanges.values_mut() {
+            bindings.retain(|(_, queue)| queue != name);
+        }
+        for consumer in queue.consumers {
+            let _ = consumer.events.send(SessionEvent::Cancelled {
+                channel_id: consumer.channel_id,
+                consumer_tag: consumer.consumer_tag,
+            });
+        }
+        Some(queue.messages.len() as u32)
+    }
+}
+
+// A message on its way in from a client: basic.publish followed by a content header and zero or
+// more body frames.
+struct PendingPublish {
+    publish: Publish,
+    properties: Option<AMQPProperties>,
+    body_size: usize,
+    body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    // True after we've sent channel.close and are waiting for close-ok.
+    closing: bool,
+    next_delivery_tag: u64,
+    unacked: BTreeMap<u64, (String, Message)>,
+    // consumer tag -> no_ack
+    consumers: HashMap<String, bool>,
+    next_consumer_id: u64,
+    next_publish_tag: Option<u64>,
+    pending: Option<PendingPublish>,
+}
+
+// Reasons a session stops handling requests on a channel or on the whole connection.
+enum Exception {
+    Channel(u16, AMQPSoftError, String),
+    Connection(AMQPHardError, String),
+}
+
+type SessionResult = std::result::Result<(), Exception>;
+
+struct Session {
+    id: u64,
+    broker: Arc<Mutex<BrokerState>>,
+    events: Sender<SessionEvent>,
+    outbound: Outbound,
+    frame_max: u32,
+    channels: HashMap<u16, ChannelState>,
+    input: Vec<u8>,
+    got_protocol_header: bool,
+    // True if the client announced the consumer_cancel_notify capability; as with RabbitMQ,
+    // consumers of clients that didn't are dropped silently when their queue goes away.
+    cancel_notify: bool,
+    // True after we've sent connection.close and are waiting for close-ok.
+    closing: bool,
+}
+
+impl Session {
+    fn run(mut self, data: Receiver<Vec<u8>>, events: Receiver<SessionEvent>) {
+        loop {
+            let keep_going = select! {
+                recv(data) -> bytes => match bytes {
+                    Ok(bytes) => self.handle_input(bytes),
+                    // client dropped its stream
+                    Err(_) => false,
+                },
+                recv(events) -> event => match event {
+                    Ok(event) => {
+                        self.handle_event(event);
+                        true
+                    }
+                    Err(_) => false,
+                },
+            };
+            if !keep_going {
+                break;
+            }
+        }
+        self.cleanup();
+        self.outbound.close();
+    }
+
+    fn send<M: IntoAmqpClass>(&self, channel_id: u16, method: M) {
+        let mut buf = OutputBuffer::empty();
+        buf.push_method(channel_id, method);
+        self.outbound.send(buf);
+    }
+
+    fn send_content(
+        &self,
+        channel_id: u16,
+        method: AmqpBasic,
+        properties: &AMQPProperties,
+        body: &[u8],
+    ) {
+        let mut buf = OutputBuffer::empty();
+        buf.push_method(channel_id, method);
+        // 60 is the class id of basic
+        buf.push_content_header(channel_id, 60, body.len(), properties);
+        let chunk_size = self.frame_max as usize - FRAME_OVERHEAD;
+        for chunk in body.chunks(chunk_size) {
+            buf.push_content_body(channel_id, chunk);
+        }
+        self.outbound.send(buf);
+    }
+
+    // Returns false once the connection is done.
+    fn handle_input(&mut self, bytes: Vec<u8>) -> bool {
+        self.input.extend(bytes);
+
+        if !self.got_protocol_header {
+            if self.input.len() < PROTOCOL_HEADER.len() {
+                return true;
+            }
+            if &self.input[..PROTOCOL_HEADER.len()] != PROTOCOL_HEADER {
+                // Per the spec, reply with the protocol version we support and hang up.
+                self.outbound.send(OutputBuffer::with_protocol_header());
+                return false;
+            }
+            self.input.drain(..PROTOCOL_HEADER.len());
+            self.got_protocol_header = true;
+            self.send_start();
+        }
+
+        loop {
+            if self.input.len() < 7 {
+                return true;
+            }
+            let size =
+                u32::from_be_bytes([self.input[3], self.input[4], self.input[5], self.input[6]])
+                    as usize
+                    + FRAME_OVERHEAD;
+            if self.input.len() < size {
+                return true;
+            }
+            let frame = match parse_frame(&self.input[..size]) {
+                Ok((_, frame)) => frame,
+                Err(_) => {
+                    self.input.drain(..size);
+                    self.connection_exception(
+                        AMQPHardError::FRAMEERROR,
+                        "mock broker could not parse frame".to_string(),
+                    );
+                    continue;
+                }
+            };
+            self.input.drain(..size);
+
+            match self.handle_frame(frame) {
+                Ok(true) => (),
+                Ok(false) => return false,
+                Err(Exception::Channel(channel_id, code, text)) => {
+                    self.channel_exception(channel_id, code, text)
+                }
+                Err(Exception::Connection(code, text)) => self.connection_exception(code, text),
+            }
+        }
+    }
+
+    fn send_start(&self) {
+        let mut capabilities = FieldTable::new();
+        for capability in &["publisher_confirms", "consumer_cancel_notify", "basic.nack"] {
+            capabilities.insert(capability.to_string(), AMQPValue::Boolean(true));
+        }
+        let mut server_properties = FieldTable::new();
+        server_properties.insert(
+            "product".to_string(),
+            AMQPValue::LongString("amiquip mock broker".to_string()),
+        );
+        server_properties.insert(
+            "version".to_string(),
+            AMQPValue::LongString(crate::built_info::PKG_VERSION.to_string()),
+        );
+        server_properties.insert(
+            "capabilities".to_string(),
+            AMQPValue::FieldTable(capabilities),
+        );
+        self.send(
+            0,
+            AmqpConnection::Start(Start {
+                version_major: 0,
+                version_minor: 9,
+                server_properties,
+                mechanisms: "PLAIN EXTERNAL".to_string(),
+                locales: "en_US".to_string(),
+            }),
+        );
+    }
+
+    fn connection_exception(&mut self, code: AMQPHardError, text: String) {
+        if self.closing {
+            return;
+        }
+        warn!("mock broker closing connection: {}", text);
+        self.closing = true;
+        self.send(
+            0,
+            AmqpConnection::Close(ConnectionClose {
+                reply_code: code.get_id(),
+                reply_text: text,
+                class_id: 0,
+                method_id: 0,
+            }),
+        );
+    }
+
+    fn channel_exception(&mut self, channel_id: u16, code: AMQPSoftError, text: String) {
+        debug!("mock broker closing channel {}: {}", channel_id, text);
+        self.close_channel_state(channel_id);
+        self.channels.insert(
+            channel_id,
+            ChannelState {
+                closing: true,
+                ..ChannelState::default()
+            },
+        );
+        self.send(
+            channel_id,
+            AmqpChannel::Close(ChannelClose {
+                reply_code: code.get_id(),
+                reply_text: text,
+                class_id: 0,
+                method_id: 0,
+            }),
+        );
+    }
+
+    // Forget all state associated with a channel, requeueing unacked messages.
+    fn close_channel_state(&mut self, channel_id: u16) {
+        let channel = match self.channels.remove(&channel_id) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let mut broker = self.broker.lock().unwrap();
+        for consumer_tag in channel.consumers.keys() {
+            broker.remove_consumer(self.id, channel_id, consumer_tag);
+        }
+        for (_, (queue, mut message)) in channel.unacked.into_iter().rev() {
+            message.redelivered = true;
+            broker.enqueue(&queue, message, true);
+        }
+    }
+
+    fn cleanup(&mut self) {
+        let channel_ids = self.channels.keys().cloned().collect::<Vec<_>>();
+        for channel_id in channel_ids {
+            self.close_channel_state(channel_id);
+        }
+        let mut broker = self.broker.lock().unwrap();
+        let exclusive = broker
+            .queues
+            .iter()
+            .filter(|(_, queue)| queue.owner == Some(self.id))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in exclusive {
+            broker.delete_queue(&name);
+        }
+    }
+
+    fn handle_event(&mut self, event: SessionEvent) {
+        match event {
+            SessionEvent::Deliver {
+                channel_id,
+                consumer_tag,
+                queue,
+                message,
+            } => {
+                let (channel, no_ack) = match self.channels.get_mut(&channel_id) {
+                    Some(channel) if channel.consumers.contains_key(&consumer_tag) => {
+                        let no_ack = channel.consumers[&consumer_tag];
+                        (channel, no_ack)
+                    }
+                    // consumer was cancelled while this delivery was in flight
+                    _ => {
+                        self.broker.lock().unwrap().enqueue(&queue, message, true);
+                        return;
+                    }
+                };
+                channel.next_delivery_tag += 1;
+                let delivery_tag = channel.next_delivery_tag;
+                let deliver = Deliver {
+                    consumer_tag,
+                    delivery_tag,
+                    redelivered: message.redelivered,
+                    exchange: message.exchange.clone(),
+                    routing_key: message.routing_key.clone(),
+                };
+                let (properties, body) = (message.properties.clone(), message.body.clone());
+                if !no_ack {
+                    channel.unacked.insert(delivery_tag, (queue, message));
+                }
+                self.send_content(channel_id, AmqpBasic::Deliver(deliver), &properties, &body);
+            }
+            SessionEvent::Cancelled {
+                channel_id,
+                consumer_tag,
+            } => {
+                if let Some(channel) = self.channels.get_mut(&channel_id) {
+                    if channel.consumers.remove(&consumer_tag).is_some() && self.cancel_notify {
+                        self.send(
+                            channel_id,
+                            AmqpBasic::Cancel(Cancel {
+                                consumer_tag,
+                                nowait: true,
+                            }),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns Ok(false) once the connection is done.
+    fn handle_frame(&mut self, frame: AMQPFrame) -> std::result::Result<bool, Exception> {
+        // After we close the connection, discard everything until the client's close-ok.
+        if self.closing {
+            return match frame {
+                AMQPFrame::Method(0, AMQPClass::Connection(AmqpConnection::CloseOk(_))) => {
+                    Ok(false)
+                }
+                _ => Ok(true),
+            };
+        }
+
+        let (channel_id, class) = match frame {
+            AMQPFrame::Heartbeat(_) => return Ok(true),
+            AMQPFrame::Method(channel_id, class) => (channel_id, class),
+            AMQPFrame::Header(channel_id, _, header) => {
+                let channel = self.open_channel(channel_id)?;
+                if channel.closing {
+                    return Ok(true);
+                }
+                match &mut channel.pending {
+                    Some(pending) if pending.properties.is_none() => {
+                        pending.body_size = header.body_size as usize;
+                        pending.properties = Some(header.properties);
+                    }
+                    _ => return unexpected_frame("content header"),
+                }
+                self.maybe_finish_publish(channel_id)?;
+                return Ok(true);
+            }
+            AMQPFrame::Body(channel_id, body) => {
+                let channel = self.open_channel(channel_id)?;
+                if channel.closing {
+                    return Ok(true);
+                }
+                match &mut channel.pending {
+                    Some(pending) if pending.properties.is_some() => pending.body.extend(body),
+                    _ => return unexpected_frame("content body"),
+                }
+                self.maybe_finish_publish(channel_id)?;
+                return Ok(true);
+            }
+            AMQPFrame::ProtocolHeader => return unexpected_frame("protocol header"),
+        };
+
+        if channel_id == 0 {
+            return self.handle_connection_method(class);
+        }
+
+        // After we close a channel, discard everything on it until the client's close-ok.
+        if let Some(channel) = self.channels.get(&channel_id) {
+            if channel.closing {
+                if let AMQPClass::Channel(AmqpChannel::CloseOk(_)) = class {
+                    self.channels.remove(&channel_id);
+                }
+                return Ok(true);
+            }
+            if channel.pending.is_some() {
+                return unexpected_frame("method while collecting content");
+            }
+        }
+
+        match class {
+            AMQPClass::Channel(AmqpChannel::Open(_)) => {
+                if self.channels.contains_key(&channel_id) {
+                    return Err(Exception::Connection(
+                        AMQPHardError::CHANNELERROR,
+                        format!("channel {} is already open", channel_id),
+                    ));
+                }
+                self.channels.insert(channel_id, ChannelState::default());
+                self.send(
+                    channel_id,
+                    AmqpChannel::OpenOk(ChannelOpenOk {
+                        channel_id: String::new(),
+                    }),
+                );
+            }
+            AMQPClass::Channel(AmqpChannel::Close(_)) => {
+                self.open_channel(channel_id)?;
+                self.close_channel_state(channel_id);
+                self.send(channel_id, AmqpChannel::CloseOk(ChannelCloseOk {}));
+            }
+            AMQPClass::Exchange(method) => self.handle_exchange_method(channel_id, method)?,
+            AMQPClass::Queue(method) => self.handle_queue_method(channel_id, method)?,
+            AMQPClass::Basic(method) => self.handle_basic_method(channel_id, method)?,
+            AMQPClass::Confirm(AmqpConfirm::Select(select)) => {
+                let channel = self.open_channel(channel_id)?;
+                if channel.next_publish_tag.is_none() {
+                    channel.next_publish_tag = Some(1);
+                }
+                if !select.nowait {
+                    self.send(channel_id, AmqpConfirm::SelectOk(SelectOk {}));
+                }
+            }
+            other => return not_implemented(other),
+        }
+        Ok(true)
+    }
+
+    fn handle_connection_method(
+        &mut self,
+        class: AMQPClass,
+    ) -> std::result::Result<bool, Exception> {
+        match class {
+            AMQPClass::Connection(AmqpConnection::StartOk(start_ok)) => {
+                self.cancel_notify = match start_ok.client_properties.get("capabilities") {
+                    Some(AMQPValue::FieldTable(capabilities)) => {
+                        capabilities.get("consumer_cancel_notify")
+                            == Some(&AMQPValue::Boolean(true))
+                    }
+                    _ => false,
+                };
+                self.send(
+                    0,
+                    AmqpConnection::Tune(Tune {
+                        channel_max: 0,
+                        frame_max: FRAME_MAX,
+                        // We don't send heartbeats, so don't ask the client for them either.
+                        heartbeat: 0,
+                    }),
+                )
+            }
+            AMQPClass::Connection(AmqpConnection::TuneOk(tune_ok)) => {
+                self.frame_max = u32::min(tune_ok.frame_max, FRAME_MAX);
+            }
+            AMQPClass::Connection(AmqpConnection::Open(_)) => self.send(
+                0,
+                AmqpConnection::OpenOk(OpenOk {
+                    known_hosts: String::new(),
+                }),
+            ),
+            AMQPClass::Connection(AmqpConnection::Close(_)) => {
+                self.send(0, AmqpConnection::CloseOk(ConnectionCloseOk {}));
+                return Ok(false);
+            }
+            other => return not_implemented(other),
+        }
+        Ok(true)
+    }
+
+    fn handle_exchange_method(&mut self, channel_id: u16, method: AmqpExchange) -> SessionResult {
+        self.open_channel(channel_id)?;
+        let mut broker = self.broker.lock().unwrap();
+        match method {
+            AmqpExchange::Declare(declare) => {
+                let exists =
+                    declare.exchange == "" || broker.exchanges.contains_key(&declare.exchange);
+                if declare.passive {
+                    if !exists {
+                        return Err(not_found(channel_id, "exchange", &declare.exchange));
+                    }
+                } else if declare.type_ != "direct" {
+                    return Err(Exception::Channel(
+                        channel_id,
+                        AMQPSoftError::PRECONDITIONFAILED,
+                        format!(
+                            "mock broker does not support exchange type '{}'",
+                            declare.type_
+                        ),
+                    ));
+                } else if !exists && declare.exchange.starts_with("amq.") {
+                    return Err(Exception::Channel(
+                        channel_id,
+                        AMQPSoftError::ACCESSREFUSED,
+                        format!(
+                            "exchange name '{}' contains reserved prefix 'amq.'",
+                            declare.exchange
+                        ),
+                    ));
+                } else if !exists {
+                    broker.exchanges.insert(declare.exchange, HashSet::new());
+                }
+                if !declare.nowait {
+                    self.send(channel_id, AmqpExchange::DeclareOk(ExchangeDeclareOk {}));
+                }
+            }
+            AmqpExchange::Delete(delete) => {
+                if broker.exchanges.remove(&delete.exchange).is_none() {
+                    return Err(not_found(channel_id, "exchange", &delete.exchange));
+                }
+                if !delete.nowait {
+                    self.send(channel_id, AmqpExchange::DeleteOk(ExchangeDeleteOk {}));
+                }
+            }
+            other => return not_implemented(AMQPClass::Exchange(other)).map(|_| ()),
+        }
+        Ok(())
+    }
+
+    fn handle_queue_method(&mut self, channel_id: u16, method: AmqpQueue) -> SessionResult {
+        self.open_channel(channel_id)?;
+        let mut broker = self.broker.lock().unwrap();
+        match method {
+            AmqpQueue::Declare(declare) => {
+                let name = if declare.queue == "" {
+                    broker.next_queue_id += 1;
+                    format!("amq.gen-{}-{}", self.id, broker.next_queue_id)
+                } else {
+                    declare.queue
+                };
+                if !broker.queues.contains_key(&name) {
+                    if declare.passive {
+                        return Err(not_found(channel_id, "queue", &name));
+                    }
+                    let queue = QueueState {
+                        owner: if declare.exclusive {
+                            Some(self.id)
+                        } else {
+                            None
+                        },
+                        auto_delete: declare.auto_delete,
+                        arguments: declare.arguments.clone(),
+                        ..QueueState::default()
+                    };
+                    broker.queues.insert(name.clone(), queue);
+                }
+                let queue = &broker.queues[&name];
+                check_owner(channel_id, self.id, &name, queue)?;
+                if !declare.passive {
+                    check_arguments(channel_id, &name, &queue.arguments, &declare.arguments)?;
+                }
+                if !declare.nowait {
+                    let declare_ok = QueueDeclareOk {
+                        queue: name.clone(),
+                        message_count: queue.messages.len() as u32,
+                        consumer_count: queue.consumers.len() as u32,
+                    };
+                    self.send(channel_id, AmqpQueue::DeclareOk(declare_ok));
+                }
+            }
+            AmqpQueue::Bind(bind) => {
+                let queue = broker
+                    .queues
+                    .get(&bind.queue)
+                    .ok_or_else(|| not_found(channel_id, "queue", &bind.queue))?;
+                check_owner(channel_id, self.id, &bind.queue, queue)?;
+                if bind.exchange == "" {
+                    return Err(Exception::Channel(
+                        channel_id,
+                        AMQPSoftError::ACCESSREFUSED,
+                        "cannot bind to the default exchange".to_string(),
+                    ));
+                }
+                broker
+                    .exchanges
+                    .get_mut(&bind.exchange)
+                    .ok_or_else(|| not_found(channel_id, "exchange", &bind.exchange))?
+                    .insert((bind.routing_key, bind.queue));
+                if !bind.nowait {
+                    self.send(channel_id, AmqpQueue::BindOk(BindOk {}));
+                }
+            }
+            AmqpQueue::Unbind(unbind) => {
+                if let Some(bindings) = broker.exchanges.get_mut(&unbind.exchange) {
+                    bindings.remove(&(unbind.routing_key, unbind.queue));
+                }
+                self.send(channel_id, AmqpQueue::UnbindOk(UnbindOk {}));
+            }
+            AmqpQueue::Purge(purge) => {
+                let queue = broker
+                    .queues
+                    .get_mut(&purge.queue)
+                    .ok_or_else(|| not_found(channel_id, "queue", &purge.queue))?;
+                let message_count = queue.messages.len() as u32;
+                queue.messages.clear();
+                if !purge.nowait {
+                    self.send(channel_id, AmqpQueue::PurgeOk(PurgeOk { message_count }));
+                }
+            }
+            AmqpQueue::Delete(delete) => {
+                let message_count = match broker.queues.get(&delete.queue) {
+                    Some(queue) => {
+                        check_owner(channel_id, self.id, &delete.queue, queue)?;
+                        if delete.if_unused && !queue.consumers.is_empty() {
+                            return Err(Exception::Channel(
+                                channel_id,
+                                AMQPSoftError::PRECONDITIONFAILED,
+                                format!("queue '{}' in use", delete.queue),
+                            ));
+                        }
+                        if delete.if_empty && !queue.messages.is_empty() {
+                            return Err(Exception::Channel(
+                                channel_id,
+                                AMQPSoftError::PRECONDITIONFAILED,
+                                format!("queue '{}' not empty", delete.queue),
+                            ));
+                        }
+                        broker.delete_queue(&delete.queue).unwrap_or(0)
+                    }
+                    // Like RabbitMQ, deleting a nonexistent queue succeeds.
+                    None => 0,
+                };
+                if !delete.nowait {
+                    self.send(
+                        channel_id,
+                        AmqpQueue::DeleteOk(QueueDeleteOk { message_count }),
+                    );
+                }
+            }
+            other => return not_implemented(AMQPClass::Queue(other)).map(|_| ()),
+        }
+        Ok(())
+    }
+
+    fn handle_basic_method(&mut self, channel_id: u16, method: AmqpBasic) -> SessionResult {
+        match method {
+            AmqpBasic::Qos(_) => {
+                self.open_channel(channel_id)?;
+                self.send(channel_id, AmqpBasic::QosOk(QosOk {}));
+            }
+            AmqpBasic::Publish(publish) => {
+                self.open_channel(channel_id)?;
+                let broker = self.broker.lock().unwrap();
+                if broker
+                    .route(&publish.exchange, &publish.routing_key)
+                    .is_none()
+                {
+                    return Err(not_found(channel_id, "exchange", &publish.exchange));
+                }
+                drop(broker);
+                self.open_channel(channel_id)?.pending = Some(PendingPublish {
+                    publish,
+                    properties: None,
+                    body_size: 0,
+                    body: Vec::new(),
+                });
+            }
+            AmqpBasic::Consume(consume) => {
+                let channel = self.open_channel(channel_id)?;
+                let consumer_tag = if consume.consumer_tag == "" {
+                    channel.next_consumer_id += 1;
+                    format!("amq.ctag-{}", channel.next_consumer_id)
+                } else {
+                    consume.consumer_tag
+                };
+                if channel.consumers.contains_key(&consumer_tag) {
+                    return Err(Exception::Connection(
+                        AMQPHardError::NOTALLOWED,
+                        format!("attempt to reuse consumer tag '{}'", consumer_tag),
+                    ));
+                }
+                let mut broker = self.broker.lock().unwrap();
+                let queue = broker
+                    .queues
+                    .get_mut(&consume.queue)
+                    .ok_or_else(|| not_found(channel_id, "queue", &consume.queue))?;
+                check_owner(channel_id, self.id, &consume.queue, queue)?;
+                if queue.consumers.iter().any(|c| c.exclusive)
+                    || (consume.exclusive && !queue.consumers.is_empty())
+                {
+                    return Err(Exception::Channel(
+                        channel_id,
+                        AMQPSoftError::ACCESSREFUSED,
+                        format!("queue '{}' in exclusive use", consume.queue),
+                    ));
+                }
+                if !consume.nowait {
+                    self.send(
+                        channel_id,
+                        AmqpBasic::ConsumeOk(ConsumeOk {
+                            consumer_tag: consumer_tag.clone(),
+                        }),
+                    );
+                }
+                queue.consumers.push(ConsumerRef {
+                    session_id: self.id,
+                    channel_id,
+                    consumer_tag: consumer_tag.clone(),
+                    exclusive: consume.exclusive,
+                    events: self.events.clone(),
+                });
+                broker.dispatch(&consume.queue);
+                drop(broker);
+                self.open_channel(channel_id)?
+                    .consumers
+                    .insert(consumer_tag, consume.no_ack);
+            }
+            AmqpBasic::Cancel(cancel) => {
+                let channel = self.open_channel(channel_id)?;
+                channel.consumers.remove(&cancel.consumer_tag);
+                self.broker.lock().unwrap().remove_consumer(
+                    self.id,
+                    channel_id,
+                    &cancel.consumer_tag,
+                );
+                if !cancel.nowait {
+                    self.send(
+                        channel_id,
+                        AmqpBasic::CancelOk(CancelOk {
+                            consumer_tag: cancel.consumer_tag,
+                        }),
+                    );
+                }
+            }
+            AmqpBasic::Get(get) => {
+                self.open_channel(channel_id)?;
+                let mut broker = self.broker.lock().unwrap();
+                let queue = broker
+                    .queues
+                    .get_mut(&get.queue)
+                    .ok_or_else(|| not_found(channel_id, "queue", &get.queue))?;
+                check_owner(channel_id, self.id, &get.queue, queue)?;
+                let message = queue.messages.pop_front();
+                let message_count = queue.messages.len() as u32;
+                drop(broker);
+
+                let message = match message {
+                    Some(message) => message,
+                    None => {
+                        self.send(
+                            channel_id,
+                            AmqpBasic::GetEmpty(GetEmpty {
+                                cluster_id: String::new(),
+                            }),
+                        );
+                        return Ok(());
+                    }
+                };
+                let channel = self.open_channel(channel_id)?;
+                channel.next_delivery_tag += 1;
+                let delivery_tag = channel.next_delivery_tag;
+                let get_ok = GetOk {
+                    delivery_tag,
+                    redelivered: message.redelivered,
+                    exchange: message.exchange.clone(),
+                    routing_key: message.routing_key.clone(),
+                    message_count,
+                };
+                let (properties, body) = (message.properties.clone(), message.body.clone());
+                if !get.no_ack {
+                    channel.unacked.insert(delivery_tag, (get.queue, message));
+                }
+                self.send_content(channel_id, AmqpBasic::GetOk(get_ok), &properties, &body);
+            }
+            AmqpBasic::Ack(ack) => {
+                self.settle(channel_id, ack.delivery_tag, ack.multiple, false)?;
+            }
+            AmqpBasic::Nack(nack) => {
+                self.settle(channel_id, nack.delivery_tag, nack.multiple, nack.requeue)?;
+            }
+            AmqpBasic::Reject(reject) => {
+                self.settle(channel_id, reject.delivery_tag, false, reject.requeue)?;
+            }
+            AmqpBasic::Recover(_) => {
+                let channel = self.open_channel(channel_id)?;
+                let tags = channel.unacked.keys().cloned().collect::<Vec<_>>();
+                for tag in tags {
+                    self.settle(channel_id, tag, false, true)?;
+                }
+                self.send(channel_id, AmqpBasic::RecoverOk(RecoverOk {}));
+            }
+            other => return not_implemented(AMQPClass::Basic(other)).map(|_| ()),
+        }
+        Ok(())
+    }
+
+    // Remove acked / nacked / rejected deliveries, requeueing them if asked.
+    fn settle(
+        &mut self,
+        channel_id: u16,
+        delivery_tag: u64,
+        multiple: bool,
+        requeue: bool,
+    ) -> SessionResult {
+        let channel = self.open_channel(channel_id)?;
+        let settled = if multiple {
+            let tag = if delivery_tag == 0 {
+                u64::max_value()
+            } else {
+                delivery_tag
+            };
+            let rest = channel.unacked.split_off(&(tag.saturating_add(1)));
+            std::mem::replace(&mut channel.unacked, rest)
+        } else {
+            match channel.unacked.remove(&delivery_tag) {
+                Some(delivery) => {
+                    let mut settled = BTreeMap::new();
+                    settled.insert(delivery_tag, delivery);
+                    settled
+                }
+                None => BTreeMap::new(),
+            }
+        };
+        if settled.is_empty() && delivery_tag != 0 {
+            return Err(Exception::Channel(
+                channel_id,
+                AMQPSoftError::PRECONDITIONFAILED,
+                format!("unknown delivery tag {}", delivery_tag),
+            ));
+        }
+        if requeue {
+            let mut broker = self.broker.lock().unwrap();
+            for (_, (queue, mut message)) in settled.into_iter().rev() {
+                message.redelivered = true;
+                broker.enqueue(&queue, message, true);
+            }
+        }
+        Ok(())
+    }
+
+    fn maybe_finish_publish(&mut self, channel_id: u16) -> SessionResult {
+        let channel = self.open_channel(channel_id)?;
+        match &channel.pending {
+            Some(PendingPublish {
+                properties: Some(_),
+                body_size,
+                body,
+                ..
+            }) if body.len() >= *body_size => (),
+            _ => return Ok(()),
+        }
+        // We just checked that pending is Some with properties; safe to unwrap.
+        let pending = channel.pending.take().unwrap();
+        let confirm_tag = channel.next_publish_tag;
+        if let Some(tag) = &mut channel.next_publish_tag {
+            *tag += 1;
+        }
+
+        let message = Message {
+            exchange: pending.publish.exchange,
+            routing_key: pending.publish.routing_key,
+            properties: pending.properties.unwrap(),
+            body: pending.body,
+            redelivered: false,
+        };
+        let mut broker = self.broker.lock().unwrap();
+        let queues = broker
+            .route(&message.exchange, &message.routing_key)
+            .unwrap_or_default();
+        for queue in &queues {
+            broker.enqueue(queue, message.clone(), false);
+        }
+        drop(broker);
+
+        if queues.is_empty() && pending.publish.mandatory {
+            let return_ = Return {
+                reply_code: AMQPSoftError::NOROUTE.get_id(),
+                reply_text: "NO_ROUTE".to_string(),
+                exchange: message.exchange.clone(),
+                routing_key: message.routing_key.clone(),
+            };
+            self.send_content(
+                channel_id,
+                AmqpBasic::Return(return_),
+                &message.properties,
+                &message.body,
+            );
+        }
+        if let Some(delivery_tag) = confirm_tag {
+            self.send(
+                channel_id,
+                AmqpBasic::Ack(Ack {
+                    delivery_tag,
+                    multiple: false,
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    fn open_channel(
+        &mut self,
+        channel_id: u16,
+    ) -> std::result::Result<&mut ChannelState, Exception> {
+        match self.channels.get_mut(&channel_id) {
+            Some(channel) => Ok(channel),
+            None => Err(Exception::Connection(
+                AMQPHardError::CHANNELERROR,
+                format!("channel {} is not open", channel_id),
+            )),
+        }
+    }
+}
+
+// Like RabbitMQ, refuse to redeclare a queue with different arguments.
+fn check_arguments(
+    channel_id: u16,
+    name: &str,
+    current: &FieldTable,
+    received: &FieldTable,
+) -> SessionResult {
+    let inequivalent = current
+        .keys()
+        .chain(received.keys())
+        .find(|key| current.get(*key) != received.get(*key));
+    match inequivalent {
+        Some(key) => Err(Exception::Channel(
+            channel_id,
+            AMQPSoftError::PRECONDITIONFAILED,
+            format!(
+                "inequivalent arg '{}' for queue '{}' in vhost '/'",
+                key, name
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn check_owner(channel_id: u16, session_id: u64, name: &str, queue: &QueueState) -> SessionResult {
+    match queue.owner {
+        Some(owner) if owner != session_id => Err(Exception::Channel(
+            channel_id,
+            AMQPSoftError::RESOURCELOCKED,
+            format!("cannot obtain exclusive access to locked queue '{}'", name),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn not_found(channel_id: u16, kind: &str, name: &str) -> Exception {
+    Exception::Channel(
+        channel_id,
+        AMQPSoftError::NOTFOUND,
+        format!("no {} '{}'", kind, name),
+    )
+}
+
+fn unexpected_frame<T>(what: &str) -> std::result::Result<T, Exception> {
+    Err(Exception::Connection(
+        AMQPHardError::UNEXPECTEDFRAME,
+        format!("mock broker received unexpected {}", what),
+    ))
+}
+
+fn not_implemented(class: AMQPClass) -> std::result::Result<bool, Exception> {
+    Err(Exception::Connection(
+        AMQPHardError::NOTIMPLEMENTED,
+        format!("mock broker does not implement {:?}", class),
+    ))
+}
+
+#[cfg(test)]
+pub(crate) fn with_mock_conn<F: FnOnce(&mut Connection)>(f: F) {
+    let broker = MockBroker::new();
+    let mut connection = broker.connect().unwrap();
+    f(&mut connection);
+    connection.close().unwrap();
+}
+
+#[cfg(test)]
+pub(crate) fn with_mock_chan<F: FnOnce(&crate::Channel)>(f: F) {
+    with_mock_conn(|connection| {
+        let channel = connection.open_channel(None).unwrap();
+        f(&channel)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ConsumerMessage, ConsumerOptions, Exchange, ExchangeDeclareOptions, ExchangeType, Publish,
+        QueueDeclareOptions,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn publish_and_consume() {
+        let broker = MockBroker::new();
+        let mut publisher = broker.connect().unwrap();
+        let mut subscriber = broker.connect().unwrap();
+
+        let pub_channel = publisher.open_channel(None).unwrap();
+        let sub_channel = subscriber.open_channel(None).unwrap();
+        let queue = sub_channel
+            .queue_declare("work", QueueDeclareOptions::default())
+            .unwrap();
+        let consumer = queue.consume(ConsumerOptions::default()).unwrap();
+
+        // bodies larger than a frame are split and reassembled
+        let big = vec![7; FRAME_MAX as usize * 2];
+        let exchange = Exchange::direct(&pub_channel);
+        exchange.publish(Publish::new(b"first", "work")).unwrap();
+        exchange.publish(Publish::new(&big, "work")).unwrap();
+
+        for expected in &[&b"first"[..], &big[..]] {
+            match consumer.receiver().recv_timeout(Duration::from_secs(5)) {
+                Ok(ConsumerMessage::Delivery(delivery)) => {
+                    assert_eq!(&delivery.body[..], *expected);
+                    consumer.ack(delivery).unwrap();
+                }
+                other => panic!("unexpected consumer message {:?}", other),
+            }
+        }
+
+        publisher.close().unwrap();
+        subscriber.close().unwrap();
+    }
+
+    #[test]
+    fn get_nack_requeue() {
+        with_mock_chan(|channel| {
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            assert!(queue.get(false).unwrap().is_none());
+
+            channel
+                .basic_publish("", Publish::new(b"hello", queue.name()))
+                .unwrap();
+            let get = queue.get(false).unwrap().unwrap();
+            assert!(!get.delivery.redelivered);
+            assert_eq!(get.delivery.consumer_tag(), None);
+            get.nack(&channel, true).unwrap();
+
+            let get = queue.get(false).unwrap().unwrap();
+            assert!(get.delivery.redelivered);
+            assert_eq!(get.delivery.body, b"hello");
+            get.ack(&channel).unwrap();
+            assert!(queue.get(false).unwrap().is_none());
+        })
+    }
+
+    #[test]
+    fn direct_exchange_routing() {
+        with_mock_chan(|channel| {
+            let exchange = channel
+                .exchange_declare(
+                    ExchangeType::Direct,
+                    "logs",
+                    ExchangeDeclareOptions::default(),
+                )
+                .unwrap();
+            let errors = channel
+                .queue_declare("errors", QueueDeclareOptions::default())
+                .unwrap();
+            errors.bind(&exchange, "error", FieldTable::new()).unwrap();
+
+            exchange.publish(Publish::new(b"oops", "error")).unwrap();
+            exchange.publish(Publish::new(b"fine", "info")).unwrap();
+
+            let get = errors.get(true).unwrap().unwrap();
+            assert_eq!(get.delivery.body, b"oops");
+            assert_eq!(get.delivery.exchange(), "logs");
+            assert_eq!(get.delivery.routing_key(), "error");
+            assert!(errors.get(true).unwrap().is_none());
+
+            // the default exchange is reported as an empty name
+            let consumer = errors.consume(ConsumerOptions::default()).unwrap();
+            Exchange::direct(&channel)
+                .publish(Publish::new(b"direct", "errors"))
+                .unwrap();
+            match consumer.receiver().recv_timeout(Duration::from_secs(5)) {
+                Ok(ConsumerMessage::Delivery(delivery)) => {
+                    assert_eq!(delivery.exchange(), "");
+                    assert_eq!(delivery.routing_key(), "errors");
+                    consumer.ack(delivery).unwrap();
+                }
+                other => panic!("unexpected consumer message {:?}", other),
+            }
+        })
+    }
+
+    #[test]
+    fn missing_queue_closes_channel() {
+        with_mock_conn(|connection| {
+            let channel = connection.open_channel(None).unwrap();
+
+            let res = channel.queue_declare_passive("nonexistent");
+            match res.unwrap_err() {
+                Error::ServerClosedChannel { code, .. } => {
+                    assert_eq!(code, AMQPSoftError::NOTFOUND.get_id())
+                }
+                err => panic!("unexpected error {}", err),
+            }
+
+            // the connection is still usable
+            let channel = connection.open_channel(None).unwrap();
+            channel
+                .queue_declare("exists", QueueDeclareOptions::default())
+                .unwrap();
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use crate::timestamp::duration_millis;
+use crate::AmqpProperties;
+use std::time::Duration;
+
+/// Extension methods for [`AmqpProperties`](struct.AmqpProperties.html).
+///
+/// `AmqpProperties` is defined by the `amq-protocol` crate; this trait adds builder methods for
+/// properties whose wire format is easy to get wrong by hand.
+///
+/// # Example
+///
+/// ```rust
+/// # use amiquip::{AmqpProperties, AmqpPropertiesExt};
+/// # use std::time::Duration;
+/// let properties = AmqpProperties::default().with_expiration_ttl(Duration::from_millis(1500));
+/// assert_eq!(properties.expiration(), &Some("1500".to_string()));
+/// ```
+pub trait AmqpPropertiesExt {
+    /// Set a per-message TTL by setting the `expiration` property. If the message is still
+    /// waiting in a queue once `ttl` has elapsed, the server discards it (or dead-letters it, if
+    /// the queue is configured to).
+    ///
+    /// RabbitMQ requires `expiration` to be a string containing a whole number of milliseconds
+    /// (e.g., `"1500"`, not `"1.5"` or `"1500ms"`); this formats `ttl` that way, rounding down to
+    /// the nearest millisecond. If the queue also has a TTL (see
+    /// [`QueueDeclareOptions::message_ttl`](struct.QueueDeclareOptions.html#method.message_ttl)),
+    /// the smaller of the two applies.
+    fn with_expiration_ttl(self, ttl: Duration) -> AmqpProperties;
+}
+
+impl AmqpPropertiesExt for AmqpProperties {
+    fn with_expiration_ttl(self, ttl: Duration) -> AmqpProperties {
+        self.with_expiration(duration_millis(ttl).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiration_is_millis_string() {
+        let properties =
+            AmqpProperties::default().with_expiration_ttl(Duration::from_micros(90_061_500_900));
+        assert_eq!(
+            properties.expiration().as_ref().map(String::as_str),
+            Some("90061500")
+        );
+        let properties = AmqpProperties::default().with_expiration_ttl(Duration::from_micros(999));
+        assert_eq!(
+            properties.expiration().as_ref().map(String::as_str),
+            Some("0")
+        );
+    }
+}
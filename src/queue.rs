@@ -1,5 +1,14 @@
-use crate::{Channel, Consumer, ConsumerOptions, Exchange, FieldTable, Get, Result};
+use crate::errors::*;
+use crate::logging::debug;
+use crate::timestamp::duration_millis;
+use crate::{
+    AmqpValue, BackoffStrategy, Channel, Consumer, ConsumerOptions, Exchange, FieldTable, Get,
+    GetBackoff, HeadersMatch, StreamConsumer, StreamOffset, StreamingGet,
+};
 use amq_protocol::protocol::queue::{Declare, Delete};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
 /// Options passed to the server when declaring a queue.
 ///
@@ -8,20 +17,14 @@ use amq_protocol::protocol::queue::{Declare, Delete};
 ///
 /// # Example
 ///
-/// The [`arguments`](#structfield.arguments) field can be used to declare a
-/// [quorum queue](https://www.rabbitmq.com/quorum-queues.html):
+/// Declaring a [quorum queue](https://www.rabbitmq.com/quorum-queues.html) that dead-letters
+/// messages after 5 failed deliveries:
 ///
 /// ```rust
-/// # use amiquip::{AmqpValue, QueueDeclareOptions, FieldTable};
-/// let mut arguments = FieldTable::new();
-/// arguments.insert(
-///     "x-queue-type".to_string(),
-///     AmqpValue::LongString("quorum".to_string()),
-/// );
-/// let options = QueueDeclareOptions {
-///     arguments,
-///     ..QueueDeclareOptions::default()
-/// };
+/// # use amiquip::{QueueDeclareOptions, QueueType};
+/// let options = QueueDeclareOptions::default()
+///     .queue_type(QueueType::Quorum)
+///     .delivery_limit(5);
 /// ```
 #[derive(Clone, Debug, Default)]
 pub struct QueueDeclareOptions {
@@ -41,11 +44,111 @@ pub struct QueueDeclareOptions {
     pub auto_delete: bool,
 
     /// Extra arguments; these are optional in general, but may be needed for some plugins or
-    /// server-specific features.
+    /// server-specific features. [`FieldTableBuilder`](struct.FieldTableBuilder.html) can be used
+    /// to build arguments containing nested tables or arrays.
     pub arguments: FieldTable,
 }
 
+/// The type of a RabbitMQ queue, sent as the `x-queue-type` argument when declaring it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueType {
+    /// A classic queue (the server's default).
+    Classic,
+
+    /// A replicated [quorum queue](https://www.rabbitmq.com/quorum-queues.html).
+    Quorum,
+
+    /// An append-only [stream](https://www.rabbitmq.com/streams.html).
+    Stream,
+}
+
+impl QueueType {
+    fn as_str(self) -> &'static str {
+        match self {
+            QueueType::Classic => "classic",
+            QueueType::Quorum => "quorum",
+            QueueType::Stream => "stream",
+        }
+    }
+}
+
 impl QueueDeclareOptions {
+    /// Options for declaring a durable [stream](https://www.rabbitmq.com/streams.html) queue
+    /// (`x-queue-type: stream`). Streams must be durable and may not be exclusive or auto-delete;
+    /// further stream arguments (e.g., `x-max-length-bytes`) can be added to
+    /// [`arguments`](#structfield.arguments).
+    pub fn stream() -> QueueDeclareOptions {
+        QueueDeclareOptions::default().queue_type(QueueType::Stream)
+    }
+
+    /// Set the type of queue to declare (`x-queue-type`). Quorum queues and streams are always
+    /// durable, so selecting either of those types also sets [`durable`](#structfield.durable).
+    pub fn queue_type(mut self, queue_type: QueueType) -> QueueDeclareOptions {
+        if queue_type != QueueType::Classic {
+            self.durable = true;
+        }
+        self.argument(
+            "x-queue-type",
+            AmqpValue::LongString(queue_type.as_str().to_string()),
+        )
+    }
+
+    /// Set the number of replicas a quorum queue is initially created with
+    /// (`x-quorum-initial-group-size`).
+    pub fn quorum_initial_group_size(self, size: u32) -> QueueDeclareOptions {
+        self.argument(
+            "x-quorum-initial-group-size",
+            AmqpValue::LongLongInt(i64::from(size)),
+        )
+    }
+
+    /// Set the number of times a message in a quorum queue may be redelivered before it is
+    /// dead-lettered or dropped (`x-delivery-limit`).
+    pub fn delivery_limit(self, limit: u32) -> QueueDeclareOptions {
+        self.argument("x-delivery-limit", AmqpValue::LongLongInt(i64::from(limit)))
+    }
+
+    /// Set a TTL for every message in the queue (`x-message-ttl`), rounded down to the nearest
+    /// millisecond. Messages that have been in the queue longer than `ttl` are discarded (or
+    /// dead-lettered). If a message also has its own TTL (see
+    /// [`AmqpPropertiesExt::with_expiration_ttl`](trait.AmqpPropertiesExt.html#tymethod.with_expiration_ttl)),
+    /// the smaller of the two applies.
+    pub fn message_ttl(self, ttl: Duration) -> QueueDeclareOptions {
+        let millis = duration_millis(ttl) as i64;
+        self.argument("x-message-ttl", AmqpValue::LongLongInt(millis))
+    }
+
+    fn argument(mut self, key: &str, value: AmqpValue) -> QueueDeclareOptions {
+        self.arguments.insert(key.to_string(), value);
+        self
+    }
+
+    // The server refuses to declare quorum queues or streams that are not durable or that are
+    // exclusive or auto-delete, closing the channel; catch that before sending the declare.
+    pub(crate) fn check_compatible(&self) -> Result<()> {
+        let queue_type = match self.arguments.get("x-queue-type") {
+            Some(AmqpValue::LongString(queue_type)) => queue_type,
+            _ => return Ok(()),
+        };
+        if queue_type != QueueType::Quorum.as_str() && queue_type != QueueType::Stream.as_str() {
+            return Ok(());
+        }
+        let option = if !self.durable {
+            "non-durable"
+        } else if self.exclusive {
+            "exclusive"
+        } else if self.auto_delete {
+            "auto-delete"
+        } else {
+            return Ok(());
+        };
+        IncompatibleQueueOptions {
+            queue_type: queue_type.clone(),
+            option,
+        }
+        .fail()
+    }
+
     pub(crate) fn into_declare(self, queue: String, passive: bool, nowait: bool) -> Declare {
         Declare {
             ticket: 0,
@@ -85,6 +188,83 @@ impl QueueDeleteOptions {
     }
 }
 
+/// The result of [`Connection::queue_declare_or_get`](struct.Connection.html#method.queue_declare_or_get).
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueStatus {
+    /// Name of the queue.
+    pub name: String,
+
+    /// Number of messages in the queue at the time it was declared.
+    pub message_count: u32,
+
+    /// Number of consumers attached to the queue at the time it was declared.
+    pub consumer_count: u32,
+
+    /// True if the queue did not exist and was created with the requested options; false if it
+    /// already existed (in which case the requested options were not checked against it).
+    pub created: bool,
+}
+
+impl QueueStatus {
+    pub(crate) fn new(queue: &Queue, created: bool) -> QueueStatus {
+        QueueStatus {
+            name: queue.name().to_string(),
+            message_count: queue.declared_message_count().unwrap_or(0),
+            consumer_count: queue.declared_consumer_count().unwrap_or(0),
+            created,
+        }
+    }
+}
+
+/// A binding of a queue to an exchange, returned by [`Queue::bind`](struct.Queue.html#method.bind)
+/// and [`Queue::bind_headers`](struct.Queue.html#method.bind_headers).
+///
+/// The server only removes a binding when an unbind matches its exchange, routing key, *and*
+/// arguments exactly; an unbind that does not match any binding succeeds without doing anything.
+/// This is easy to get wrong for headers exchange bindings, whose arguments carry the headers to
+/// match. A `QueueBinding` records everything the bind sent, so [`unbind`](#method.unbind) always
+/// removes the binding it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueBinding {
+    queue: String,
+    exchange: String,
+    routing_key: String,
+    arguments: FieldTable,
+}
+
+impl QueueBinding {
+    /// Name of the bound queue.
+    #[inline]
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// Name of the exchange the queue is bound to.
+    #[inline]
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    /// Routing key of the binding.
+    #[inline]
+    pub fn routing_key(&self) -> &str {
+        &self.routing_key
+    }
+
+    /// Arguments of the binding, including any added by
+    /// [`Queue::bind_headers`](struct.Queue.html#method.bind_headers).
+    #[inline]
+    pub fn arguments(&self) -> &FieldTable {
+        &self.arguments
+    }
+
+    /// Synchronously remove this binding. `channel` does not need to be the channel the binding
+    /// was created on.
+    pub fn unbind(self, channel: &Channel) -> Result<()> {
+        channel.queue_unbind(self.queue, self.exchange, self.routing_key, self.arguments)
+    }
+}
+
 /// Handle for a declared AMQP queue.
 pub struct Queue<'a> {
     channel: &'a Channel,
@@ -140,11 +320,22 @@ impl<'a> Queue<'a> {
         self.consumer_count
     }
 
+    /// Passively redeclare this queue, returning a new handle whose
+    /// [`declared_message_count`](#method.declared_message_count) and
+    /// [`declared_consumer_count`](#method.declared_consumer_count) reflect the queue's current
+    /// state. See [`Channel::queue_declare_passive`](struct.Channel.html#method.queue_declare_passive)
+    /// for details, including how a queue that no longer exists is reported.
+    #[inline]
+    pub fn declare_passive(&self) -> Result<Queue<'a>> {
+        self.channel.queue_declare_passive(self.name.clone())
+    }
+
     /// Synchronously get a single message from the queue.
     ///
     /// On success, returns `Some(message)` if there was a message in the queue or `None` if there
-    /// were no messages in the queue. If `no_ack` is false, you are responsible for acknowledging
-    /// the returned message, typically via [`Get::ack`](struct.Get.html#method.ack).
+    /// were no messages ready in the queue (the server replied with `basic.get-empty`); errors are
+    /// never reported as `None`. If `no_ack` is false, you are responsible for acknowledging the
+    /// returned message, typically via [`Get::ack`](struct.Get.html#method.ack).
     ///
     /// Prefer using [`consume`](#method.consume) to allow the server to push messages to you on
     /// demand instead of polling with `get`.
@@ -153,23 +344,133 @@ impl<'a> Queue<'a> {
         self.channel.basic_get(self.name.clone(), no_ack)
     }
 
+    /// Synchronously get a single message from the queue, receiving its body in chunks as it
+    /// arrives. See [`Channel::basic_get_streaming`](struct.Channel.html#method.basic_get_streaming)
+    /// for details.
+    #[inline]
+    pub fn get_streaming(&self, no_ack: bool) -> Result<Option<StreamingGet>> {
+        self.channel.basic_get_streaming(self.name.clone(), no_ack)
+    }
+
+    /// Repeatedly [`get`](#method.get) a single message from the queue until one is available,
+    /// sleeping between attempts according to `backoff` while the queue is empty.
+    ///
+    /// Returns `Ok(Some(message))` once a message is received, or `Ok(None)` if polling was
+    /// cancelled. Sending a message on `cancel` (or dropping all of its senders) cancels polling;
+    /// the cancellation is noticed immediately while sleeping, or after the get that is in
+    /// progress completes. Pass
+    /// [`crossbeam_channel::never()`](https://docs.rs/crossbeam-channel/0.3/crossbeam_channel/fn.never.html)
+    /// to poll without a cancellation signal.
+    ///
+    /// As with `get`, prefer [`consume`](#method.consume) where possible.
+    pub fn get_with_backoff<T>(
+        &self,
+        no_ack: bool,
+        backoff: GetBackoff,
+        cancel: &Receiver<T>,
+    ) -> Result<Option<Get>> {
+        let mut delay = backoff.initial;
+        loop {
+            if let Some(get) = self.get(no_ack)? {
+                return Ok(Some(get));
+            }
+            match cancel.recv_timeout(delay) {
+                Err(RecvTimeoutError::Timeout) => (),
+                Ok(_) | Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+            delay = backoff.next_delay(delay);
+        }
+    }
+
     /// Synchronously start a consumer on this queue.
     #[inline]
     pub fn consume(&self, options: ConsumerOptions) -> Result<Consumer<'a>> {
         self.channel.basic_consume(self.name.clone(), options)
     }
 
+    /// Synchronously start an exclusive consumer on this queue, waiting for exclusive access if
+    /// another consumer already has it.
+    ///
+    /// This supports active/standby workers: every instance calls this method, one becomes the
+    /// active consumer, and the rest keep retrying (sleeping between attempts according to
+    /// `retry_strategy`) until the active consumer is cancelled or its connection goes away.
+    /// `options.exclusive` is set to true regardless of its value.
+    ///
+    /// Each refused attempt causes the server to close the channel, so this queue must have been
+    /// declared on a channel opened with
+    /// [`Connection::open_auto_reopen_channel`](struct.Connection.html#method.open_auto_reopen_channel);
+    /// on any other channel, the first
+    /// [`Error::ConsumerExclusiveConflict`](enum.Error.html#variant.ConsumerExclusiveConflict) is
+    /// returned. Any other error is returned immediately. Otherwise, this method retries forever.
+    pub fn consume_exclusive_with_failover(
+        &self,
+        options: ConsumerOptions,
+        retry_strategy: &BackoffStrategy,
+    ) -> Result<Consumer<'a>> {
+        let options = ConsumerOptions {
+            exclusive: true,
+            ..options
+        };
+        let mut attempt = 0;
+        loop {
+            match self.consume(options.clone()) {
+                Err(Error::ConsumerExclusiveConflict { .. }) if self.channel.is_auto_reopen() => {
+                    attempt += 1;
+                    let delay = retry_strategy.delay(attempt);
+                    debug!(
+                        "queue {} has another exclusive consumer; retrying in {:?}",
+                        self.name, delay
+                    );
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Synchronously start a consumer on this [stream](https://www.rabbitmq.com/streams.html)
+    /// queue, reading from `offset`. At most `credit` unacknowledged messages will be delivered
+    /// at a time; the server requires this to be nonzero.
+    ///
+    /// This sets the per-consumer prefetch count on this queue's channel to `credit` (via
+    /// [`Channel::qos`](struct.Channel.html#method.qos)), which also applies to any consumers
+    /// started on the channel afterwards.
+    pub fn stream_consume(&self, offset: StreamOffset, credit: u16) -> Result<StreamConsumer<'a>> {
+        StreamConsumer::new(self.channel, self, offset, credit)
+    }
+
+    /// Asynchronously start a consumer on this queue. `options.consumer_tag` must be set; see
+    /// [`Channel::basic_consume_nowait`](struct.Channel.html#method.basic_consume_nowait).
+    #[inline]
+    pub fn consume_nowait(&self, options: ConsumerOptions) -> Result<Consumer<'a>> {
+        self.channel
+            .basic_consume_nowait(self.name.clone(), options)
+    }
+
     /// Synchronously bind this queue to an exchange with the given routing key. `arguments` are
     /// typically optional, and are plugin / server dependent.
-    #[inline]
+    ///
+    /// On success, returns a [`QueueBinding`](struct.QueueBinding.html) that can be used to
+    /// remove exactly this binding later.
     pub fn bind<S: Into<String>>(
         &self,
         exchange: &Exchange,
         routing_key: S,
         arguments: FieldTable,
-    ) -> Result<()> {
-        self.channel
-            .queue_bind(self.name(), exchange.name(), routing_key, arguments)
+    ) -> Result<QueueBinding> {
+        let binding = QueueBinding {
+            queue: self.name.clone(),
+            exchange: exchange.name().to_string(),
+            routing_key: routing_key.into(),
+            arguments,
+        };
+        self.channel.queue_bind(
+            binding.queue.clone(),
+            binding.exchange.clone(),
+            binding.routing_key.clone(),
+            binding.arguments.clone(),
+        )?;
+        Ok(binding)
     }
 
     /// Asynchronously bind this queue to an exchange with the given routing key. `arguments` are
@@ -185,8 +486,84 @@ impl<'a> Queue<'a> {
             .queue_bind_nowait(self.name(), exchange.name(), routing_key, arguments)
     }
 
+    /// Synchronously bind this queue to a headers exchange. The `x-match` argument and header
+    /// values from `headers_match` are added to `arguments` (replacing any entries with the same
+    /// keys); the routing key is ignored by headers exchanges and is sent as an empty string.
+    ///
+    /// On success, returns a [`QueueBinding`](struct.QueueBinding.html) holding the combined
+    /// arguments, which an unbind must repeat exactly to remove the binding.
+    pub fn bind_headers(
+        &self,
+        exchange: &Exchange,
+        headers_match: HeadersMatch,
+        mut arguments: FieldTable,
+    ) -> Result<QueueBinding> {
+        arguments.extend(headers_match.into_arguments());
+        self.bind(exchange, "", arguments)
+    }
+
+    /// Synchronously bind this queue to an exchange with each of the given routing keys.
+    /// `arguments` are typically optional, and are plugin / server dependent; the same arguments
+    /// are used for every binding.
+    ///
+    /// The binds are issued one at a time, waiting for each `queue.bind-ok` before sending the
+    /// next. If any bind fails, the error is returned immediately and the remaining routing keys
+    /// are not bound. If you do not need to wait for confirmation of each bind, use
+    /// [`bind_all_nowait`](#method.bind_all_nowait), which sends all the binds without waiting
+    /// for replies.
+    pub fn bind_all<I, S>(
+        &self,
+        exchange: &Exchange,
+        routing_keys: I,
+        arguments: FieldTable,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for routing_key in routing_keys {
+            self.channel.queue_bind(
+                self.name(),
+                exchange.name(),
+                routing_key,
+                arguments.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Asynchronously bind this queue to an exchange with each of the given routing keys.
+    /// `arguments` are typically optional, and are plugin / server dependent; the same arguments
+    /// are used for every binding.
+    pub fn bind_all_nowait<I, S>(
+        &self,
+        exchange: &Exchange,
+        routing_keys: I,
+        arguments: FieldTable,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for routing_key in routing_keys {
+            self.channel.queue_bind_nowait(
+                self.name(),
+                exchange.name(),
+                routing_key,
+                arguments.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
     /// Synchronously unbind this queue from an exchange with the given routing key. `arguments`
     /// are typically optional, and are plugin / server dependent.
+    ///
+    /// The routing key and arguments must exactly match those of the binding being removed
+    /// (including the `x-match` and header arguments of a headers exchange binding); otherwise
+    /// the server does nothing and still reports success. Prefer
+    /// [`QueueBinding::unbind`](struct.QueueBinding.html#method.unbind) with the binding returned
+    /// by [`bind`](#method.bind) to avoid this.
     #[inline]
     pub fn unbind<S: Into<String>>(
         &self,
@@ -224,3 +601,173 @@ impl<'a> Queue<'a> {
         self.channel.queue_delete_nowait(self.name(), options)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum_arguments() {
+        let options = QueueDeclareOptions::default()
+            .queue_type(QueueType::Quorum)
+            .quorum_initial_group_size(3)
+            .delivery_limit(5);
+        assert!(options.durable);
+        let mut expected = FieldTable::new();
+        expected.insert(
+            "x-queue-type".to_string(),
+            AmqpValue::LongString("quorum".to_string()),
+        );
+        expected.insert(
+            "x-quorum-initial-group-size".to_string(),
+            AmqpValue::LongLongInt(3),
+        );
+        expected.insert("x-delivery-limit".to_string(), AmqpValue::LongLongInt(5));
+        assert_eq!(options.arguments, expected);
+        options.check_compatible().unwrap();
+    }
+
+    #[test]
+    fn incompatible_options() {
+        let check = |options: QueueDeclareOptions| match options.check_compatible() {
+            Err(Error::IncompatibleQueueOptions { queue_type, option }) => {
+                format!("{} {}", queue_type, option)
+            }
+            other => panic!("unexpected result {:?}", other),
+        };
+
+        let quorum = QueueDeclareOptions::default().queue_type(QueueType::Quorum);
+        let exclusive = QueueDeclareOptions {
+            exclusive: true,
+            ..quorum.clone()
+        };
+        assert_eq!(check(exclusive), "quorum exclusive");
+        let auto_delete = QueueDeclareOptions {
+            auto_delete: true,
+            ..QueueDeclareOptions::stream()
+        };
+        assert_eq!(check(auto_delete), "stream auto-delete");
+        let transient = QueueDeclareOptions {
+            durable: false,
+            ..quorum
+        };
+        assert_eq!(check(transient), "quorum non-durable");
+
+        let classic = QueueDeclareOptions {
+            exclusive: true,
+            auto_delete: true,
+            ..QueueDeclareOptions::default().queue_type(QueueType::Classic)
+        };
+        assert!(!classic.durable);
+        classic.check_compatible().unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "mock-broker"))]
+mod mock_broker_tests {
+    use super::*;
+    use crate::mock_broker::with_mock_chan;
+    use crate::{
+        ConsumerMessage, ExchangeDeclareOptions, ExchangeType, FieldTableBuilder, MockBroker,
+        Publish,
+    };
+
+    #[test]
+    fn unbind_queue_binding() {
+        with_mock_chan(|channel| {
+            let exchange = channel
+                .exchange_declare(
+                    ExchangeType::Direct,
+                    "bindings",
+                    ExchangeDeclareOptions::default(),
+                )
+                .unwrap();
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default())
+                .unwrap();
+            let arguments = FieldTableBuilder::new().string("x-tag", "a").build();
+            let binding = queue.bind(&exchange, "key", arguments.clone()).unwrap();
+            assert_eq!(binding.queue(), queue.name());
+            assert_eq!(binding.exchange(), "bindings");
+            assert_eq!(binding.routing_key(), "key");
+            assert_eq!(binding.arguments(), &arguments);
+
+            exchange.publish(Publish::new(b"bound", "key")).unwrap();
+            assert_eq!(queue.get(true).unwrap().unwrap().delivery.body, b"bound");
+
+            binding.unbind(&channel).unwrap();
+            exchange.publish(Publish::new(b"unbound", "key")).unwrap();
+            assert!(queue.get(true).unwrap().is_none());
+        })
+    }
+
+    #[test]
+    fn consume_exclusive_with_failover() {
+        let broker = MockBroker::new();
+        let mut connection = broker.connect().unwrap();
+        let channel = connection.open_channel(None).unwrap();
+        let queue = channel
+            .queue_declare("failover", QueueDeclareOptions::default())
+            .unwrap();
+        let active = queue
+            .consume_exclusive_with_failover(
+                ConsumerOptions::default(),
+                &BackoffStrategy::Fixed(Duration::from_millis(10)),
+            )
+            .unwrap();
+
+        // without an auto-reopen channel, the conflict is returned instead of retried
+        let mut plain_connection = broker.connect().unwrap();
+        let plain_channel = plain_connection.open_channel(None).unwrap();
+        match plain_channel
+            .queue_declare_passive("failover")
+            .unwrap()
+            .consume_exclusive_with_failover(
+                ConsumerOptions::default(),
+                &BackoffStrategy::Fixed(Duration::from_millis(10)),
+            ) {
+            Err(Error::ConsumerExclusiveConflict { queue }) => assert_eq!(queue, "failover"),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+        plain_connection.close().unwrap();
+
+        // the retry strategy is consulted once per refused attempt; report each one
+        let (refused_tx, refused_rx) = crossbeam_channel::unbounded();
+        let retry_strategy = BackoffStrategy::Custom(Box::new(move |attempt| {
+            let _ = refused_tx.send(attempt);
+            Duration::from_millis(10)
+        }));
+        let standby_broker = broker.clone();
+        let standby = std::thread::spawn(move || {
+            let mut connection = standby_broker.connect().unwrap();
+            let channel = connection.open_auto_reopen_channel().unwrap();
+            let queue = channel.queue_declare_passive("failover").unwrap();
+            let consumer = queue
+                .consume_exclusive_with_failover(
+                    ConsumerOptions {
+                        no_ack: true,
+                        ..ConsumerOptions::default()
+                    },
+                    &retry_strategy,
+                )
+                .unwrap();
+            let body = match consumer.receiver().recv_timeout(Duration::from_secs(5)) {
+                Ok(ConsumerMessage::Delivery(delivery)) => delivery.body,
+                other => panic!("unexpected consumer message {:?}", other),
+            };
+            connection.close().unwrap();
+            body
+        });
+
+        // only fail over once the standby has been refused
+        assert_eq!(refused_rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        active.cancel().unwrap();
+        channel
+            .basic_publish("", Publish::new(b"standby", "failover"))
+            .unwrap();
+        assert_eq!(standby.join().unwrap(), b"standby".to_vec());
+        assert!(refused_rx.try_iter().all(|attempt| attempt > 1));
+
+        connection.close().unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use crate::errors::*;
+use std::fmt;
+
+// Routing keys are sent as AMQP short strings.
+const MAX_LEN: usize = 255;
+
+/// A dot-separated routing key for [topic exchanges](enum.ExchangeType.html#variant.Topic),
+/// built one segment at a time.
+///
+/// Each segment is checked as it is added; a segment that is empty or contains `.`, `*`, or `#`
+/// is rejected with
+/// [`Error::InvalidRoutingKeySegment`](enum.Error.html#variant.InvalidRoutingKeySegment), so a
+/// value that accidentally contains a dot cannot silently change the shape of the key. A
+/// `RoutingKey` can be used anywhere a routing key string is accepted:
+///
+/// ```rust
+/// use amiquip::{Publish, Result, RoutingKey};
+///
+/// # fn main() -> Result<()> {
+/// let region = "eu";
+/// let key = RoutingKey::from_segments(&["orders", region, "created"])?;
+/// assert_eq!(key.as_str(), "orders.eu.created");
+///
+/// let publish = Publish::new(b"{}", key);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RoutingKey(String);
+
+impl RoutingKey {
+    /// Create a routing key consisting of the single segment `segment`.
+    pub fn new<S: AsRef<str>>(segment: S) -> Result<RoutingKey> {
+        let segment = segment.as_ref();
+        check_segment(segment)?;
+        RoutingKey(String::new()).checked_append(segment)
+    }
+
+    /// Create a routing key by joining `segments` with dots. `segments` must not be empty.
+    pub fn from_segments<I, S>(segments: I) -> Result<RoutingKey>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut segments = segments.into_iter();
+        let first = match segments.next() {
+            Some(first) => first,
+            None => {
+                return InvalidRoutingKeySegment {
+                    segment: String::new(),
+                }
+                .fail()
+            }
+        };
+        segments.try_fold(RoutingKey::new(first)?, RoutingKey::push)
+    }
+
+    /// Append `segment` to this routing key, separated by a dot.
+    pub fn push<S: AsRef<str>>(self, segment: S) -> Result<RoutingKey> {
+        let segment = segment.as_ref();
+        check_segment(segment)?;
+        let mut key = self.0;
+        key.push('.');
+        RoutingKey(key).checked_append(segment)
+    }
+
+    /// The routing key as a string.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn checked_append(mut self, segment: &str) -> Result<RoutingKey> {
+        self.0.push_str(segment);
+        if self.0.len() > MAX_LEN {
+            return RoutingKeyTooLong { len: self.0.len() }.fail();
+        }
+        Ok(self)
+    }
+}
+
+fn check_segment(segment: &str) -> Result<()> {
+    if segment.is_empty() || segment.contains(|c| c == '.' || c == '*' || c == '#') {
+        return InvalidRoutingKeySegment {
+            segment: segment.to_string(),
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+impl AsRef<str> for RoutingKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<RoutingKey> for String {
+    fn from(key: RoutingKey) -> String {
+        key.0
+    }
+}
+
+impl fmt::Display for RoutingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_segments() {
+        let key = RoutingKey::new("orders")
+            .and_then(|key| key.push("eu"))
+            .and_then(|key| key.push(String::from("created")))
+            .unwrap();
+        assert_eq!(key.as_str(), "orders.eu.created");
+        assert_eq!(
+            RoutingKey::from_segments(&["orders", "eu", "created"]).unwrap(),
+            key
+        );
+        assert_eq!(String::from(key), "orders.eu.created");
+    }
+
+    #[test]
+    fn rejects_invalid_segments() {
+        for segment in &["", "eu.west", "*", "orders#"] {
+            match RoutingKey::from_segments(&["orders", *segment]) {
+                Err(Error::InvalidRoutingKeySegment { segment: ref s }) => assert_eq!(s, *segment),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        let empty: &[&str] = &[];
+        assert!(RoutingKey::from_segments(empty).is_err());
+    }
+
+    #[test]
+    fn rejects_long_keys() {
+        let segment = "x".repeat(100);
+        match RoutingKey::from_segments(&[&segment, &segment, &segment]) {
+            Err(Error::RoutingKeyTooLong { len }) => assert_eq!(len, 302),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
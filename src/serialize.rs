@@ -12,6 +12,7 @@ use amq_protocol::protocol::exchange::AMQPMethod as AmqpExchange;
 use amq_protocol::protocol::queue::AMQPMethod as AmqpQueue;
 use amq_protocol::protocol::AMQPClass;
 use cookie_factory::GenError;
+use std::mem;
 use std::ops::{Index, RangeFrom};
 use std::result::Result as StdResult;
 
@@ -171,6 +172,12 @@ pub trait IntoAmqpClass {
     fn into_class(self) -> AMQPClass;
 }
 
+impl IntoAmqpClass for AMQPClass {
+    fn into_class(self) -> AMQPClass {
+        self
+    }
+}
+
 impl IntoAmqpClass for AmqpConnection {
     fn into_class(self) -> AMQPClass {
         AMQPClass::Connection(self)
@@ -207,25 +214,75 @@ impl IntoAmqpClass for AmqpExchange {
     }
 }
 
+// Counts of what has been serialized into an OutputBuffer, used by the I/O loop to
+// maintain connection metrics without having to parse outgoing data.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct OutputStats {
+    pub(crate) frames: u64,
+    pub(crate) publishes: u64,
+    // Content header and body frames; only publishes send content.
+    pub(crate) content_frames: u64,
+    pub(crate) acks: u64,
+    pub(crate) nacks: u64,
+}
+
+impl OutputStats {
+    fn add(&mut self, other: OutputStats) {
+        self.frames += other.frames;
+        self.publishes += other.publishes;
+        self.content_frames += other.content_frames;
+        self.acks += other.acks;
+        self.nacks += other.nacks;
+    }
+}
+
+// Each frame has 8 bytes of overhead (7 byte header, 1 byte frame-end).
+pub(crate) const FRAME_OVERHEAD: usize = 8;
+
 #[derive(Debug)]
-pub(crate) struct OutputBuffer(Vec<u8>);
+pub(crate) struct OutputBuffer(Vec<u8>, OutputStats);
 
 impl OutputBuffer {
     pub fn with_protocol_header() -> OutputBuffer {
-        OutputBuffer(Vec::from("AMQP\x00\x00\x09\x01".as_bytes()))
+        OutputBuffer(
+            Vec::from("AMQP\x00\x00\x09\x01".as_bytes()),
+            OutputStats::default(),
+        )
     }
 
     pub(crate) fn empty() -> OutputBuffer {
-        OutputBuffer(Vec::new())
+        OutputBuffer(Vec::new(), OutputStats::default())
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> OutputBuffer {
+        OutputBuffer(Vec::with_capacity(capacity), OutputStats::default())
+    }
+
+    // Number of bytes push_content_body needs to frame `len` bytes of content in frames
+    // carrying at most `frame_max` bytes of content each.
+    pub(crate) fn content_body_len(len: usize, frame_max: usize) -> usize {
+        let frames = if len == 0 {
+            0
+        } else {
+            (len - 1) / frame_max + 1
+        };
+        len + frames * FRAME_OVERHEAD
     }
 
     pub(crate) fn drain_into_new_buf(&mut self) -> OutputBuffer {
-        let mut buf = OutputBuffer(Vec::with_capacity(self.len()));
+        let mut buf = OutputBuffer(Vec::with_capacity(self.len()), OutputStats::default());
         buf.0.append(&mut self.0);
+        buf.1 = mem::replace(&mut self.1, OutputStats::default());
         buf
     }
 
+    #[inline]
+    pub(crate) fn stats(&self) -> OutputStats {
+        self.1
+    }
+
     pub fn push_heartbeat(&mut self) {
+        self.1.frames += 1;
         // serializing heartbeat cannot fail; safe to unwrap.
         serialize(&mut self.0, |buf, pos| gen_heartbeat_frame((buf, pos)))
     }
@@ -237,6 +294,15 @@ impl OutputBuffer {
         M: IntoAmqpClass,
     {
         let class = method.into_class();
+        self.1.frames += 1;
+        match &class {
+            AMQPClass::Basic(AmqpBasic::Publish(_)) => self.1.publishes += 1,
+            AMQPClass::Basic(AmqpBasic::Ack(_)) => self.1.acks += 1,
+            AMQPClass::Basic(AmqpBasic::Nack(_)) | AMQPClass::Basic(AmqpBasic::Reject(_)) => {
+                self.1.nacks += 1
+            }
+            _ => (),
+        }
         serialize(&mut self.0, |buf, pos| {
             gen_method_frame((buf, pos), channel_id, &class)
         })
@@ -250,12 +316,16 @@ impl OutputBuffer {
         properties: &AMQPProperties,
     ) {
         let length = length as u64;
+        self.1.frames += 1;
+        self.1.content_frames += 1;
         serialize(&mut self.0, |buf, pos| {
             gen_content_header_frame((buf, pos), channel_id, class_id, length, properties)
         })
     }
 
     pub(crate) fn push_content_body(&mut self, channel_id: u16, content: &[u8]) {
+        self.1.frames += 1;
+        self.1.content_frames += 1;
         serialize(&mut self.0, |buf, pos| {
             gen_content_body_frame((buf, pos), channel_id, content)
         })
@@ -273,7 +343,8 @@ impl OutputBuffer {
 
     #[inline]
     pub fn clear(&mut self) {
-        self.0.clear()
+        self.0.clear();
+        self.1 = OutputStats::default();
     }
 
     #[inline]
@@ -283,7 +354,8 @@ impl OutputBuffer {
 
     #[inline]
     pub fn append(&mut self, mut other: OutputBuffer) {
-        self.0.append(&mut other.0)
+        self.0.append(&mut other.0);
+        self.1.add(other.1);
     }
 }
 
@@ -384,3 +456,36 @@ fn serialize<F: Fn(&mut [u8], usize) -> StdResult<(&mut [u8], usize), GenError>>
         buf.resize(resize_to, 0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_body_len() {
+        let content = vec![0; 1000];
+        for &frame_max in &[
+            1,
+            7,
+            100,
+            999,
+            1000,
+            1001,
+            usize::max_value() - FRAME_OVERHEAD,
+        ] {
+            for &len in &[0, 1, 100, 1000] {
+                let mut buf = OutputBuffer::empty();
+                for chunk in content[..len].chunks(frame_max) {
+                    buf.push_content_body(1, chunk);
+                }
+                assert_eq!(
+                    buf.len(),
+                    OutputBuffer::content_body_len(len, frame_max),
+                    "len = {}, frame_max = {}",
+                    len,
+                    frame_max
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Spans around AMQP operations, reported via the `tracing` crate when the `tracing` feature is
+// enabled. Without the feature every function here returns a zero-sized guard and compiles away,
+// so call sites don't need their own cfgs. Hold the returned guard for the duration of the
+// operation.
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use std::fmt::Debug;
+    use tracing::debug_span;
+    use tracing::field::{display, Empty};
+
+    pub(crate) type Entered = tracing::span::EnteredSpan;
+
+    // AMQP methods' Debug impls look like `Declare(Declare { .. })`; use the part before the
+    // first parenthesis as the method name.
+    fn method_name<M: Debug>(method: &M) -> String {
+        let mut name = format!("{:?}", method);
+        if let Some(pos) = name.find('(') {
+            name.truncate(pos);
+        }
+        name
+    }
+
+    pub(crate) fn rpc<M: Debug>(channel_id: u16, method: &M) -> Entered {
+        let span = debug_span!("amqp.rpc", channel_id, method = Empty);
+        // Formatting the method is relatively expensive; skip it unless someone is listening.
+        if !span.is_disabled() {
+            span.record("method", &display(method_name(method)));
+        }
+        span.entered()
+    }
+
+    pub(crate) fn publish(
+        channel_id: u16,
+        exchange: &str,
+        routing_key: &str,
+        delivery_tag: Option<u64>,
+    ) -> Entered {
+        let span = debug_span!(
+            "amqp.publish",
+            channel_id,
+            exchange,
+            routing_key,
+            delivery_tag = Empty
+        );
+        if let Some(delivery_tag) = delivery_tag {
+            span.record("delivery_tag", &delivery_tag);
+        }
+        span.entered()
+    }
+
+    pub(crate) fn delivery(channel_id: u16, consumer_tag: &str, delivery_tag: u64) -> Entered {
+        debug_span!("amqp.delivery", channel_id, consumer_tag, delivery_tag).entered()
+    }
+
+    pub(crate) fn ack(channel_id: u16, method: &'static str, delivery_tag: u64) -> Entered {
+        debug_span!("amqp.ack", channel_id, method, delivery_tag).entered()
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    pub(crate) struct Entered;
+
+    #[inline]
+    pub(crate) fn rpc<M>(_channel_id: u16, _method: &M) -> Entered {
+        Entered
+    }
+
+    #[inline]
+    pub(crate) fn publish(
+        _channel_id: u16,
+        _exchange: &str,
+        _routing_key: &str,
+        _delivery_tag: Option<u64>,
+    ) -> Entered {
+        Entered
+    }
+
+    #[inline]
+    pub(crate) fn delivery(_channel_id: u16, _consumer_tag: &str, _delivery_tag: u64) -> Entered {
+        Entered
+    }
+
+    #[inline]
+    pub(crate) fn ack(_channel_id: u16, _method: &'static str, _delivery_tag: u64) -> Entered {
+        Entered
+    }
+}
+
+pub(crate) use self::imp::*;
@@ -1,7 +1,8 @@
 use crate::Result;
 use mio::net::TcpStream;
 use mio::Evented;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::time::Duration;
 
 pub(crate) trait HandshakeStream: Evented + Send + 'static {
     type Stream: IoStream;
@@ -10,9 +11,29 @@ pub(crate) trait HandshakeStream: Evented + Send + 'static {
 }
 
 /// Combination trait for readable, writable streams that can be polled by mio.
-pub trait IoStream: Read + Write + Evented + Send + 'static {}
+///
+/// Implement this for a custom transport to pass it to
+/// [`Connection::insecure_open_stream`](struct.Connection.html#method.insecure_open_stream) or
+/// [`Connection::open_tls_stream`](struct.Connection.html#method.open_tls_stream). The stream
+/// must be nonblocking: reads and writes that cannot make progress should fail with
+/// `io::ErrorKind::WouldBlock`, and the stream must signal readiness through its `Evented`
+/// registration when they can.
+pub trait IoStream: Read + Write + Evented + Send + 'static {
+    /// Apply the TCP options from
+    /// [`ConnectionOptions`](struct.ConnectionOptions.html#method.tcp_nodelay) before the
+    /// connection starts. The default implementation does nothing, which is appropriate for
+    /// streams that are not TCP sockets.
+    fn set_tcp_options(&self, _nodelay: bool, _keepalive: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-impl IoStream for TcpStream {}
+impl IoStream for TcpStream {
+    fn set_tcp_options(&self, nodelay: bool, keepalive: Option<Duration>) -> io::Result<()> {
+        self.set_nodelay(nodelay)?;
+        self.set_keepalive(keepalive)
+    }
+}
 
 #[cfg(feature = "native-tls")]
 mod native_tls;
@@ -6,6 +6,11 @@ use snafu::ResultExt;
 use std::io::{self, Read, Write};
 
 /// Newtype wrapper around a `native_tls::TlsConnector` to make it usable by amiquip's I/O loop.
+///
+/// Cloning a `TlsConnector` reuses its configuration, so a connector built once (e.g., with
+/// client certificates, additional root certificates, or a minimum protocol version) can be
+/// shared by many connections.
+#[derive(Clone)]
 pub struct TlsConnector(native_tls::TlsConnector);
 
 impl TlsConnector {
@@ -0,0 +1,50 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// AMQP timestamps (the `timestamp` property and timestamp header values) are whole seconds since
+// the Unix epoch. Times before the epoch are clamped to it, and sub-second precision is dropped.
+pub(crate) fn to_amqp_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+// Returns `None` if `seconds` is too far in the future to be represented as a `SystemTime`.
+pub(crate) fn from_amqp_timestamp(seconds: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+// Whole milliseconds in `duration`, as used by TTLs (the `expiration` property and
+// `x-message-ttl`) and delayed-message `x-delay` headers. Sub-millisecond precision is dropped.
+pub(crate) fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds_since_epoch() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_500_000_000_750);
+        assert_eq!(to_amqp_timestamp(time), 1_500_000_000);
+        assert_eq!(
+            from_amqp_timestamp(1_500_000_000),
+            Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000))
+        );
+        assert_eq!(to_amqp_timestamp(UNIX_EPOCH - Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn whole_milliseconds() {
+        assert_eq!(
+            duration_millis(Duration::from_micros(90_061_500_900)),
+            90_061_500
+        );
+        assert_eq!(duration_millis(Duration::from_micros(999)), 0);
+    }
+
+    #[test]
+    fn unrepresentable_timestamp() {
+        assert_eq!(from_amqp_timestamp(u64::max_value()), None);
+    }
+}